@@ -1,8 +1,676 @@
+use std::env;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::guid::Guid;
 
 /// The address of the system message bus is given in the DBUS_SYSTEM_BUS_ADDRESS environment variable.
 /// If that variable is not set, applications should try to connect to the well-known address unix:path=/var/run/dbus/system_bus_socket
-const WELL_KNOWN_DBUS_SYSTEM_BUS_ENV: &str = "DBUS_SYSTEM_BUS_ADDRESS";
+pub const WELL_KNOWN_DBUS_SYSTEM_BUS_ENV: &str = "DBUS_SYSTEM_BUS_ADDRESS";
 
 /// The address of the system message bus is given in the DBUS_SYSTEM_BUS_ADDRESS environment variable.
 /// If that variable is not set, applications should try to connect to the well-known address unix:path=/var/run/dbus/system_bus_socket
-const WELL_KNOWN_DBUS_SYSTEM_BUS_ADDRESS: &str = "unix:path=/var/run/dbus/system_bus_socket";
\ No newline at end of file
+pub const WELL_KNOWN_DBUS_SYSTEM_BUS_ADDRESS: &str = "unix:path=/var/run/dbus/system_bus_socket";
+
+/// The address of the login session message bus is given in the DBUS_SESSION_BUS_ADDRESS environment variable.
+/// If that variable is not set, applications may try the socket `bus` in XDG_RUNTIME_DIR.
+pub const WELL_KNOWN_DBUS_SESSION_BUS_ENV: &str = "DBUS_SESSION_BUS_ADDRESS";
+
+/// The directory of user-specific runtime files, which may contain the session bus socket `bus`.
+pub const XDG_RUNTIME_DIR_ENV: &str = "XDG_RUNTIME_DIR";
+
+/// The address of the message bus that started a service is given in DBUS_STARTER_ADDRESS.
+pub const DBUS_STARTER_ADDRESS_ENV: &str = "DBUS_STARTER_ADDRESS";
+
+/// The type of the message bus that started a service, `system` or `session`.
+pub const DBUS_STARTER_BUS_TYPE_ENV: &str = "DBUS_STARTER_BUS_TYPE";
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn unix() {
+        let address = Address::from_str("unix:path=/var/run/dbus/system_bus_socket").unwrap();
+        assert_eq!(Transport::Unix(UnixAddress::Path("/var/run/dbus/system_bus_socket".to_string())), address.transport);
+        assert_eq!(None, address.guid);
+
+        let address = Address::from_str("unix:abstract=/tmp/dbus-Xn%20j,guid=0123456789abcdef0123456789ABCDEF").unwrap();
+        assert_eq!(Transport::Unix(UnixAddress::Abstract("/tmp/dbus-Xn j".to_string())), address.transport);
+        let guid = Guid::from_str("0123456789abcdef0123456789abcdef").unwrap();
+        assert_eq!(Some(guid), address.guid);
+        assert!(address.matches_guid(&guid));
+        assert!(!address.matches_guid(&Guid::from_bytes([0; 16])));
+        assert!(Address::from_str("unix:runtime=yes").unwrap().matches_guid(&guid));
+
+        assert_eq!(Transport::Unix(UnixAddress::Runtime), Address::from_str("unix:runtime=yes").unwrap().transport);
+        assert_eq!(Transport::Unix(UnixAddress::Tmpdir("/tmp".to_string())), Address::from_str("unix:tmpdir=/tmp").unwrap().transport);
+        assert_eq!(Err(AddressError::ConflictingKeys), Address::from_str("unix:path=/a,abstract=b"));
+        assert_eq!(Err(AddressError::MissingKey("path".to_string())), Address::from_str("unix:"));
+        assert_eq!(Err(AddressError::InvalidValue("runtime".to_string(), "no".to_string())), Address::from_str("unix:runtime=no"));
+    }
+
+    #[test]
+    fn tcp() {
+        let address = Address::from_str("tcp:host=localhost,port=4711,family=ipv6").unwrap();
+        let expected = TcpAddress {
+            host: Some("localhost".to_string()),
+            bind: None,
+            port: Some(4711),
+            family: Some(Family::Ipv6),
+        };
+        assert_eq!(Transport::Tcp(expected.clone()), address.transport);
+
+        let address = Address::from_str("nonce-tcp:host=localhost,port=4711,family=ipv6,noncefile=/tmp/nonce").unwrap();
+        assert_eq!(Transport::NonceTcp(NonceTcpAddress { tcp: expected, noncefile: Some("/tmp/nonce".to_string()) }), address.transport);
+
+        assert_eq!(Err(AddressError::InvalidValue("port".to_string(), "70000".to_string())), Address::from_str("tcp:port=70000"));
+        assert_eq!(Err(AddressError::UnknownKey("noncefile".to_string())), Address::from_str("tcp:noncefile=/tmp/nonce"));
+    }
+
+    #[test]
+    fn unixexec() {
+        let address = Address::from_str("unixexec:path=/usr/bin/ssh,argv1=-xT,argv2=host,argv3=systemd-stdio-bridge").unwrap();
+        let expected = UnixExecAddress {
+            path: "/usr/bin/ssh".to_string(),
+            argv0: None,
+            args: vec!["-xT".to_string(), "host".to_string(), "systemd-stdio-bridge".to_string()],
+        };
+        assert_eq!(Transport::UnixExec(expected), address.transport);
+        assert_eq!(Err(AddressError::MissingKey("argv1".to_string())), Address::from_str("unixexec:path=/bin/true,argv2=x"));
+    }
+
+    #[test]
+    fn unknown_transport() {
+        let address = Address::from_str("launchd:env=DBUS_LAUNCHD_SESSION_BUS_SOCKET").unwrap();
+        let options = vec![("env".to_string(), "DBUS_LAUNCHD_SESSION_BUS_SOCKET".to_string())];
+        assert_eq!(Transport::Unknown("launchd".to_string(), options), address.transport);
+        assert_eq!("launchd:env=DBUS_LAUNCHD_SESSION_BUS_SOCKET", address.to_string());
+    }
+
+    #[test]
+    fn address_list() {
+        let addresses = parse_addresses("unix:path=/tmp/a;;tcp:host=127.0.0.1,port=0;").unwrap();
+        assert_eq!(2, addresses.len());
+        assert_eq!("unix:path=/tmp/a;tcp:host=127.0.0.1,port=0", format_addresses(&addresses));
+        assert_eq!(Err(AddressError::Empty), parse_addresses(";"));
+    }
+
+    #[test]
+    fn invalid_syntax() {
+        assert_eq!(Err(AddressError::MissingTransport), Address::from_str("path=/tmp/a"));
+        assert_eq!(Err(AddressError::MissingTransport), Address::from_str(":path=/tmp/a"));
+        assert_eq!(Err(AddressError::MissingValue("path".to_string())), Address::from_str("unix:path"));
+        assert_eq!(Err(AddressError::EmptyKey), Address::from_str("unix:=x"));
+        assert_eq!(Err(AddressError::DuplicateKey("path".to_string())), Address::from_str("unix:path=/a,path=/b"));
+        assert_eq!(Err(AddressError::UnescapedCharacter(' ')), Address::from_str("unix:path=/a b"));
+        assert_eq!(Err(AddressError::InvalidEscape), Address::from_str("unix:path=/a%2"));
+        assert_eq!(Err(AddressError::InvalidEscape), Address::from_str("unix:path=/a%zz"));
+        assert_eq!(Err(AddressError::InvalidUtf8), Address::from_str("unix:path=/a%ff"));
+        assert_eq!(Err(AddressError::InvalidGuid), Address::from_str("unix:path=/a,guid=1234"));
+    }
+
+    #[test]
+    fn escaping() {
+        let address = Address {
+            transport: Transport::Unix(UnixAddress::Path("/tmp/a b,c=d;%ä".to_string())),
+            guid: None,
+        };
+        assert_eq!("unix:path=/tmp/a%20b%2cc%3dd%3b%25%c3%a4", address.to_string());
+        assert_eq!(Ok(address.clone()), Address::from_str(&address.to_string()));
+    }
+
+    fn env<'a>(vars: &'a [(&'a str, &'a str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        move |name| vars.iter().find(|(k, _)| *k == name).map(|(_, v)| v.to_string())
+    }
+
+    #[test]
+    fn system_bus() {
+        let addresses = BusType::System.addresses_from(env(&[])).unwrap();
+        assert_eq!(vec![Address::from_str(WELL_KNOWN_DBUS_SYSTEM_BUS_ADDRESS).unwrap()], addresses);
+
+        let addresses = BusType::System.addresses_from(env(&[(WELL_KNOWN_DBUS_SYSTEM_BUS_ENV, "unix:path=/a;tcp:port=1")])).unwrap();
+        assert_eq!("unix:path=/a;tcp:port=1", format_addresses(&addresses));
+    }
+
+    #[test]
+    fn session_bus() {
+        let vars = [(WELL_KNOWN_DBUS_SESSION_BUS_ENV, "unix:abstract=/tmp/x"), (XDG_RUNTIME_DIR_ENV, "/run/user/1000")];
+        let addresses = BusType::Session.addresses_from(env(&vars)).unwrap();
+        assert_eq!("unix:abstract=/tmp/x", format_addresses(&addresses));
+
+        let dir = env::temp_dir().join(format!("dbus-native-runtime-{}", std::process::id()));
+        let runtime_dir = dir.to_str().unwrap();
+        assert_eq!(Err(AddressError::NoBusAddress(BusType::Session)), BusType::Session.addresses_from(env(&[(XDG_RUNTIME_DIR_ENV, runtime_dir)])));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("bus"), b"").unwrap();
+        let addresses = BusType::Session.addresses_from(env(&[(XDG_RUNTIME_DIR_ENV, runtime_dir)]));
+        std::fs::remove_dir_all(&dir).unwrap();
+        let path = dir.join("bus").to_str().unwrap().to_string();
+        assert_eq!(vec![Address { transport: Transport::Unix(UnixAddress::Path(path)), guid: None }], addresses.unwrap());
+
+        assert_eq!(Err(AddressError::NoBusAddress(BusType::Session)), BusType::Session.addresses_from(env(&[(WELL_KNOWN_DBUS_SESSION_BUS_ENV, "")])));
+    }
+
+    #[test]
+    fn starter_bus() {
+        let vars = [(DBUS_STARTER_ADDRESS_ENV, "unix:path=/starter"), (DBUS_STARTER_BUS_TYPE_ENV, "system")];
+        assert_eq!("unix:path=/starter", format_addresses(&BusType::Starter.addresses_from(env(&vars)).unwrap()));
+
+        let vars = [(DBUS_STARTER_BUS_TYPE_ENV, "system"), (WELL_KNOWN_DBUS_SYSTEM_BUS_ENV, "unix:path=/system")];
+        assert_eq!("unix:path=/system", format_addresses(&BusType::Starter.addresses_from(env(&vars)).unwrap()));
+
+        let vars = [(DBUS_STARTER_BUS_TYPE_ENV, "user")];
+        let err = AddressError::InvalidValue(DBUS_STARTER_BUS_TYPE_ENV.to_string(), "user".to_string());
+        assert_eq!(Err(err), BusType::Starter.addresses_from(env(&vars)));
+        assert_eq!(Err(AddressError::NoBusAddress(BusType::Starter)), BusType::Starter.addresses_from(env(&[])));
+    }
+
+    quickcheck! {
+        fn serializer_round_trip(path: String, host: String, port: Option<u16>, args: Vec<String>) -> bool {
+            let addresses = vec![
+                Address { transport: Transport::Unix(UnixAddress::Path(path.clone())), guid: Some(Guid::from_bytes([0xab; 16])) },
+                Address { transport: Transport::Tcp(TcpAddress { host: Some(host), bind: None, port, family: Some(Family::Ipv4) }), guid: None },
+                Address { transport: Transport::UnixExec(UnixExecAddress { path, argv0: None, args }), guid: None },
+            ];
+            parse_addresses(&format_addresses(&addresses)) == Ok(addresses)
+        }
+    }
+}
+
+/// A D-Bus server address, telling a client how to connect to a server or a server
+/// where to listen, e.g. `unix:path=/var/run/dbus/system_bus_socket`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Address {
+    pub transport: Transport,
+    /// The GUID of the server, which the client verifies during authentication.
+    pub guid: Option<Guid>,
+}
+
+impl Address {
+    /// Whether a server identified by `guid` may be connected to through this address,
+    /// which is the case unless the address names a different GUID.
+    #[inline]
+    pub fn matches_guid(&self, guid: &Guid) -> bool {
+        self.guid.as_ref().is_none_or(|expected| expected == guid)
+    }
+}
+
+/// The transport mechanism of an address along with its parameters.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Transport {
+    Unix(UnixAddress),
+    Tcp(TcpAddress),
+    NonceTcp(NonceTcpAddress),
+    UnixExec(UnixExecAddress),
+    /// A transport that is not supported, such as `launchd` or `autolaunch`,
+    /// with its unescaped key-value pairs in order.
+    Unknown(String, Vec<(String, String)>),
+}
+
+/// The location of a Unix domain socket. Exactly one key must be given.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UnixAddress {
+    /// Path of the socket in the file system.
+    Path(String),
+    /// Name of a socket in the Linux abstract namespace.
+    Abstract(String),
+    /// Directory in which a server creates a socket with a random file name. Listening only.
+    Dir(String),
+    /// Like `Dir`, but the server may use the abstract namespace instead. Listening only.
+    Tmpdir(String),
+    /// The `bus` socket in `$XDG_RUNTIME_DIR`, e.g. for the session bus.
+    Runtime,
+}
+
+/// Parameters of the `tcp` transport.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TcpAddress {
+    /// DNS name or IP address.
+    pub host: Option<String>,
+    /// The interface a server listens on, `*` for all.
+    pub bind: Option<String>,
+    /// The port, or 0 for a server to choose one.
+    pub port: Option<u16>,
+    pub family: Option<Family>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Family {
+    Ipv4,
+    Ipv6,
+}
+
+/// Parameters of the `nonce-tcp` transport, where the client sends the 16 bytes of a
+/// nonce file only readable by the user before authenticating.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NonceTcpAddress {
+    pub tcp: TcpAddress,
+    /// File containing the nonce.
+    pub noncefile: Option<String>,
+}
+
+impl From<TcpAddress> for NonceTcpAddress {
+    #[inline]
+    fn from(tcp: TcpAddress) -> NonceTcpAddress {
+        NonceTcpAddress { tcp, noncefile: None }
+    }
+}
+
+/// Parameters of the `unixexec` transport, which runs a program and talks to it over its stdin and stdout.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnixExecAddress {
+    /// Path of the program, looked up in `PATH` if it does not contain a '/'.
+    pub path: String,
+    /// The zeroth argument, defaulting to `path`.
+    pub argv0: Option<String>,
+    /// The arguments `argv1`, `argv2`, … in order.
+    pub args: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AddressError {
+    /// The address list contains no address.
+    Empty,
+
+    /// An address must begin with the name of its transport followed by a ':' (colon).
+    MissingTransport,
+
+    /// The key is not followed by a '=' and a value.
+    MissingValue(String),
+
+    /// Keys must not be empty.
+    EmptyKey,
+
+    /// Each key may only appear once per address.
+    DuplicateKey(String),
+
+    /// Only the characters "[-0-9A-Za-z_/.\*]" may appear unescaped in values.
+    UnescapedCharacter(char),
+
+    /// A '%' must be followed by two hexadecimal digits.
+    InvalidEscape,
+
+    /// Unescaped values must be valid UTF-8.
+    InvalidUtf8,
+
+    /// The key is not known for the transport.
+    UnknownKey(String),
+
+    /// A required key, or one of a set of keys, is missing.
+    MissingKey(String),
+
+    /// Only one of the keys `path`, `abstract`, `dir`, `tmpdir` and `runtime` may be given.
+    ConflictingKeys,
+
+    /// The value of the key is invalid.
+    InvalidValue(String, String),
+
+    /// The guid must consist of 32 hexadecimal digits.
+    InvalidGuid,
+
+    /// The environment does not specify an address for the bus.
+    NoBusAddress(BusType),
+}
+
+impl fmt::Display for AddressError {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AddressError::Empty => write!(f, "empty address"),
+            AddressError::MissingTransport => write!(f, "address must begin with a transport name followed by `:`"),
+            AddressError::MissingValue(key) => write!(f, "missing value for key `{}`", key),
+            AddressError::EmptyKey => write!(f, "empty key"),
+            AddressError::DuplicateKey(key) => write!(f, "duplicate key `{}`", key),
+            AddressError::UnescapedCharacter(c) => write!(f, "character `{}` must be escaped", c),
+            AddressError::InvalidEscape => write!(f, "`%` must be followed by two hexadecimal digits"),
+            AddressError::InvalidUtf8 => write!(f, "unescaped value is not valid UTF-8"),
+            AddressError::UnknownKey(key) => write!(f, "unknown key `{}`", key),
+            AddressError::MissingKey(key) => write!(f, "missing key `{}`", key),
+            AddressError::ConflictingKeys => write!(f, "only one of `path`, `abstract`, `dir`, `tmpdir` and `runtime` may be given"),
+            AddressError::InvalidValue(key, value) => write!(f, "invalid value `{}` for key `{}`", value, key),
+            AddressError::InvalidGuid => write!(f, "guid must consist of 32 hexadecimal digits"),
+            AddressError::NoBusAddress(bus_type) => write!(f, "no address found for the {} bus", bus_type),
+        }
+    }
+}
+
+impl std::error::Error for AddressError {}
+
+/// Parses a list of addresses separated by ';' (semicolon), in the order in which
+/// connecting should be attempted. Empty entries are skipped.
+#[inline]
+pub fn parse_addresses(s: &str) -> Result<Vec<Address>, AddressError> {
+    let addresses = s
+        .split(';')
+        .filter(|address| !address.is_empty())
+        .map(Address::from_str)
+        .collect::<Result<Vec<_>, _>>()?;
+    if addresses.is_empty() {
+        return Err(AddressError::Empty);
+    }
+    Ok(addresses)
+}
+
+/// Serializes addresses into a list separated by ';' (semicolon).
+#[inline]
+pub fn format_addresses(addresses: &[Address]) -> String {
+    addresses.iter().map(Address::to_string).collect::<Vec<_>>().join(";")
+}
+
+/// Bytes that may appear unescaped in values.
+fn is_optionally_escaped(b: u8) -> bool {
+    matches!(b, b'-' | b'0'..=b'9' | b'A'..=b'Z' | b'a'..=b'z' | b'_' | b'/' | b'.' | b'\\' | b'*')
+}
+
+fn unescape(value: &str) -> Result<String, AddressError> {
+    let bytes = value.as_bytes();
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = value.get(i + 1..i + 3).ok_or(AddressError::InvalidEscape)?;
+                if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    return Err(AddressError::InvalidEscape);
+                }
+                unescaped.push(u8::from_str_radix(hex, 16).map_err(|_| AddressError::InvalidEscape)?);
+                i += 3;
+            }
+            b if is_optionally_escaped(b) => {
+                unescaped.push(b);
+                i += 1;
+            }
+            _ => {
+                let c = value[i..].chars().next().unwrap_or(char::REPLACEMENT_CHARACTER);
+                return Err(AddressError::UnescapedCharacter(c));
+            }
+        }
+    }
+    String::from_utf8(unescaped).map_err(|_| AddressError::InvalidUtf8)
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for b in value.bytes() {
+        if is_optionally_escaped(b) {
+            escaped.push(b as char);
+        } else {
+            escaped.push_str(&format!("%{:02x}", b));
+        }
+    }
+    escaped
+}
+
+/// The unescaped key-value pairs of an address, consumed by the transport specific parsers.
+struct Options(Vec<(String, String)>);
+
+impl Options {
+    fn take(&mut self, key: &str) -> Option<String> {
+        let i = self.0.iter().position(|(k, _)| k == key)?;
+        Some(self.0.remove(i).1)
+    }
+
+    /// Fails on the first key that was not taken.
+    fn finish(self) -> Result<(), AddressError> {
+        match self.0.into_iter().next() {
+            Some((key, _)) => Err(AddressError::UnknownKey(key)),
+            None => Ok(()),
+        }
+    }
+}
+
+fn parse_unix(options: &mut Options) -> Result<UnixAddress, AddressError> {
+    let mut addresses = Vec::new();
+    if let Some(path) = options.take("path") {
+        addresses.push(UnixAddress::Path(path));
+    }
+    if let Some(name) = options.take("abstract") {
+        addresses.push(UnixAddress::Abstract(name));
+    }
+    if let Some(dir) = options.take("dir") {
+        addresses.push(UnixAddress::Dir(dir));
+    }
+    if let Some(dir) = options.take("tmpdir") {
+        addresses.push(UnixAddress::Tmpdir(dir));
+    }
+    if let Some(runtime) = options.take("runtime") {
+        if runtime != "yes" {
+            return Err(AddressError::InvalidValue("runtime".to_string(), runtime));
+        }
+        addresses.push(UnixAddress::Runtime);
+    }
+
+    match addresses.len() {
+        0 => Err(AddressError::MissingKey("path".to_string())),
+        1 => Ok(addresses.remove(0)),
+        _ => Err(AddressError::ConflictingKeys),
+    }
+}
+
+fn parse_tcp(options: &mut Options) -> Result<TcpAddress, AddressError> {
+    let port = match options.take("port") {
+        Some(port) => Some(port.parse().map_err(|_| AddressError::InvalidValue("port".to_string(), port))?),
+        None => None,
+    };
+    let family = match options.take("family").as_deref() {
+        Some("ipv4") => Some(Family::Ipv4),
+        Some("ipv6") => Some(Family::Ipv6),
+        Some(family) => return Err(AddressError::InvalidValue("family".to_string(), family.to_string())),
+        None => None,
+    };
+    Ok(TcpAddress {
+        host: options.take("host"),
+        bind: options.take("bind"),
+        port,
+        family,
+    })
+}
+
+fn parse_unixexec(options: &mut Options) -> Result<UnixExecAddress, AddressError> {
+    let path = options.take("path").ok_or_else(|| AddressError::MissingKey("path".to_string()))?;
+    let argv0 = options.take("argv0");
+
+    let mut args = Vec::new();
+    while let Some(arg) = options.take(&format!("argv{}", args.len() + 1)) {
+        args.push(arg);
+    }
+    // arguments must be numbered consecutively
+    if options.0.iter().any(|(key, _)| key.starts_with("argv")) {
+        return Err(AddressError::MissingKey(format!("argv{}", args.len() + 1)));
+    }
+    Ok(UnixExecAddress { path, argv0, args })
+}
+
+impl FromStr for Address {
+    type Err = AddressError;
+
+    /// Parses a single address of the form `transport:key=value,key=value`.
+    #[inline]
+    fn from_str(s: &str) -> Result<Address, AddressError> {
+        let (transport, pairs) = match s.find(':') {
+            Some(0) | None => return Err(AddressError::MissingTransport),
+            Some(i) => (&s[..i], &s[i + 1..]),
+        };
+
+        let mut options = Options(Vec::new());
+        for pair in pairs.split(',').filter(|pair| !pair.is_empty()) {
+            let (key, value) = match pair.find('=') {
+                Some(i) => (&pair[..i], &pair[i + 1..]),
+                None => return Err(AddressError::MissingValue(pair.to_string())),
+            };
+            if key.is_empty() {
+                return Err(AddressError::EmptyKey);
+            }
+            if options.0.iter().any(|(k, _)| k == key) {
+                return Err(AddressError::DuplicateKey(key.to_string()));
+            }
+            options.0.push((key.to_string(), unescape(value)?));
+        }
+
+        let guid = match options.take("guid") {
+            Some(guid) => Some(Guid::from_str(&guid).map_err(|_| AddressError::InvalidGuid)?),
+            None => None,
+        };
+
+        let transport = match transport {
+            "unix" => Transport::Unix(parse_unix(&mut options)?),
+            "tcp" => Transport::Tcp(parse_tcp(&mut options)?),
+            "nonce-tcp" => {
+                let noncefile = options.take("noncefile");
+                Transport::NonceTcp(NonceTcpAddress { tcp: parse_tcp(&mut options)?, noncefile })
+            }
+            "unixexec" => Transport::UnixExec(parse_unixexec(&mut options)?),
+            name => Transport::Unknown(name.to_string(), std::mem::take(&mut options.0)),
+        };
+        options.finish()?;
+
+        Ok(Address { transport, guid })
+    }
+}
+
+fn write_tcp(pairs: &mut Vec<(String, String)>, tcp: &TcpAddress) {
+    if let Some(host) = &tcp.host {
+        pairs.push(("host".to_string(), host.clone()));
+    }
+    if let Some(bind) = &tcp.bind {
+        pairs.push(("bind".to_string(), bind.clone()));
+    }
+    if let Some(port) = tcp.port {
+        pairs.push(("port".to_string(), port.to_string()));
+    }
+    match tcp.family {
+        Some(Family::Ipv4) => pairs.push(("family".to_string(), "ipv4".to_string())),
+        Some(Family::Ipv6) => pairs.push(("family".to_string(), "ipv6".to_string())),
+        None => {}
+    }
+}
+
+/// Serializes the address with all values escaped, such that parsing it yields an equal address.
+impl fmt::Display for Address {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut pairs = Vec::new();
+        let transport = match &self.transport {
+            Transport::Unix(unix) => {
+                match unix {
+                    UnixAddress::Path(path) => pairs.push(("path".to_string(), path.clone())),
+                    UnixAddress::Abstract(name) => pairs.push(("abstract".to_string(), name.clone())),
+                    UnixAddress::Dir(dir) => pairs.push(("dir".to_string(), dir.clone())),
+                    UnixAddress::Tmpdir(dir) => pairs.push(("tmpdir".to_string(), dir.clone())),
+                    UnixAddress::Runtime => pairs.push(("runtime".to_string(), "yes".to_string())),
+                }
+                "unix"
+            }
+            Transport::Tcp(tcp) => {
+                write_tcp(&mut pairs, tcp);
+                "tcp"
+            }
+            Transport::NonceTcp(nonce_tcp) => {
+                write_tcp(&mut pairs, &nonce_tcp.tcp);
+                if let Some(noncefile) = &nonce_tcp.noncefile {
+                    pairs.push(("noncefile".to_string(), noncefile.clone()));
+                }
+                "nonce-tcp"
+            }
+            Transport::UnixExec(unixexec) => {
+                pairs.push(("path".to_string(), unixexec.path.clone()));
+                if let Some(argv0) = &unixexec.argv0 {
+                    pairs.push(("argv0".to_string(), argv0.clone()));
+                }
+                for (i, arg) in unixexec.args.iter().enumerate() {
+                    pairs.push((format!("argv{}", i + 1), arg.clone()));
+                }
+                "unixexec"
+            }
+            Transport::Unknown(name, options) => {
+                pairs.extend(options.iter().cloned());
+                name.as_str()
+            }
+        };
+        if let Some(guid) = &self.guid {
+            pairs.push(("guid".to_string(), guid.to_string()));
+        }
+
+        write!(f, "{}:", transport)?;
+        for (i, (key, value)) in pairs.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}={}", key, escape(value))?;
+        }
+        Ok(())
+    }
+}
+
+/// The well-known message buses.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BusType {
+    /// The system-wide message bus.
+    System,
+    /// The message bus of the current login session.
+    Session,
+    /// The message bus that started this process through service activation.
+    Starter,
+}
+
+impl fmt::Display for BusType {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BusType::System => write!(f, "system"),
+            BusType::Session => write!(f, "session"),
+            BusType::Starter => write!(f, "starter"),
+        }
+    }
+}
+
+impl BusType {
+    /// The addresses of the bus according to the environment, in the order in which
+    /// connecting should be attempted.
+    #[inline]
+    pub fn addresses(self) -> Result<Vec<Address>, AddressError> {
+        self.addresses_from(|name| env::var(name).ok())
+    }
+
+    /// Like [`addresses`](BusType::addresses), with environment variables looked up by `var`.
+    /// Empty variables are treated as unset.
+    #[inline]
+    pub fn addresses_from<F>(self, var: F) -> Result<Vec<Address>, AddressError>
+        where F: Fn(&str) -> Option<String>
+    {
+        self.resolve(&|name| var(name).filter(|value| !value.is_empty()))
+    }
+
+    fn resolve(self, var: &dyn Fn(&str) -> Option<String>) -> Result<Vec<Address>, AddressError> {
+        match self {
+            BusType::System => match var(WELL_KNOWN_DBUS_SYSTEM_BUS_ENV) {
+                Some(addresses) => parse_addresses(&addresses),
+                None => parse_addresses(WELL_KNOWN_DBUS_SYSTEM_BUS_ADDRESS),
+            },
+            BusType::Session => {
+                if let Some(addresses) = var(WELL_KNOWN_DBUS_SESSION_BUS_ENV) {
+                    return parse_addresses(&addresses);
+                }
+                let socket = var(XDG_RUNTIME_DIR_ENV).map(|dir| Path::new(&dir).join("bus"));
+                match socket.as_ref().filter(|socket| socket.exists()).and_then(|socket| socket.to_str()) {
+                    Some(path) => Ok(vec![Address { transport: Transport::Unix(UnixAddress::Path(path.to_string())), guid: None }]),
+                    None => Err(AddressError::NoBusAddress(self)),
+                }
+            }
+            BusType::Starter => {
+                if let Some(addresses) = var(DBUS_STARTER_ADDRESS_ENV) {
+                    return parse_addresses(&addresses);
+                }
+                match var(DBUS_STARTER_BUS_TYPE_ENV).as_deref() {
+                    Some("system") => BusType::System.resolve(var),
+                    Some("session") => BusType::Session.resolve(var),
+                    Some(bus_type) => Err(AddressError::InvalidValue(DBUS_STARTER_BUS_TYPE_ENV.to_string(), bus_type.to_string())),
+                    None => Err(AddressError::NoBusAddress(self)),
+                }
+            }
+        }
+    }
+}
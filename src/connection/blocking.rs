@@ -0,0 +1,362 @@
+//! A connection doing blocking IO on its transport.
+
+use std::collections::VecDeque;
+use std::io;
+use std::time::{Duration, Instant};
+
+use crate::address::{Address, BusType, Transport};
+use crate::guid::Guid;
+use crate::message::Message;
+use crate::names::UniqueName;
+use crate::protocol::ClientAuth;
+use crate::transport::tcp::TcpTransport;
+#[cfg(unix)]
+use crate::transport::unix::UnixTransport;
+#[cfg(unix)]
+use crate::transport::unixexec::UnixExecTransport;
+use crate::transport::DbusTransport;
+use crate::type_system::Serial;
+
+use super::{check_reply, client_auth, hello, receive_auth, reply_serial, unique_name, MessageDecoder, Serials, DEFAULT_TIMEOUT, READ_BUFFER_SIZE};
+
+/// An authenticated connection, exchanging messages over the transport `T`.
+///
+/// Messages received while [`call`](Connection::call) waits for a reply are queued and
+/// returned by [`receive`](Connection::receive) in the order they arrived.
+#[derive(Debug)]
+pub struct Connection<T = Box<dyn DbusTransport + Send>> {
+    transport: T,
+    decoder: MessageDecoder,
+    serials: Serials,
+    queue: VecDeque<Message>,
+    guid: Guid,
+    unix_fd: bool,
+    unique_name: Option<UniqueName>,
+}
+
+impl Connection {
+    /// Connects to the first of `addresses` that accepts a connection, authenticates and
+    /// calls Hello, as is required on a message bus. An address whose server has another
+    /// GUID than the address names fails like any other, moving on to the next address.
+    #[inline]
+    pub fn connect(addresses: &[Address]) -> io::Result<Connection> {
+        let mut last_err = io::Error::new(io::ErrorKind::NotFound, "No address to connect to");
+        for address in addresses {
+            match Connection::connect_to(address) {
+                Ok(connection) => return Ok(connection),
+                Err(err) => last_err = err,
+            }
+        }
+        Err(last_err)
+    }
+
+    fn connect_to(address: &Address) -> io::Result<Connection> {
+        let (transport, nul_byte_sent) = open(address)?;
+        let mut auth = client_auth(transport.supports_unix_fds());
+        if nul_byte_sent {
+            auth.skip_nul_byte();
+        }
+        let mut connection = Connection::authenticate(transport, auth)?;
+        if !address.matches_guid(&connection.guid) {
+            let str_err = format!("Server GUID `{}` differs from the one of address `{}`", connection.guid, address);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, str_err));
+        }
+        connection.hello()?;
+        Ok(connection)
+    }
+
+    /// Connects to the message bus `bus_type` at the addresses in the environment.
+    #[inline]
+    pub fn bus(bus_type: BusType) -> io::Result<Connection> {
+        let addresses = bus_type.addresses().map_err(|err| io::Error::new(io::ErrorKind::NotFound, err))?;
+        Connection::connect(&addresses)
+    }
+}
+
+impl<T: DbusTransport> Connection<T> {
+    /// Authenticates with `auth` over `transport` and switches to message mode, without
+    /// calling Hello, e.g. for a peer-to-peer connection. Fails with `TimedOut` if the
+    /// server does not complete the authentication within the default call timeout.
+    #[inline]
+    pub fn authenticate(transport: T, auth: ClientAuth) -> io::Result<Connection<T>> {
+        Connection::authenticate_until(transport, auth, Instant::now() + DEFAULT_TIMEOUT)
+    }
+
+    fn authenticate_until(mut transport: T, mut auth: ClientAuth, deadline: Instant) -> io::Result<Connection<T>> {
+        let mut decoder = MessageDecoder::new();
+        let mut buffer = [0; READ_BUFFER_SIZE];
+        loop {
+            transport.write_all(&auth.take_output())?;
+            if auth.is_authenticated() {
+                break;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "Server did not complete the authentication in time"));
+            }
+            transport.set_read_timeout(Some(deadline - now))?;
+            let result = transport.read(&mut buffer);
+            transport.set_read_timeout(None)?;
+            match result {
+                Ok(n) => receive_auth(&mut auth, &mut decoder, &buffer[..n])?,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock || err.kind() == io::ErrorKind::TimedOut => {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "Server did not complete the authentication in time"));
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(Connection {
+            transport,
+            decoder,
+            serials: Serials::new(),
+            queue: VecDeque::new(),
+            guid: *auth.guid().expect("authenticated clients know the server GUID"),
+            unix_fd: auth.unix_fd(),
+            unique_name: None,
+        })
+    }
+
+    /// Calls `org.freedesktop.DBus.Hello`, which must be the first call on a message bus,
+    /// returning the unique name the bus assigned to the connection.
+    #[inline]
+    pub fn hello(&mut self) -> io::Result<&UniqueName> {
+        let reply = self.call(hello(), DEFAULT_TIMEOUT)?;
+        Ok(self.unique_name.insert(unique_name(reply)?))
+    }
+
+    /// The unique name of the connection, known once Hello was called.
+    #[inline]
+    pub fn unique_name(&self) -> Option<&UniqueName> {
+        self.unique_name.as_ref()
+    }
+
+    /// The GUID of the server.
+    #[inline]
+    pub fn guid(&self) -> &Guid {
+        &self.guid
+    }
+
+    /// Whether Unix file descriptors can be passed.
+    #[inline]
+    pub fn unix_fd(&self) -> bool {
+        self.unix_fd
+    }
+
+    #[inline]
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Assigns the next serial to `message` and sends it, returning the serial.
+    #[inline]
+    pub fn send(&mut self, mut message: Message) -> io::Result<Serial> {
+        if message.unix_fds() > 0 && !self.unix_fd {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "Connection can not pass file descriptors"));
+        }
+        let serial = self.serials.assign(&mut message);
+        self.transport.send_message(&message)?;
+        Ok(serial)
+    }
+
+    /// The next message received, blocking until one arrives. Fails with `UnexpectedEof`
+    /// once the server closed the connection.
+    #[inline]
+    pub fn receive(&mut self) -> io::Result<Message> {
+        match self.queue.pop_front() {
+            Some(message) => Ok(message),
+            None => self.read_message(None),
+        }
+    }
+
+    /// Sends the method call `message` and waits for its reply, queuing the messages
+    /// received meanwhile. An error reply fails with an `io::Error` wrapping a
+    /// [`MethodError`](super::MethodError), no reply within `timeout` with `TimedOut`.
+    #[inline]
+    pub fn call(&mut self, message: Message, timeout: Duration) -> io::Result<Message> {
+        let deadline = Instant::now() + timeout;
+        let serial = self.send(message)?;
+        loop {
+            let message = self.read_message(Some(deadline))?;
+            if reply_serial(&message) == Some(serial) {
+                return check_reply(message);
+            }
+            self.queue.push_back(message);
+        }
+    }
+
+    /// Reads until a message is complete, or fails with `TimedOut` past `deadline`.
+    fn read_message(&mut self, deadline: Option<Instant>) -> io::Result<Message> {
+        let mut buffer = [0; READ_BUFFER_SIZE];
+        loop {
+            if let Some(message) = self.decoder.decode()? {
+                return Ok(message);
+            }
+            if let Some(deadline) = deadline {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "No reply within the timeout"));
+                }
+                self.transport.set_read_timeout(Some(deadline - now))?;
+            }
+            let result = self.recv(&mut buffer);
+            if deadline.is_some() {
+                self.transport.set_read_timeout(None)?;
+            }
+            match result {
+                Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Server closed the connection")),
+                Ok(n) => self.decoder.push(&buffer[..n]),
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(ref err) if deadline.is_some() && (err.kind() == io::ErrorKind::WouldBlock || err.kind() == io::ErrorKind::TimedOut) => {}
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    fn recv(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        #[cfg(unix)]
+        {
+            let mut fds = Vec::new();
+            let n = self.transport.recv_with_fds(buffer, &mut fds)?;
+            self.decoder.push_fds(fds);
+            Ok(n)
+        }
+        #[cfg(not(unix))]
+        self.transport.read(buffer)
+    }
+}
+
+/// Connects the transport of `address`, returning whether the nul byte preceding
+/// authentication was sent along with credentials.
+fn open(address: &Address) -> io::Result<(Box<dyn DbusTransport + Send>, bool)> {
+    match &address.transport {
+        #[cfg(unix)]
+        Transport::Unix(unix) => {
+            let transport = UnixTransport::connect(unix)?;
+            transport.send_nul_byte()?;
+            Ok((Box::new(transport), true))
+        }
+        Transport::Tcp(tcp) => Ok((Box::new(TcpTransport::connect(tcp)?), false)),
+        Transport::NonceTcp(nonce_tcp) => Ok((Box::new(TcpTransport::connect_nonce(nonce_tcp)?), false)),
+        #[cfg(unix)]
+        Transport::UnixExec(unix_exec) => {
+            let transport = UnixExecTransport::spawn(unix_exec)?;
+            transport.transport().send_nul_byte()?;
+            Ok((Box::new(transport), true))
+        }
+        _ => {
+            let str_err = format!("Can not connect to address `{}`", address);
+            Err(io::Error::new(io::ErrorKind::Unsupported, str_err))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::connection::tests::{serve, GUID};
+    use crate::connection::MethodError;
+    use crate::message::{HeaderField, HeaderFieldCode};
+    use crate::protocol::server::{AuthPolicy, ServerAuth};
+    use crate::transport::memory::MemoryTransport;
+    use crate::type_system::{ObjectPath, Value};
+    use crate::{interface_name, member_name};
+    use std::thread;
+
+    fn connection() -> Connection<MemoryTransport> {
+        let (client, server) = MemoryTransport::pair();
+        serve(server, ServerAuth::new(GUID.parse().unwrap(), AuthPolicy::default(), Some(1000)));
+        let mut connection = Connection::authenticate(client, ClientAuth::external(1000)).unwrap();
+        assert_eq!(":1.42", connection.hello().unwrap().as_str());
+        connection
+    }
+
+    #[test]
+    fn hello_and_serials() {
+        let mut connection = connection();
+        assert_eq!(GUID, connection.guid().to_string());
+        assert_eq!(Some(":1.42"), connection.unique_name().map(|name| name.as_str()));
+        assert!(!connection.unix_fd());
+        let signal = Message::signal(ObjectPath("/".into()), interface_name!("org.a"), member_name!("B"), vec![]);
+        assert_eq!(Serial(2), connection.send(signal).unwrap());
+    }
+
+    #[test]
+    fn call_queues_other_messages() {
+        let mut connection = connection();
+        let call = Message::method_call(None, ObjectPath("/".into()), None, member_name!("Echo"), vec![Value::Uint32(7)]);
+        let reply = connection.call(call, DEFAULT_TIMEOUT).unwrap();
+        assert_eq!(Some(Serial(2)), reply.header().reply_serial());
+        assert_eq!(vec![Value::Uint32(7)], reply.body().arguments);
+
+        let signal = connection.receive().unwrap();
+        assert!(matches!(signal.header().field(HeaderFieldCode::Member), Some(HeaderField::Member(member)) if member.as_str() == "Ping"));
+    }
+
+    #[test]
+    fn error_replies_and_timeouts() {
+        let mut connection = connection();
+        let call = Message::method_call(None, ObjectPath("/".into()), None, member_name!("Fail"), vec![]);
+        let err = connection.call(call, DEFAULT_TIMEOUT).unwrap_err();
+        let err = err.into_inner().unwrap().downcast::<MethodError>().unwrap();
+        assert_eq!("org.a.Failed", err.name.as_str());
+        assert_eq!(Some("as requested"), err.message.as_deref());
+
+        let call = Message::method_call(None, ObjectPath("/".into()), None, member_name!("Ignore"), vec![]);
+        let err = connection.call(call, Duration::from_millis(20)).unwrap_err();
+        assert_eq!(io::ErrorKind::TimedOut, err.kind());
+    }
+
+    #[test]
+    fn server_closes_connection() {
+        let (client, server) = MemoryTransport::pair();
+        drop(server);
+        let err = Connection::authenticate(client, ClientAuth::external(1000)).unwrap_err();
+        assert_eq!(io::ErrorKind::BrokenPipe, err.kind());
+    }
+
+    #[test]
+    fn server_does_not_answer() {
+        let (client, _server) = MemoryTransport::pair();
+        let deadline = Instant::now() + Duration::from_millis(50);
+        let err = Connection::authenticate_until(client, ClientAuth::external(1000), deadline).unwrap_err();
+        assert_eq!(io::ErrorKind::TimedOut, err.kind());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn connect_to_address() {
+        use std::os::unix::net::UnixListener;
+
+        let dir = std::env::temp_dir().join(format!("dbus-native-connection-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("socket");
+        let listener = UnixListener::bind(&path).unwrap();
+        let accept = move || {
+            let transport = UnixTransport::from(listener.accept().unwrap().0);
+            transport.recv_nul_byte().unwrap();
+            let uid = transport.peer_credentials().unwrap().uid;
+            let mut auth = ServerAuth::new(GUID.parse().unwrap(), AuthPolicy::default(), Some(uid));
+            auth.skip_nul_byte();
+            serve(transport, auth);
+        };
+        let server = thread::spawn(move || {
+            accept();
+            accept();
+            accept();
+        });
+
+        let other = Guid::from_bytes([0; 16]);
+        let addresses = crate::address::parse_addresses(&format!("unix:path={},guid={}", path.display(), other)).unwrap();
+        let err = Connection::connect(&addresses).err().unwrap();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+
+        let addresses = format!("unix:path={}/missing;unix:path={},guid={};unix:path={},guid={}", dir.display(), path.display(), other, path.display(), GUID);
+        let addresses = crate::address::parse_addresses(&addresses).unwrap();
+        let connection = Connection::connect(&addresses).unwrap();
+        assert_eq!(Some(":1.42"), connection.unique_name().map(|name| name.as_str()));
+        server.join().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
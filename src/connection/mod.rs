@@ -0,0 +1,361 @@
+//! Client connections to a D-Bus server or message bus.
+//!
+//! Authentication with [`ClientAuth`] and message framing with [`MessageDecoder`] do no
+//! IO themselves, the blocking and the async connection only move bytes between them
+//! and a transport.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+#[cfg(unix)]
+use std::collections::VecDeque;
+#[cfg(unix)]
+use std::os::unix::io::OwnedFd;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::message::{HeaderField, HeaderFieldCode, Message, MessageType};
+use crate::names::{ErrorName, UniqueName};
+use crate::protocol::{AuthError, AuthMechanism, ClientAuth};
+use crate::type_system::{Serial, Value};
+use crate::{bus_name, interface_name, member_name, object_path};
+
+pub mod blocking;
+pub mod nonblocking;
+#[cfg(feature = "tokio")]
+pub mod tokio;
+
+/// How long a call waits for its reply unless told otherwise, as in the reference implementation.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(25);
+
+/// Size of the buffer bytes are read into from the transport.
+const READ_BUFFER_SIZE: usize = 4096;
+
+/// Splits the bytes received in message mode into messages, attaching the file
+/// descriptors received along with them.
+#[derive(Debug, Default)]
+pub struct MessageDecoder {
+    buffer: Vec<u8>,
+    #[cfg(unix)]
+    fds: VecDeque<OwnedFd>,
+}
+
+impl MessageDecoder {
+    #[inline]
+    pub fn new() -> MessageDecoder {
+        MessageDecoder::default()
+    }
+
+    #[inline]
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Adds file descriptors received along with the bytes pushed next.
+    #[cfg(unix)]
+    #[inline]
+    pub fn push_fds(&mut self, fds: Vec<OwnedFd>) {
+        self.fds.extend(fds);
+    }
+
+    /// The next message, none until all of its bytes have been pushed. Messages of
+    /// unknown types are skipped, closing the file descriptors sent with them.
+    #[inline]
+    pub fn decode(&mut self) -> io::Result<Option<Message>> {
+        loop {
+            let length = match Message::frame_length(&self.buffer)? {
+                Some(length) if length <= self.buffer.len() => length,
+                _ => return Ok(None),
+            };
+            if self.buffer[1] > MessageType::Signal as u8 {
+                #[cfg(unix)]
+                self.take_fds(Message::frame_unix_fds(&self.buffer[..length])?)?;
+                self.buffer.drain(..length);
+                continue;
+            }
+            #[allow(unused_mut)]
+            let mut message = Message::from_bytes(&self.buffer[..length])?;
+            self.buffer.drain(..length);
+            #[cfg(unix)]
+            message.set_received_fds(self.take_fds(message.unix_fds())?)?;
+            return Ok(Some(message));
+        }
+    }
+
+    /// The `unix_fds` file descriptors received first, which belong to the message decoded.
+    #[cfg(unix)]
+    fn take_fds(&mut self, unix_fds: u32) -> io::Result<Vec<OwnedFd>> {
+        let unix_fds = unix_fds as usize;
+        if unix_fds > self.fds.len() {
+            let str_err = format!("Message announces {} file descriptors but {} were received", unix_fds, self.fds.len());
+            return Err(io::Error::new(io::ErrorKind::InvalidData, str_err));
+        }
+        Ok(self.fds.drain(..unix_fds).collect())
+    }
+}
+
+/// An error reply to a method call, the error of the `io::Error` a failed call returns.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MethodError {
+    pub name: ErrorName,
+    /// The first argument of the reply, if it is a string.
+    pub message: Option<String>,
+}
+
+impl fmt::Display for MethodError {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.message {
+            Some(message) => write!(f, "{}: {}", self.name.as_str(), message),
+            None => write!(f, "{}", self.name.as_str()),
+        }
+    }
+}
+
+impl Error for MethodError {}
+
+/// Assigns the serials of the messages sent on a connection, counting up from 1.
+#[derive(Debug)]
+pub(crate) struct Serials {
+    next: u32,
+}
+
+impl Serials {
+    pub(crate) fn new() -> Serials {
+        Serials { next: 1 }
+    }
+
+    pub(crate) fn assign(&mut self, message: &mut Message) -> Serial {
+        let serial = Serial(self.next);
+        self.next = self.next.checked_add(1).unwrap_or(1);
+        message.set_serial(serial);
+        serial
+    }
+}
+
+/// The authentication of a client connecting to an address, as the effective uid of
+/// the process with the mechanisms of the reference implementation in its order.
+pub(crate) fn client_auth(negotiate_unix_fd: bool) -> ClientAuth {
+    #[cfg(unix)]
+    // SAFETY: geteuid has no preconditions and can not fail.
+    let uid = unsafe { libc::geteuid() };
+    #[cfg(not(unix))]
+    let uid = 0;
+    let mut auth = ClientAuth::new(uid, vec![AuthMechanism::External, AuthMechanism::DbusCookieSha1, AuthMechanism::Anonymous]);
+    auth.set_negotiate_unix_fd(negotiate_unix_fd);
+    auth
+}
+
+/// Handles `bytes` read during authentication, passing those following it to `decoder`.
+/// No bytes mean the server closed the connection.
+pub(crate) fn receive_auth(auth: &mut ClientAuth, decoder: &mut MessageDecoder, bytes: &[u8]) -> io::Result<()> {
+    if bytes.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Server closed the connection during authentication"));
+    }
+    let consumed = auth.receive(bytes).map_err(auth_error)?;
+    decoder.push(&bytes[consumed..]);
+    Ok(())
+}
+
+fn auth_error(err: AuthError) -> io::Error {
+    let kind = match err {
+        AuthError::Rejected(_) | AuthError::TooManyFailures => io::ErrorKind::PermissionDenied,
+        AuthError::Protocol(_) | AuthError::Parse(_) | AuthError::Finished => io::ErrorKind::InvalidData,
+    };
+    io::Error::new(kind, err)
+}
+
+/// The call of `org.freedesktop.DBus.Hello`, which must be the first on a message bus.
+pub(crate) fn hello() -> Message {
+    Message::method_call(
+        Some(bus_name!("org.freedesktop.DBus")),
+        object_path!("/org/freedesktop/DBus"),
+        Some(interface_name!("org.freedesktop.DBus")),
+        member_name!("Hello"),
+        vec![],
+    )
+}
+
+/// The unique name the bus assigned to the connection, returned by Hello.
+pub(crate) fn unique_name(reply: Message) -> io::Result<UniqueName> {
+    match reply.body().arguments.first() {
+        Some(Value::String(name)) => UniqueName::from_str(name).map_err(|err| {
+            let str_err = format!("Invalid unique name `{}` returned by Hello: {:?}", name, err);
+            io::Error::new(io::ErrorKind::InvalidData, str_err)
+        }),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "Hello did not return a unique name")),
+    }
+}
+
+/// The serial of the call `message` replies to, none if it is not a method return or error.
+pub(crate) fn reply_serial(message: &Message) -> Option<Serial> {
+    match message.header().message_type() {
+        MessageType::MethodReturn | MessageType::Error => message.header().reply_serial(),
+        _ => None,
+    }
+}
+
+/// Turns an error reply into an `io::Error` wrapping a [`MethodError`].
+pub(crate) fn check_reply(reply: Message) -> io::Result<Message> {
+    if reply.header().message_type() != MessageType::Error {
+        return Ok(reply);
+    }
+    let name = match reply.header().field(HeaderFieldCode::ErrorName) {
+        Some(HeaderField::ErrorName(name)) => name.clone(),
+        _ => unreachable!("error messages are read with an error name"),
+    };
+    let message = match reply.body().arguments.first() {
+        Some(Value::String(message)) => Some(message.clone()),
+        _ => None,
+    };
+    Err(io::Error::other(MethodError { name, message }))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::error_name;
+    use crate::protocol::server::ServerAuth;
+    use crate::transport::DbusTransport;
+    use crate::type_system::ObjectPath;
+    #[cfg(unix)]
+    use crate::type_system::UnixFd;
+    use std::thread;
+
+    pub(super) const GUID: &str = "1234deadbeef5678deadbeef9abcdef0";
+
+    /// The messages a bus sends in response to `call`.
+    pub(super) fn respond(call: &Message) -> Vec<Message> {
+        let member = match call.header().field(HeaderFieldCode::Member) {
+            Some(HeaderField::Member(member)) => member.as_str().to_string(),
+            _ => return vec![],
+        };
+        match member.as_str() {
+            "Hello" => vec![Message::method_return(call, vec![Value::String(":1.42".to_string())])],
+            "Echo" => vec![
+                Message::signal(ObjectPath("/".into()), interface_name!("org.a"), member_name!("Ping"), vec![]),
+                Message::method_return(call, call.body().arguments.clone()),
+            ],
+            "Fail" => vec![Message::error(call, error_name!("org.a.Failed"), "as requested")],
+            _ => vec![],
+        }
+    }
+
+    /// Authenticates a client on `transport` and answers its calls until it disconnects.
+    pub(super) fn serve<T: DbusTransport + Send + 'static>(mut transport: T, mut auth: ServerAuth) -> thread::JoinHandle<()> {
+        thread::spawn(move || {
+            let mut decoder = MessageDecoder::new();
+            let mut serials = Serials::new();
+            let mut buffer = [0; 1024];
+            loop {
+                let n = match transport.read(&mut buffer) {
+                    Ok(0) | Err(_) => return,
+                    Ok(n) => n,
+                };
+                let consumed = if auth.is_authenticated() { 0 } else { auth.receive(&buffer[..n]).unwrap() };
+                transport.write_all(&auth.take_output()).unwrap();
+                decoder.push(&buffer[consumed..n]);
+                while let Some(call) = decoder.decode().unwrap() {
+                    for mut message in respond(&call) {
+                        serials.assign(&mut message);
+                        transport.send_message(&message).unwrap();
+                    }
+                }
+            }
+        })
+    }
+
+    fn signal(serial: u32) -> Message {
+        let mut message = Message::signal(ObjectPath("/".into()), interface_name!("org.a"), member_name!("B"), vec![Value::Uint32(serial)]);
+        message.set_serial(Serial(serial));
+        message
+    }
+
+    #[test]
+    fn decode_partial_messages() {
+        let mut bytes = signal(1).to_bytes().unwrap();
+        bytes.extend(signal(2).to_bytes().unwrap());
+        let mut decoder = MessageDecoder::new();
+        decoder.push(&bytes[..10]);
+        assert!(decoder.decode().unwrap().is_none());
+        decoder.push(&bytes[10..bytes.len() - 1]);
+        assert_eq!(Serial(1), decoder.decode().unwrap().unwrap().header().serial());
+        assert!(decoder.decode().unwrap().is_none());
+        decoder.push(&bytes[bytes.len() - 1..]);
+        assert_eq!(Serial(2), decoder.decode().unwrap().unwrap().header().serial());
+    }
+
+    #[test]
+    fn skip_unknown_message_types() {
+        let mut bytes = signal(1).to_bytes().unwrap();
+        bytes[1] = 9;
+        bytes.extend(signal(2).to_bytes().unwrap());
+        let mut decoder = MessageDecoder::new();
+        decoder.push(&bytes);
+        assert_eq!(Serial(2), decoder.decode().unwrap().unwrap().header().serial());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn skip_fds_of_unknown_message_types() {
+        use std::os::unix::io::AsRawFd;
+
+        let mut unknown = signal(1);
+        unknown.push_fd_argument(OwnedFd::from(std::fs::File::open("/dev/null").unwrap()));
+        let mut bytes = unknown.to_bytes().unwrap();
+        bytes[1] = 9;
+        let mut message = signal(2);
+        message.push_fd_argument(OwnedFd::from(std::fs::File::open("/dev/null").unwrap()));
+        bytes.extend(message.to_bytes().unwrap());
+
+        let fds = vec![OwnedFd::from(std::fs::File::open("/dev/null").unwrap()), OwnedFd::from(std::fs::File::open("/dev/null").unwrap())];
+        let raw_fd = fds[1].as_raw_fd();
+        let mut decoder = MessageDecoder::new();
+        decoder.push_fds(fds);
+        decoder.push(&bytes);
+        let mut message = decoder.decode().unwrap().unwrap();
+        assert_eq!(Serial(2), message.header().serial());
+        assert_eq!(raw_fd, message.take_fd(UnixFd(0)).unwrap().as_raw_fd());
+        assert!(decoder.fds.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn decode_fds() {
+        let mut message = signal(1);
+        message.push_fd_argument(OwnedFd::from(std::fs::File::open("/dev/null").unwrap()));
+        let mut decoder = MessageDecoder::new();
+        decoder.push(&message.to_bytes().unwrap());
+        assert!(decoder.decode().is_err());
+
+        let mut decoder = MessageDecoder::new();
+        decoder.push_fds(vec![OwnedFd::from(std::fs::File::open("/dev/null").unwrap())]);
+        decoder.push(&message.to_bytes().unwrap());
+        let mut message = decoder.decode().unwrap().unwrap();
+        assert!(message.take_fd(UnixFd(0)).is_ok());
+    }
+
+    #[test]
+    fn serials_skip_zero() {
+        let mut serials = Serials { next: u32::MAX };
+        let mut message = signal(1);
+        assert_eq!(Serial(u32::MAX), serials.assign(&mut message));
+        assert_eq!(Serial(1), serials.assign(&mut message));
+        assert_eq!(Serial(1), message.header().serial());
+    }
+
+    #[test]
+    fn error_replies() {
+        let mut call = hello();
+        call.set_serial(Serial(3));
+        let mut error = Message::error(&call, error_name!("org.a.Failed"), "no");
+        error.set_serial(Serial(4));
+        assert_eq!(Some(Serial(3)), reply_serial(&error));
+        assert_eq!(None, reply_serial(&call));
+
+        let err = check_reply(error).unwrap_err();
+        let err = err.into_inner().unwrap().downcast::<MethodError>().unwrap();
+        assert_eq!("org.a.Failed: no", err.to_string());
+    }
+}
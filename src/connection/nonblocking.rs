@@ -0,0 +1,363 @@
+//! A connection for async code, doing its IO in a background task over any stream
+//! implementing the `futures-io` traits.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::task::{Context, Poll};
+
+use futures_channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures_channel::oneshot;
+use futures_core::Stream;
+use futures_io::{AsyncRead, AsyncWrite};
+use futures_util::future::{self, Either};
+use futures_util::io::{AsyncReadExt, AsyncWriteExt};
+use futures_util::{pin_mut, StreamExt};
+
+use crate::guid::Guid;
+use crate::message::Message;
+use crate::names::UniqueName;
+use crate::protocol::ClientAuth;
+use crate::type_system::Serial;
+
+use super::{check_reply, hello, receive_auth, reply_serial, unique_name, MessageDecoder, Serials, READ_BUFFER_SIZE};
+
+/// The calls waiting for their reply, by serial.
+type Pending = Arc<Mutex<HashMap<u32, oneshot::Sender<Message>>>>;
+
+/// The bytes of a message to write, along with where to deliver its reply.
+#[derive(Debug)]
+struct Outgoing {
+    bytes: Vec<u8>,
+    reply: Option<(Serial, oneshot::Sender<Message>)>,
+}
+
+/// An authenticated connection for async code. Its IO is done by an [`IoTask`], which
+/// has to be spawned on the executor of choice, so the connection works with any
+/// runtime whose streams implement the `futures-io` traits.
+///
+/// The connection is a cheap handle, clones share the same underlying stream. File
+/// descriptors can not be passed.
+#[derive(Clone, Debug)]
+pub struct Connection {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    guid: Guid,
+    serials: Mutex<Serials>,
+    outgoing: UnboundedSender<Outgoing>,
+    incoming: Mutex<Option<Incoming>>,
+    unique_name: Mutex<Option<UniqueName>>,
+}
+
+impl Connection {
+    /// Authenticates with `auth` over `stream` and switches to message mode, without
+    /// calling Hello. The connection can only be used while the returned task runs.
+    #[inline]
+    pub async fn authenticate<S>(mut stream: S, mut auth: ClientAuth) -> io::Result<(Connection, IoTask)>
+    where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        auth.set_negotiate_unix_fd(false);
+        let mut decoder = MessageDecoder::new();
+        let mut buffer = [0; READ_BUFFER_SIZE];
+        loop {
+            stream.write_all(&auth.take_output()).await?;
+            stream.flush().await?;
+            if auth.is_authenticated() {
+                break;
+            }
+            match stream.read(&mut buffer).await {
+                Ok(n) => receive_auth(&mut auth, &mut decoder, &buffer[..n])?,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        let (outgoing, outgoing_rx) = mpsc::unbounded();
+        let (incoming, incoming_rx) = mpsc::unbounded();
+        let connection = Connection {
+            shared: Arc::new(Shared {
+                guid: *auth.guid().expect("authenticated clients know the server GUID"),
+                serials: Mutex::new(Serials::new()),
+                outgoing,
+                incoming: Mutex::new(Some(Incoming(incoming_rx))),
+                unique_name: Mutex::new(None),
+            }),
+        };
+        let task = IoTask(Box::pin(run(stream, decoder, outgoing_rx, incoming)));
+        Ok((connection, task))
+    }
+
+    /// Calls `org.freedesktop.DBus.Hello`, which must be the first call on a message bus,
+    /// returning the unique name the bus assigned to the connection.
+    #[inline]
+    pub async fn hello(&self) -> io::Result<UniqueName> {
+        let name = unique_name(self.call(hello()).await?)?;
+        *lock(&self.shared.unique_name) = Some(name.clone());
+        Ok(name)
+    }
+
+    /// The unique name of the connection, known once Hello was called.
+    #[inline]
+    pub fn unique_name(&self) -> Option<UniqueName> {
+        lock(&self.shared.unique_name).clone()
+    }
+
+    /// The GUID of the server.
+    #[inline]
+    pub fn guid(&self) -> &Guid {
+        &self.shared.guid
+    }
+
+    /// Assigns the next serial to `message` and queues it for the IO task, returning the
+    /// serial. Fails with `BrokenPipe` once the task ended.
+    #[inline]
+    pub fn send(&self, message: Message) -> io::Result<Serial> {
+        self.enqueue(message, None)
+    }
+
+    /// Sends the method call `message` and waits for its reply. An error reply fails with
+    /// an `io::Error` wrapping a [`MethodError`](super::MethodError), the end of the IO
+    /// task with `UnexpectedEof`. There is no timeout, use the one of the runtime; a call
+    /// given up on that way is forgotten once the next call is written.
+    #[inline]
+    pub async fn call(&self, message: Message) -> io::Result<Message> {
+        let (sender, receiver) = oneshot::channel();
+        self.enqueue(message, Some(sender))?;
+        let reply = receiver
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::UnexpectedEof, "Connection closed before the reply arrived"))?;
+        check_reply(reply)
+    }
+
+    /// The stream of received messages other than replies, which can be taken once.
+    /// Messages are queued until it is taken and discarded once it is dropped.
+    #[inline]
+    pub fn incoming(&self) -> Option<Incoming> {
+        lock(&self.shared.incoming).take()
+    }
+
+    fn enqueue(&self, mut message: Message, reply: Option<oneshot::Sender<Message>>) -> io::Result<Serial> {
+        if message.unix_fds() > 0 {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "Connection can not pass file descriptors"));
+        }
+        // Serials are assigned and queued under the lock, so messages are written in
+        // the order of their serials.
+        let mut serials = lock(&self.shared.serials);
+        let serial = serials.assign(&mut message);
+        let bytes = message.to_bytes()?;
+        let reply = reply.map(|sender| (serial, sender));
+        self.shared
+            .outgoing
+            .unbounded_send(Outgoing { bytes, reply })
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Connection is closed"))?;
+        Ok(serial)
+    }
+}
+
+/// The messages received on a [`Connection`] other than replies, ending with its
+/// [`IoTask`].
+#[derive(Debug)]
+pub struct Incoming(UnboundedReceiver<Message>);
+
+impl Stream for Incoming {
+    type Item = Message;
+
+    #[inline]
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Message>> {
+        self.0.poll_next_unpin(cx)
+    }
+}
+
+/// The reading and writing of a [`Connection`], to be spawned on an executor.
+///
+/// It completes with `Ok` once every handle of the connection was dropped, or with the
+/// error that ended the connection, e.g. `UnexpectedEof` when the server closed it.
+/// Calls waiting for a reply fail when it ends or is dropped.
+pub struct IoTask(Pin<Box<dyn Future<Output = io::Result<()>> + Send>>);
+
+impl fmt::Debug for IoTask {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("IoTask")
+    }
+}
+
+impl Future for IoTask {
+    type Output = io::Result<()>;
+
+    #[inline]
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.0.as_mut().poll(cx)
+    }
+}
+
+async fn run<S>(stream: S, decoder: MessageDecoder, outgoing: UnboundedReceiver<Outgoing>, incoming: UnboundedSender<Message>) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let pending = Pending::default();
+    let (reader, writer) = stream.split();
+    let read = read_messages(reader, decoder, incoming, pending.clone());
+    let write = write_messages(writer, outgoing, pending);
+    pin_mut!(read, write);
+    match future::select(read, write).await {
+        Either::Left((result, _)) | Either::Right((result, _)) => result,
+    }
+}
+
+/// Passes replies to their callers and other messages to `incoming`.
+async fn read_messages<R: AsyncRead + Unpin>(mut reader: R, mut decoder: MessageDecoder, incoming: UnboundedSender<Message>, pending: Pending) -> io::Result<()> {
+    let mut buffer = [0; READ_BUFFER_SIZE];
+    loop {
+        while let Some(message) = decoder.decode()? {
+            let caller = reply_serial(&message).and_then(|serial| lock(&pending).remove(&serial.0));
+            // Callers may have given up waiting and nobody may listen to incoming messages.
+            let _ = match caller {
+                Some(caller) => caller.send(message).ok(),
+                None => incoming.unbounded_send(message).ok(),
+            };
+        }
+        match reader.read(&mut buffer).await {
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Server closed the connection")),
+            Ok(n) => decoder.push(&buffer[..n]),
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Writes queued messages, registering calls before they are written so their reply
+/// can not arrive first. Calls whose future was dropped, e.g. by a timeout of the
+/// runtime, are forgotten then, as their reply may never arrive.
+async fn write_messages<W: AsyncWrite + Unpin>(mut writer: W, mut outgoing: UnboundedReceiver<Outgoing>, pending: Pending) -> io::Result<()> {
+    while let Some(Outgoing { bytes, reply }) = outgoing.next().await {
+        if let Some((serial, caller)) = reply {
+            let mut pending = lock(&pending);
+            pending.retain(|_, caller| !caller.is_canceled());
+            if !caller.is_canceled() {
+                pending.insert(serial.0, caller);
+            }
+        }
+        writer.write_all(&bytes).await?;
+        writer.flush().await?;
+    }
+    writer.close().await
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::connection::tests::{serve, GUID};
+    use crate::connection::MethodError;
+    use crate::message::{HeaderField, HeaderFieldCode};
+    use crate::protocol::server::{AuthPolicy, ServerAuth};
+    use crate::transport::memory::MemoryTransport;
+    use crate::type_system::{ObjectPath, Value};
+    use crate::interface_name;
+    use futures_executor::block_on;
+    use std::thread;
+
+    fn connection() -> (Connection, IoTask, thread::JoinHandle<()>) {
+        let (client, server) = MemoryTransport::pair();
+        let server = serve(server, ServerAuth::new(GUID.parse().unwrap(), AuthPolicy::default(), Some(1000)));
+        let (connection, task) = block_on(Connection::authenticate(client, ClientAuth::external(1000))).unwrap();
+        assert_eq!(GUID, connection.guid().to_string());
+        (connection, task, server)
+    }
+
+    fn call(member: &str, args: Vec<Value>) -> Message {
+        Message::method_call(None, ObjectPath("/".into()), Some(interface_name!("org.a")), member.parse().unwrap(), args)
+    }
+
+    #[test]
+    fn calls_and_incoming() {
+        let (connection, task, _) = connection();
+        let task = thread::spawn(move || block_on(task));
+        block_on(async {
+            assert_eq!(None, connection.unique_name());
+            assert_eq!(":1.42", connection.hello().await.unwrap().as_str());
+            assert_eq!(":1.42", connection.unique_name().unwrap().as_str());
+
+            let reply = connection.call(call("Echo", vec![Value::Uint32(7)])).await.unwrap();
+            assert_eq!(vec![Value::Uint32(7)], reply.body().arguments);
+
+            let err = connection.call(call("Fail", vec![])).await.unwrap_err();
+            let err = err.into_inner().unwrap().downcast::<MethodError>().unwrap();
+            assert_eq!("org.a.Failed: as requested", err.to_string());
+
+            let mut incoming = connection.incoming().unwrap();
+            assert!(connection.incoming().is_none());
+            let signal = incoming.next().await.unwrap();
+            assert!(matches!(signal.header().field(HeaderFieldCode::Member), Some(HeaderField::Member(member)) if member.as_str() == "Ping"));
+        });
+        drop(connection);
+        assert!(task.join().unwrap().is_ok());
+    }
+
+    #[test]
+    fn concurrent_calls() {
+        let (connection, task, _) = connection();
+        let task = thread::spawn(move || block_on(task));
+        let calls = (0..8).map(|i| {
+            let connection = connection.clone();
+            async move { connection.call(call("Echo", vec![Value::Uint32(i)])).await.unwrap() }
+        });
+        let replies = block_on(future::join_all(calls));
+        for (i, reply) in replies.iter().enumerate() {
+            assert_eq!(vec![Value::Uint32(i as u32)], reply.body().arguments);
+        }
+        drop(connection);
+        assert!(task.join().unwrap().is_ok());
+    }
+
+    #[test]
+    fn dropping_task_fails_calls() {
+        let (connection, task, _) = connection();
+        let pending = connection.call(call("Silent", vec![]));
+        let (result, _) = block_on(future::join(pending, async { drop(task) }));
+        assert_eq!(io::ErrorKind::UnexpectedEof, result.unwrap_err().kind());
+        let err = connection.send(call("Silent", vec![])).unwrap_err();
+        assert_eq!(io::ErrorKind::BrokenPipe, err.kind());
+    }
+
+    #[test]
+    fn cancelled_calls_are_forgotten() {
+        let pending = Pending::default();
+        let (first, first_rx) = oneshot::channel();
+        let (cancelled, _) = oneshot::channel();
+        let (outgoing, outgoing_rx) = mpsc::unbounded();
+        outgoing.unbounded_send(Outgoing { bytes: vec![1], reply: Some((Serial(1), first)) }).unwrap();
+        outgoing.unbounded_send(Outgoing { bytes: vec![2], reply: Some((Serial(2), cancelled)) }).unwrap();
+        drop(outgoing);
+        block_on(write_messages(Vec::new(), outgoing_rx, pending.clone())).unwrap();
+        assert_eq!(vec![1], lock(&pending).keys().copied().collect::<Vec<_>>());
+
+        drop(first_rx);
+        let (third, _third_rx) = oneshot::channel();
+        let (outgoing, outgoing_rx) = mpsc::unbounded();
+        outgoing.unbounded_send(Outgoing { bytes: vec![3], reply: Some((Serial(3), third)) }).unwrap();
+        drop(outgoing);
+        block_on(write_messages(Vec::new(), outgoing_rx, pending.clone())).unwrap();
+        assert_eq!(vec![3], lock(&pending).keys().copied().collect::<Vec<_>>());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn fds_unsupported() {
+        let (connection, _, _) = connection();
+        let mut message = call("Echo", vec![]);
+        message.push_fd_argument(std::os::unix::io::OwnedFd::from(std::fs::File::open("/dev/null").unwrap()));
+        assert_eq!(io::ErrorKind::Unsupported, connection.send(message).unwrap_err().kind());
+    }
+}
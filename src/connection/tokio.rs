@@ -0,0 +1,200 @@
+//! Connecting an async [`Connection`] on the tokio runtime.
+
+use std::io;
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures_io::{AsyncRead, AsyncWrite};
+
+use crate::address::{Address, BusType, Transport};
+use crate::transport::tcp::TcpTransport;
+#[cfg(unix)]
+use crate::transport::unix::UnixTransport;
+
+use super::{client_auth, DEFAULT_TIMEOUT};
+use super::nonblocking::Connection;
+
+/// Adapts a tokio stream to the `futures-io` traits the async connection is built on.
+#[derive(Debug)]
+pub struct TokioIo<T>(pub T);
+
+impl<T: ::tokio::io::AsyncRead + Unpin> AsyncRead for TokioIo<T> {
+    #[inline]
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let mut buf = ::tokio::io::ReadBuf::new(buf);
+        match Pin::new(&mut self.0).poll_read(cx, &mut buf) {
+            Poll::Ready(Ok(())) => Poll::Ready(Ok(buf.filled().len())),
+            Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<T: ::tokio::io::AsyncWrite + Unpin> AsyncWrite for TokioIo<T> {
+    #[inline]
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    #[inline]
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    #[inline]
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+/// A stream connected to an address, before it is handed to tokio.
+enum Opened {
+    /// A Unix socket, the nul byte preceding authentication already sent.
+    #[cfg(unix)]
+    Unix(UnixStream),
+    Tcp(TcpStream),
+}
+
+/// Connects to the first of `addresses` that accepts a connection, authenticates, spawns
+/// the IO task on the current runtime and calls Hello, as is required on a message bus.
+/// An address whose server has another GUID than the address names, or does not
+/// complete the authentication and Hello within the default call timeout, fails like
+/// any other, moving on to the next address. The runtime must have its time driver enabled.
+#[inline]
+pub async fn connect(addresses: &[Address]) -> io::Result<Connection> {
+    let mut last_err = io::Error::new(io::ErrorKind::NotFound, "No address to connect to");
+    for address in addresses {
+        match connect_to(address, DEFAULT_TIMEOUT).await {
+            Ok(connection) => return Ok(connection),
+            Err(err) => last_err = err,
+        }
+    }
+    Err(last_err)
+}
+
+async fn connect_to(address: &Address, timeout: Duration) -> io::Result<Connection> {
+    let opening = address.clone();
+    let opened = ::tokio::task::spawn_blocking(move || open(&opening)).await.map_err(io::Error::other)??;
+    let mut auth = client_auth(false);
+    let authenticating = async move {
+        match opened {
+            #[cfg(unix)]
+            Opened::Unix(stream) => {
+                auth.skip_nul_byte();
+                stream.set_nonblocking(true)?;
+                Connection::authenticate(TokioIo(::tokio::net::UnixStream::from_std(stream)?), auth).await
+            }
+            Opened::Tcp(stream) => {
+                stream.set_nonblocking(true)?;
+                Connection::authenticate(TokioIo(::tokio::net::TcpStream::from_std(stream)?), auth).await
+            }
+        }
+    };
+    let (connection, task) = within(timeout, authenticating).await?;
+    if !address.matches_guid(connection.guid()) {
+        let str_err = format!("Server GUID `{}` differs from the one of address `{}`", connection.guid(), address);
+        return Err(io::Error::new(io::ErrorKind::InvalidData, str_err));
+    }
+    ::tokio::spawn(task);
+    within(timeout, connection.hello()).await?;
+    Ok(connection)
+}
+
+/// Runs `future` for at most `timeout`, failing with `TimedOut` like a blocking read.
+async fn within<T>(timeout: Duration, future: impl Future<Output = io::Result<T>>) -> io::Result<T> {
+    match ::tokio::time::timeout(timeout, future).await {
+        Ok(result) => result,
+        Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "Server did not answer in time")),
+    }
+}
+
+/// Connects to the message bus `bus_type` at the addresses in the environment.
+#[inline]
+pub async fn bus(bus_type: BusType) -> io::Result<Connection> {
+    let addresses = bus_type.addresses().map_err(|err| io::Error::new(io::ErrorKind::NotFound, err))?;
+    connect(&addresses).await
+}
+
+/// Connects the socket of `address`, blocking.
+fn open(address: &Address) -> io::Result<Opened> {
+    match &address.transport {
+        #[cfg(unix)]
+        Transport::Unix(unix) => {
+            let transport = UnixTransport::connect(unix)?;
+            transport.send_nul_byte()?;
+            Ok(Opened::Unix(transport.into_inner()))
+        }
+        Transport::Tcp(tcp) => Ok(Opened::Tcp(TcpTransport::connect(tcp)?.into_inner())),
+        Transport::NonceTcp(nonce_tcp) => Ok(Opened::Tcp(TcpTransport::connect_nonce(nonce_tcp)?.into_inner())),
+        _ => {
+            let str_err = format!("Can not connect to address `{}` with tokio", address);
+            Err(io::Error::new(io::ErrorKind::Unsupported, str_err))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::connection::tests::{serve, GUID};
+    use crate::guid::Guid;
+    use crate::protocol::server::{AuthPolicy, ServerAuth};
+    use std::thread;
+
+    #[cfg(unix)]
+    #[test]
+    fn connect_to_address() {
+        use std::os::unix::net::UnixListener;
+
+        let dir = std::env::temp_dir().join(format!("dbus-native-tokio-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("socket");
+        let listener = UnixListener::bind(&path).unwrap();
+        let accept = move || {
+            let transport = UnixTransport::from(listener.accept().unwrap().0);
+            transport.recv_nul_byte().unwrap();
+            let uid = transport.peer_credentials().unwrap().uid;
+            let mut auth = ServerAuth::new(GUID.parse().unwrap(), AuthPolicy::default(), Some(uid));
+            auth.skip_nul_byte();
+            serve(transport, auth)
+        };
+        let server = thread::spawn(move || {
+            accept();
+            accept().join().unwrap();
+        });
+
+        let runtime = ::tokio::runtime::Builder::new_current_thread().enable_io().enable_time().build().unwrap();
+        let other = Guid::from_bytes([0; 16]);
+        let addresses = format!("unix:path={}/missing;unix:path={},guid={};unix:path={},guid={}", dir.display(), path.display(), other, path.display(), GUID);
+        let addresses = crate::address::parse_addresses(&addresses).unwrap();
+        let connection = runtime.block_on(connect(&addresses)).unwrap();
+        assert_eq!(Some(":1.42"), connection.unique_name().as_ref().map(|name| name.as_str()));
+        drop(connection);
+        drop(runtime);
+        server.join().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn server_does_not_answer() {
+        use std::os::unix::net::UnixListener;
+
+        let dir = std::env::temp_dir().join(format!("dbus-native-tokio-silent-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("socket");
+        let _listener = UnixListener::bind(&path).unwrap();
+
+        let runtime = ::tokio::runtime::Builder::new_current_thread().enable_io().enable_time().build().unwrap();
+        let address = crate::address::parse_addresses(&format!("unix:path={}", path.display())).unwrap().remove(0);
+        let err = runtime.block_on(connect_to(&address, Duration::from_millis(50))).err().unwrap();
+        assert_eq!(io::ErrorKind::TimedOut, err.kind());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
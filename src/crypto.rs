@@ -0,0 +1,13 @@
+//! Randomness and comparison of secrets, shared by the nonces, cookies and GUIDs.
+
+use std::io;
+
+/// Fills `buf` with random bytes from the operating system.
+pub(crate) fn random(buf: &mut [u8]) -> io::Result<()> {
+    getrandom::getrandom(buf).map_err(|err| io::Error::other(err.to_string()))
+}
+
+/// Compares without returning early, not revealing the position of the first difference.
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
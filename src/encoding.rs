@@ -0,0 +1,87 @@
+use byteorder::ByteOrder;
+use std::io;
+
+use crate::gvariant;
+use crate::reader::DbusReader;
+use crate::type_system::{Signature, Type, Value};
+use crate::writer::DbusWriter;
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use byteorder::LittleEndian;
+
+    #[test]
+    fn encodings_round_trip() {
+        let value = Value::Struct(vec![
+            Value::Boolean(true),
+            Value::Array(Signature("s".into()), vec![Value::String("a".to_string()), Value::String("bc".to_string())]),
+            Value::Variant(Box::new(Value::Int16(-3))),
+        ]);
+        for encoding in &[Encoding::DBus, Encoding::GVariant] {
+            let bytes = encoding.to_bytes::<LittleEndian>(&value).unwrap();
+            assert_eq!(value, encoding.from_bytes::<LittleEndian>(&value.signature(), &bytes).unwrap());
+        }
+        // booleans are 4 bytes in the D-Bus encoding but a single byte in GVariant
+        assert_eq!(28, Encoding::DBus.to_bytes::<LittleEndian>(&value).unwrap().len());
+        assert_eq!(13, Encoding::GVariant.to_bytes::<LittleEndian>(&value).unwrap().len());
+    }
+
+    #[test]
+    fn maybe_has_no_dbus_encoding() {
+        let value = Value::Maybe(Signature("y".into()), None);
+        assert!(Encoding::DBus.to_bytes::<LittleEndian>(&value).is_err());
+        assert!(Encoding::GVariant.to_bytes::<LittleEndian>(&value).is_ok());
+    }
+
+    #[test]
+    fn mismatched_array_elements() {
+        let value = Value::Array(Signature("u".into()), vec![Value::Uint32(1), Value::String("a".to_string())]);
+        for encoding in &[Encoding::DBus, Encoding::GVariant] {
+            let err = encoding.to_bytes::<LittleEndian>(&value).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+        }
+    }
+}
+
+/// Serialization formats of the type system.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    /// The D-Bus marshaling format as used in messages.
+    DBus,
+    /// The GVariant serialization format used by GLib and stored in dconf, GVDB or ostree.
+    GVariant,
+}
+
+impl Encoding {
+    /// Serializes `value` with numbers in the byte order `T`.
+    #[inline]
+    pub fn to_bytes<T: ByteOrder>(self, value: &Value) -> Result<Vec<u8>, io::Error> {
+        match self {
+            Encoding::DBus => {
+                let mut writer = DbusWriter::new(Vec::with_capacity(value.encoded_size(0)));
+                writer.write_value::<T>(value)?;
+                Ok(writer.into_inner())
+            }
+            Encoding::GVariant => gvariant::to_bytes::<T>(value),
+        }
+    }
+
+    /// Deserializes a value of the single complete type `signature` spanning all of `bytes`.
+    #[inline]
+    pub fn from_bytes<T: ByteOrder>(self, signature: &Signature, bytes: &[u8]) -> Result<Value, io::Error> {
+        match self {
+            Encoding::DBus => {
+                let t = Type::parse_single(&signature.0).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))?;
+                let mut reader = DbusReader::new(bytes);
+                let value = reader.read_value::<T>(&t)?;
+                if reader.position() != bytes.len() {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "Trailing bytes after value"));
+                }
+                Ok(value)
+            }
+            Encoding::GVariant => gvariant::from_bytes::<T>(signature, bytes),
+        }
+    }
+}
@@ -0,0 +1,157 @@
+//! The globally unique ID identifying a server, shared by all its addresses. The reference
+//! implementation generates 12 random bytes followed by the creation time, and formats
+//! it as 32 hex digits.
+//! https://dbus.freedesktop.org/doc/dbus-specification.html#uuids
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::crypto::random;
+use crate::type_system::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Guid([u8; 16]);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GuidError {
+    /// The GUID does not consist of 32 characters.
+    InvalidLength(usize),
+    InvalidCharacter(char),
+    /// The value holding the GUID is not a string.
+    NotAString,
+}
+
+impl fmt::Display for GuidError {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GuidError::InvalidLength(len) => write!(f, "GUID has {} instead of 32 hex digits", len),
+            GuidError::InvalidCharacter(c) => write!(f, "invalid character '{}' in GUID", c),
+            GuidError::NotAString => write!(f, "GUID is not a string"),
+        }
+    }
+}
+
+impl Error for GuidError {}
+
+impl Guid {
+    /// A new GUID of 12 random bytes and the current time in seconds since the Unix
+    /// epoch, truncated to 32 bits, in big endian.
+    #[inline]
+    pub fn generate() -> io::Result<Guid> {
+        let mut bytes = [0; 16];
+        random(&mut bytes[..12])?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        bytes[12..].copy_from_slice(&(now as u32).to_be_bytes());
+        Ok(Guid(bytes))
+    }
+
+    #[inline]
+    pub const fn from_bytes(bytes: [u8; 16]) -> Guid {
+        Guid(bytes)
+    }
+
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8; 16] {
+        &self.0
+    }
+
+    /// The creation time in seconds since the Unix epoch, if generated in the reference
+    /// format.
+    #[inline]
+    pub fn timestamp(&self) -> u32 {
+        u32::from_be_bytes([self.0[12], self.0[13], self.0[14], self.0[15]])
+    }
+}
+
+impl FromStr for Guid {
+    type Err = GuidError;
+
+    /// Parses 32 hex digits of either case.
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(c) = s.chars().find(|c| !c.is_ascii_hexdigit()) {
+            return Err(GuidError::InvalidCharacter(c));
+        }
+        if s.len() != 32 {
+            return Err(GuidError::InvalidLength(s.len()));
+        }
+        let mut bytes = [0; 16];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).expect("hex digits were checked");
+        }
+        Ok(Guid(bytes))
+    }
+}
+
+/// Formats as 32 lower case hex digits.
+impl fmt::Display for Guid {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in &self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// The string returned by `org.freedesktop.DBus.GetId`.
+impl From<Guid> for Value {
+    #[inline]
+    fn from(guid: Guid) -> Value {
+        Value::String(guid.to_string())
+    }
+}
+
+/// Reads the reply of `org.freedesktop.DBus.GetId`.
+impl TryFrom<&Value> for Guid {
+    type Error = GuidError;
+
+    #[inline]
+    fn try_from(value: &Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::String(s) => s.parse(),
+            _ => Err(GuidError::NotAString),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn generate() {
+        let a = Guid::generate().unwrap();
+        let b = Guid::generate().unwrap();
+        assert_ne!(a, b);
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as u32;
+        assert!(now - a.timestamp() < 60);
+    }
+
+    #[test]
+    fn parse_and_format() {
+        let guid: Guid = "0123456789abcdef0123456789ABCDEF".parse().unwrap();
+        assert_eq!(0x89ab_cdef, guid.timestamp());
+        assert_eq!("0123456789abcdef0123456789abcdef", guid.to_string());
+        assert_eq!(Ok(guid), guid.to_string().parse());
+        assert_eq!(Err(GuidError::InvalidLength(4)), "1234".parse::<Guid>());
+        assert_eq!(Err(GuidError::InvalidCharacter('g')), "g123456789abcdef0123456789abcdef".parse::<Guid>());
+        assert_eq!(Err(GuidError::InvalidCharacter('é')), "é123456789abcdef0123456789abcde".parse::<Guid>());
+    }
+
+    #[test]
+    fn get_id_value() {
+        let guid = Guid::from_bytes([7; 16]);
+        let value = Value::from(guid);
+        assert_eq!(Ok(guid), Guid::try_from(&value));
+        assert_eq!(Err(GuidError::NotAString), Guid::try_from(&Value::Uint32(7)));
+        assert_eq!(Err(GuidError::InvalidLength(0)), Guid::try_from(&Value::String(String::new())));
+        assert_eq!("GUID is not a string", GuidError::NotAString.to_string());
+    }
+}
@@ -0,0 +1,535 @@
+//! https://developer.gnome.org/glib/stable/gvariant-text.html
+//! https://people.gnome.org/~desrt/gvariant-serialisation.pdf
+//!
+//! GVariant serializes the D-Bus type system, extended by the maybe type `m`, with
+//! container sizes given by the enclosing container instead of length prefixes.
+//! Variable-sized elements are delimited by framing offsets at the end of their container.
+use byteorder::{ByteOrder, LittleEndian};
+use std::cmp;
+use std::io;
+
+use crate::type_system::{ObjectPath, Signature, Type, UnixFd, Value};
+use crate::writer::padding;
+
+type Result<T> = std::result::Result<T, io::Error>;
+
+/// The maximum total depth of container nesting, 32 array type codes and 32 open parentheses.
+const MAX_VALUE_DEPTH: usize = 64;
+
+/// Alignment boundary of the type in bytes in the GVariant encoding.
+#[inline]
+pub fn alignment(t: &Type) -> usize {
+    match t {
+        Type::Byte | Type::Boolean | Type::String | Type::ObjectPath | Type::Signature => 1,
+        Type::Int16 | Type::Uint16 => 2,
+        Type::Int32 | Type::Uint32 | Type::UnixFd => 4,
+        Type::Int64 | Type::Uint64 | Type::Double | Type::Variant => 8,
+        Type::Array(element) | Type::Maybe(element) => alignment(element),
+        Type::Struct(fields) => fields.iter().map(alignment).max().unwrap_or(1),
+        Type::DictEntry(key, value) => cmp::max(alignment(key), alignment(value)),
+    }
+}
+
+/// Size in bytes of all values of the type in the GVariant encoding,
+/// or `None` if the size depends on the value.
+#[inline]
+pub fn fixed_size(t: &Type) -> Option<usize> {
+    match t {
+        Type::Byte | Type::Boolean => Some(1),
+        Type::Int16 | Type::Uint16 => Some(2),
+        Type::Int32 | Type::Uint32 | Type::UnixFd => Some(4),
+        Type::Int64 | Type::Uint64 | Type::Double => Some(8),
+        Type::String | Type::ObjectPath | Type::Signature | Type::Variant | Type::Array(_) | Type::Maybe(_) => None,
+        Type::Struct(fields) => struct_fixed_size(&fields.iter().collect::<Vec<_>>()),
+        Type::DictEntry(key, value) => struct_fixed_size(&[key, value]),
+    }
+}
+
+/// Structures are fixed-sized if all members are, their size is rounded up to their alignment.
+/// The unit type `()` has a size of 1.
+fn struct_fixed_size(fields: &[&Type]) -> Option<usize> {
+    if fields.is_empty() {
+        return Some(1);
+    }
+
+    let mut size = 0;
+    for field in fields {
+        size += padding(size, alignment(field)) + fixed_size(field)?;
+    }
+    let alignment = fields.iter().map(|field| alignment(field)).max().unwrap_or(1);
+    Some(size + padding(size, alignment))
+}
+
+/// Serializes `value` in the GVariant format with numbers in the byte order `T`.
+#[inline]
+pub fn to_bytes<T: ByteOrder>(value: &Value) -> Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    write_value::<T>(&mut buffer, value)?;
+    Ok(buffer)
+}
+
+/// Deserializes a value of the single complete type `signature` spanning all of `bytes`.
+#[inline]
+pub fn from_bytes<T: ByteOrder>(signature: &Signature, bytes: &[u8]) -> Result<Value> {
+    let mut types = Type::parse_gvariant(&signature.0).map_err(invalid_data)?;
+    if types.len() != 1 {
+        return Err(invalid_data("Expected a single complete type"));
+    }
+    read_value::<T>(&types.remove(0), bytes, 0)
+}
+
+fn invalid_data<E: ToString>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
+
+fn value_type(value: &Value) -> Result<Type> {
+    let mut types = Type::parse_gvariant(&value.signature().0).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))?;
+    Ok(types.remove(0))
+}
+
+fn write_padding(buffer: &mut Vec<u8>, alignment: usize) {
+    let len = buffer.len() + padding(buffer.len(), alignment);
+    buffer.resize(len, 0);
+}
+
+fn write_string(buffer: &mut Vec<u8>, s: &str) -> Result<()> {
+    if s.contains('\0') {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Strings must not contain nul bytes"));
+    }
+    buffer.extend_from_slice(s.as_bytes());
+    buffer.push(0);
+    Ok(())
+}
+
+fn write_value<T: ByteOrder>(buffer: &mut Vec<u8>, value: &Value) -> Result<()> {
+    match value {
+        Value::Byte(n) => buffer.push(*n),
+        Value::Boolean(b) => buffer.push(*b as u8),
+        Value::Int16(i) => {
+            write_padding(buffer, 2);
+            let mut bytes = [0; 2];
+            T::write_i16(&mut bytes, *i);
+            buffer.extend_from_slice(&bytes);
+        }
+        Value::Uint16(u) => {
+            write_padding(buffer, 2);
+            let mut bytes = [0; 2];
+            T::write_u16(&mut bytes, *u);
+            buffer.extend_from_slice(&bytes);
+        }
+        Value::Int32(i) => {
+            write_padding(buffer, 4);
+            let mut bytes = [0; 4];
+            T::write_i32(&mut bytes, *i);
+            buffer.extend_from_slice(&bytes);
+        }
+        Value::Uint32(u) | Value::UnixFd(UnixFd(u)) => {
+            write_padding(buffer, 4);
+            let mut bytes = [0; 4];
+            T::write_u32(&mut bytes, *u);
+            buffer.extend_from_slice(&bytes);
+        }
+        Value::Int64(i) => {
+            write_padding(buffer, 8);
+            let mut bytes = [0; 8];
+            T::write_i64(&mut bytes, *i);
+            buffer.extend_from_slice(&bytes);
+        }
+        Value::Uint64(u) => {
+            write_padding(buffer, 8);
+            let mut bytes = [0; 8];
+            T::write_u64(&mut bytes, *u);
+            buffer.extend_from_slice(&bytes);
+        }
+        Value::Double(f) => {
+            write_padding(buffer, 8);
+            let mut bytes = [0; 8];
+            T::write_f64(&mut bytes, *f);
+            buffer.extend_from_slice(&bytes);
+        }
+        Value::String(s) => write_string(buffer, s)?,
+        Value::ObjectPath(ObjectPath(s)) => write_string(buffer, s)?,
+        Value::Signature(Signature(s)) => write_string(buffer, s)?,
+        Value::Array(element, elements) => {
+            Value::check_elements(element, elements)?;
+            let element = Type::parse_gvariant(&element.0).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))?;
+            let element = match element.as_slice() {
+                [element] => element,
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "Array element signature must be a single complete type")),
+            };
+            write_padding(buffer, alignment(element));
+            let start = buffer.len();
+            let mut offsets = Vec::new();
+            for x in elements {
+                write_padding(buffer, alignment(element));
+                write_value::<T>(buffer, x)?;
+                if fixed_size(element).is_none() {
+                    offsets.push(buffer.len() - start);
+                }
+            }
+            write_framing_offsets(buffer, start, &offsets);
+        }
+        Value::Struct(fields) => write_struct::<T>(buffer, &fields.iter().collect::<Vec<_>>())?,
+        Value::DictEntry(key, value) => write_struct::<T>(buffer, &[key, value])?,
+        Value::Variant(value) => {
+            write_padding(buffer, 8);
+            write_value::<T>(buffer, value)?;
+            buffer.push(0);
+            buffer.extend_from_slice(value.signature().0.as_bytes());
+        }
+        Value::Maybe(_, None) => {}
+        Value::Maybe(_, Some(value)) => {
+            write_value::<T>(buffer, value)?;
+            if fixed_size(&value_type(value)?).is_none() {
+                buffer.push(0);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Members are aligned relative to the start of the structure, the end of every
+/// variable-sized member except the last is stored in a framing offset, in reverse order.
+fn write_struct<T: ByteOrder>(buffer: &mut Vec<u8>, fields: &[&Value]) -> Result<()> {
+    let types = fields.iter().map(|field| value_type(field)).collect::<Result<Vec<_>>>()?;
+    let struct_alignment = types.iter().map(alignment).max().unwrap_or(1);
+    write_padding(buffer, struct_alignment);
+
+    let start = buffer.len();
+    let mut offsets = Vec::new();
+    for (i, (field, t)) in fields.iter().zip(types.iter()).enumerate() {
+        write_padding(buffer, alignment(t));
+        write_value::<T>(buffer, field)?;
+        if fixed_size(t).is_none() && i + 1 < fields.len() {
+            offsets.push(buffer.len() - start);
+        }
+    }
+
+    if fields.is_empty() {
+        buffer.push(0);
+    } else if struct_fixed_size(&types.iter().collect::<Vec<_>>()).is_some() {
+        write_padding(buffer, struct_alignment);
+    } else {
+        offsets.reverse();
+        write_framing_offsets(buffer, start, &offsets);
+    }
+    Ok(())
+}
+
+/// Framing offsets are little-endian and not aligned, their size is the smallest of
+/// 1, 2, 4 or 8 bytes able to address the whole container including the offsets.
+fn write_framing_offsets(buffer: &mut Vec<u8>, start: usize, offsets: &[usize]) {
+    let body_size = buffer.len() - start;
+    let offset_size = if body_size + offsets.len() <= 0xff {
+        1
+    } else if body_size + 2 * offsets.len() <= 0xffff {
+        2
+    } else if body_size + 4 * offsets.len() <= 0xffff_ffff {
+        4
+    } else {
+        8
+    };
+
+    for offset in offsets {
+        let mut bytes = [0; 8];
+        LittleEndian::write_u64(&mut bytes, *offset as u64);
+        buffer.extend_from_slice(&bytes[..offset_size]);
+    }
+}
+
+/// Size of the framing offsets of a container of `len` bytes.
+fn framing_offset_size(len: usize) -> usize {
+    if len > 0xffff_ffff {
+        8
+    } else if len > 0xffff {
+        4
+    } else if len > 0xff {
+        2
+    } else if len > 0 {
+        1
+    } else {
+        0
+    }
+}
+
+fn read_framing_offset(bytes: &[u8], position: usize, offset_size: usize) -> Result<usize> {
+    match bytes.get(position..position + offset_size) {
+        Some(offset) => Ok(LittleEndian::read_uint(offset, offset_size) as usize),
+        None => Err(invalid_data("Framing offset out of bounds")),
+    }
+}
+
+fn check_fixed_size(t: &Type, bytes: &[u8], size: usize) -> Result<()> {
+    if bytes.len() != size {
+        let str_err = format!("Invalid size {} for value of type `{}`, expected {}", bytes.len(), t, size);
+        return Err(invalid_data(str_err));
+    }
+    Ok(())
+}
+
+fn read_string(bytes: &[u8]) -> Result<String> {
+    match bytes.split_last() {
+        Some((0, s)) if !s.contains(&0) => String::from_utf8(s.to_vec()).map_err(invalid_data),
+        _ => Err(invalid_data("Strings must be nul-terminated and must not contain nul bytes")),
+    }
+}
+
+fn read_value<T: ByteOrder>(t: &Type, bytes: &[u8], depth: usize) -> Result<Value> {
+    if depth > MAX_VALUE_DEPTH {
+        return Err(invalid_data("Value exceeds maximum nesting depth"));
+    }
+    if let Some(size) = fixed_size(t) {
+        check_fixed_size(t, bytes, size)?;
+    }
+
+    let value = match t {
+        Type::Byte => Value::Byte(bytes[0]),
+        Type::Boolean => match bytes[0] {
+            0 => Value::Boolean(false),
+            1 => Value::Boolean(true),
+            x => return Err(invalid_data(format!("Invalid boolean `{}`", x))),
+        },
+        Type::Int16 => Value::Int16(T::read_i16(bytes)),
+        Type::Uint16 => Value::Uint16(T::read_u16(bytes)),
+        Type::Int32 => Value::Int32(T::read_i32(bytes)),
+        Type::Uint32 => Value::Uint32(T::read_u32(bytes)),
+        Type::UnixFd => Value::UnixFd(UnixFd(T::read_u32(bytes))),
+        Type::Int64 => Value::Int64(T::read_i64(bytes)),
+        Type::Uint64 => Value::Uint64(T::read_u64(bytes)),
+        Type::Double => Value::Double(T::read_f64(bytes)),
+        Type::String => Value::String(read_string(bytes)?),
+        Type::ObjectPath => {
+            let path = read_string(bytes)?;
+            Value::ObjectPath(path.parse().map_err(|err| invalid_data(format!("Invalid object path `{}`: {}", path, err)))?)
+        }
+        Type::Signature => {
+            let signature = read_string(bytes)?;
+            Value::Signature(signature.parse().map_err(|err| invalid_data(format!("Invalid signature `{}`: {}", signature, err)))?)
+        }
+        Type::Array(element) => Value::Array(Signature(element.to_string().into()), read_array::<T>(element, bytes, depth)?),
+        Type::Struct(fields) => Value::Struct(read_struct::<T>(&fields.iter().collect::<Vec<_>>(), bytes, depth)?),
+        Type::DictEntry(key, value) => {
+            let mut entry = read_struct::<T>(&[key, value], bytes, depth)?;
+            let value = entry.pop().unwrap();
+            let key = entry.pop().unwrap();
+            Value::DictEntry(Box::new(key), Box::new(value))
+        }
+        Type::Variant => {
+            let separator = match bytes.iter().rposition(|b| *b == 0) {
+                Some(separator) => separator,
+                None => return Err(invalid_data("Variant is missing its type")),
+            };
+            let signature = std::str::from_utf8(&bytes[separator + 1..]).map_err(invalid_data)?;
+            let mut types = Type::parse_gvariant(signature).map_err(invalid_data)?;
+            if types.len() != 1 {
+                return Err(invalid_data("Variant type must be a single complete type"));
+            }
+            Value::Variant(Box::new(read_value::<T>(&types.remove(0), &bytes[..separator], depth + 1)?))
+        }
+        Type::Maybe(element) => {
+            let signature = Signature(element.to_string().into());
+            if bytes.is_empty() {
+                Value::Maybe(signature, None)
+            } else if fixed_size(element).is_some() {
+                Value::Maybe(signature, Some(Box::new(read_value::<T>(element, bytes, depth + 1)?)))
+            } else {
+                match bytes.split_last() {
+                    Some((0, content)) => Value::Maybe(signature, Some(Box::new(read_value::<T>(element, content, depth + 1)?))),
+                    _ => return Err(invalid_data("Maybe value is missing its trailing nul byte")),
+                }
+            }
+        }
+    };
+    Ok(value)
+}
+
+fn read_array<T: ByteOrder>(element: &Type, bytes: &[u8], depth: usize) -> Result<Vec<Value>> {
+    if bytes.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    if let Some(size) = fixed_size(element) {
+        if !bytes.len().is_multiple_of(size) {
+            return Err(invalid_data("Array size is not a multiple of the element size"));
+        }
+        return bytes.chunks(size).map(|x| read_value::<T>(element, x, depth + 1)).collect();
+    }
+
+    let offset_size = framing_offset_size(bytes.len());
+    let offsets_start = read_framing_offset(bytes, bytes.len() - offset_size, offset_size)?;
+    if offsets_start > bytes.len() || !(bytes.len() - offsets_start).is_multiple_of(offset_size) {
+        return Err(invalid_data("Invalid framing offset"));
+    }
+
+    let mut elements = Vec::new();
+    let mut start = 0;
+    for position in (offsets_start..bytes.len()).step_by(offset_size) {
+        let end = read_framing_offset(bytes, position, offset_size)?;
+        start += padding(start, alignment(element));
+        if start > end || end > offsets_start {
+            return Err(invalid_data("Invalid framing offset"));
+        }
+        elements.push(read_value::<T>(element, &bytes[start..end], depth + 1)?);
+        start = end;
+    }
+    Ok(elements)
+}
+
+fn read_struct<T: ByteOrder>(fields: &[&Type], bytes: &[u8], depth: usize) -> Result<Vec<Value>> {
+    if fields.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let offset_size = framing_offset_size(bytes.len());
+    let offsets = fields[..fields.len() - 1].iter().filter(|field| fixed_size(field).is_none()).count();
+    let frame_end = match bytes.len().checked_sub(offsets * offset_size) {
+        Some(frame_end) => frame_end,
+        None => return Err(invalid_data("Invalid framing offset")),
+    };
+
+    let mut values = Vec::with_capacity(fields.len());
+    let mut start = 0;
+    let mut offset_index = 0;
+    for (i, field) in fields.iter().enumerate() {
+        start += padding(start, alignment(field));
+        let end = match fixed_size(field) {
+            Some(size) => start + size,
+            None if i + 1 == fields.len() => frame_end,
+            None => {
+                offset_index += 1;
+                read_framing_offset(bytes, bytes.len() - offset_index * offset_size, offset_size)?
+            }
+        };
+        if start > end || end > frame_end {
+            return Err(invalid_data("Invalid framing offset"));
+        }
+        values.push(read_value::<T>(field, &bytes[start..end], depth + 1)?);
+        start = end;
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use byteorder::BigEndian;
+
+    fn string(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    fn round_trip(value: Value) -> bool {
+        let bytes = to_bytes::<LittleEndian>(&value).unwrap();
+        value == from_bytes::<LittleEndian>(&value.signature(), &bytes).unwrap()
+    }
+
+    #[test]
+    fn string_and_maybe() {
+        assert_eq!(b"hello world\0".to_vec(), to_bytes::<LittleEndian>(&string("hello world")).unwrap());
+
+        let just = Value::Maybe(Signature("s".into()), Some(Box::new(string("hello world"))));
+        assert_eq!(b"hello world\0\0".to_vec(), to_bytes::<LittleEndian>(&just).unwrap());
+
+        let nothing = Value::Maybe(Signature("s".into()), None);
+        assert!(to_bytes::<LittleEndian>(&nothing).unwrap().is_empty());
+    }
+
+    #[test]
+    fn boolean_array() {
+        let value = Value::Array(Signature("b".into()), vec![Value::Boolean(true), Value::Boolean(false)]);
+        assert_eq!(vec![1, 0], to_bytes::<LittleEndian>(&value).unwrap());
+    }
+
+    #[test]
+    fn struct_with_framing_offset() {
+        let value = Value::Struct(vec![string("foo"), Value::Int32(-1)]);
+        let expected = vec![b'f', b'o', b'o', 0, 0xff, 0xff, 0xff, 0xff, 0x04];
+        assert_eq!(expected, to_bytes::<LittleEndian>(&value).unwrap());
+        assert_eq!(value, from_bytes::<LittleEndian>(&Signature("(si)".into()), &expected).unwrap());
+    }
+
+    #[test]
+    fn string_array() {
+        let value = Value::Array(Signature("s".into()), vec![string("i"), string("can"), string("has"), string("strings?")]);
+        let expected = b"i\0can\0has\0strings?\0\x02\x06\x0a\x13".to_vec();
+        assert_eq!(expected, to_bytes::<LittleEndian>(&value).unwrap());
+        assert_eq!(value, from_bytes::<LittleEndian>(&Signature("as".into()), &expected).unwrap());
+    }
+
+    #[test]
+    fn struct_array() {
+        let value = Value::Array(Signature("(si)".into()), vec![
+            Value::Struct(vec![string("hi"), Value::Int32(-2)]),
+            Value::Struct(vec![string("bye"), Value::Int32(-1)]),
+        ]);
+        let expected = vec![
+            b'h', b'i', 0, 0, 0xfe, 0xff, 0xff, 0xff, 0x03, 0, 0, 0,
+            b'b', b'y', b'e', 0, 0xff, 0xff, 0xff, 0xff, 0x04, 0x09, 0x15,
+        ];
+        assert_eq!(expected, to_bytes::<LittleEndian>(&value).unwrap());
+        assert_eq!(value, from_bytes::<LittleEndian>(&Signature("a(si)".into()), &expected).unwrap());
+    }
+
+    #[test]
+    fn variant() {
+        let value = Value::Variant(Box::new(Value::Uint16(0x0102)));
+        assert_eq!(vec![0x01, 0x02, 0, b'q'], to_bytes::<BigEndian>(&value).unwrap());
+        assert_eq!(value, from_bytes::<BigEndian>(&Signature("v".into()), &[0x01, 0x02, 0, b'q']).unwrap());
+    }
+
+    #[test]
+    fn fixed_size_struct_padding() {
+        let value = Value::Struct(vec![Value::Int64(1), Value::Byte(2)]);
+        assert_eq!(16, to_bytes::<LittleEndian>(&value).unwrap().len());
+        assert_eq!(Some(16), fixed_size(&Type::parse_gvariant("(xy)").unwrap()[0]));
+        assert_eq!(Some(1), fixed_size(&Type::parse_gvariant("()").unwrap()[0]));
+    }
+
+    #[test]
+    fn invalid_object_paths_and_signatures() {
+        let path = Value::ObjectPath(ObjectPath("/a/b".into()));
+        assert_eq!(path, from_bytes::<LittleEndian>(&Signature("o".into()), b"/a/b\0").unwrap());
+        for bytes in &[&b"a/b\0"[..], b"/a/\0", b"/a-b\0", b"\0"] {
+            let err = from_bytes::<LittleEndian>(&Signature("o".into()), bytes).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        }
+
+        let signature = Value::Signature(Signature("a{sv}".into()));
+        assert_eq!(signature, from_bytes::<LittleEndian>(&Signature("g".into()), b"a{sv}\0").unwrap());
+        for bytes in &[&b"a{sv\0"[..], b"a\0", b"z\0", b"m\0"] {
+            let err = from_bytes::<LittleEndian>(&Signature("g".into()), bytes).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        }
+    }
+
+    #[test]
+    fn invalid_framing_offset() {
+        let bytes = b"i\0can\0\x02\x09".to_vec();
+        assert!(from_bytes::<LittleEndian>(&Signature("as".into()), &bytes).is_err());
+    }
+
+    #[test]
+    fn nested_containers_round_trip() {
+        let dict = Value::Array(Signature("{sv}".into()), vec![
+            Value::DictEntry(Box::new(string("a")), Box::new(Value::Variant(Box::new(Value::Double(1.5))))),
+            Value::DictEntry(Box::new(string("b")), Box::new(Value::Variant(Box::new(
+                Value::Maybe(Signature("ay".into()), Some(Box::new(Value::Array(Signature("y".into()), vec![Value::Byte(1)]))))
+            )))),
+        ]);
+        assert!(round_trip(Value::Struct(vec![dict, Value::Maybe(Signature("u".into()), Some(Box::new(Value::Uint32(5))))])));
+    }
+
+    quickcheck! {
+        fn round_trip_strings_and_integers(a: Vec<String>, b: Vec<(u8, i64)>, c: Option<String>) -> bool {
+            let a: Vec<Value> = a.into_iter().filter(|s| !s.contains('\0')).map(Value::String).collect();
+            let b = b.into_iter().map(|(x, y)| Value::Struct(vec![Value::Byte(x), Value::Int64(y)])).collect();
+            let c = c.filter(|s| !s.contains('\0')).map(|s| Box::new(Value::String(s)));
+            round_trip(Value::Struct(vec![
+                Value::Array(Signature("s".into()), a),
+                Value::Array(Signature("(yx)".into()), b),
+                Value::Maybe(Signature("s".into()), c),
+            ]))
+        }
+    }
+}
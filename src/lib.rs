@@ -12,9 +12,17 @@ extern crate lazy_static;
 #[macro_use]
 extern crate quickcheck;
 
-mod address;
-mod message;
-mod names;
-mod reader;
-mod type_system;
-mod writer;
+pub mod address;
+pub mod connection;
+mod crypto;
+pub mod encoding;
+pub mod guid;
+pub mod gvariant;
+mod macros;
+pub mod message;
+pub mod names;
+pub mod protocol;
+pub mod reader;
+pub mod transport;
+pub mod type_system;
+pub mod writer;
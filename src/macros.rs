@@ -0,0 +1,151 @@
+//! Constructors for names, object paths and signatures from literals, validated
+//! at compile time. An invalid literal fails constant evaluation, and a valid one
+//! is borrowed for `'static` instead of being copied.
+
+/// An [`InterfaceName`](crate::names::InterfaceName) validated at compile time.
+///
+/// ```
+/// let _ = dbus_native::interface_name!("org.freedesktop.DBus");
+/// ```
+///
+/// An invalid literal does not compile:
+///
+/// ```compile_fail
+/// let _ = dbus_native::interface_name!("org..DBus");
+/// ```
+#[macro_export]
+macro_rules! interface_name {
+    ($s:expr) => {{
+        const NAME: $crate::names::InterfaceName = $crate::names::InterfaceName::from_static($s);
+        NAME
+    }};
+}
+
+/// A [`MemberName`](crate::names::MemberName) validated at compile time.
+///
+/// ```
+/// let _ = dbus_native::member_name!("Hello");
+/// ```
+///
+/// An invalid literal does not compile:
+///
+/// ```compile_fail
+/// let _ = dbus_native::member_name!("Not.A.Member");
+/// ```
+#[macro_export]
+macro_rules! member_name {
+    ($s:expr) => {{
+        const NAME: $crate::names::MemberName = $crate::names::MemberName::from_static($s);
+        NAME
+    }};
+}
+
+/// An [`ErrorName`](crate::names::ErrorName) validated at compile time.
+///
+/// ```
+/// let _ = dbus_native::error_name!("org.freedesktop.DBus.Error.Failed");
+/// ```
+///
+/// An invalid literal does not compile:
+///
+/// ```compile_fail
+/// let _ = dbus_native::error_name!("Failed");
+/// ```
+#[macro_export]
+macro_rules! error_name {
+    ($s:expr) => {{
+        const NAME: $crate::names::ErrorName = $crate::names::ErrorName::from_static($s);
+        NAME
+    }};
+}
+
+/// A [`BusName`](crate::names::BusName) validated at compile time, unique if it begins with ':'.
+///
+/// ```
+/// let _ = dbus_native::bus_name!(":1.42");
+/// ```
+///
+/// An invalid literal does not compile:
+///
+/// ```compile_fail
+/// let _ = dbus_native::bus_name!("org.1DBus");
+/// ```
+#[macro_export]
+macro_rules! bus_name {
+    ($s:expr) => {{
+        const NAME: $crate::names::BusName = $crate::names::BusName::from_static($s);
+        NAME
+    }};
+}
+
+/// An [`ObjectPath`](crate::type_system::ObjectPath) validated at compile time.
+///
+/// ```
+/// let _ = dbus_native::object_path!("/org/freedesktop/DBus");
+/// ```
+///
+/// An invalid literal does not compile:
+///
+/// ```compile_fail
+/// let _ = dbus_native::object_path!("/org/freedesktop/");
+/// ```
+#[macro_export]
+macro_rules! object_path {
+    ($s:expr) => {{
+        const PATH: $crate::type_system::ObjectPath = $crate::type_system::ObjectPath::from_static($s);
+        PATH
+    }};
+}
+
+/// A [`Signature`](crate::type_system::Signature) validated at compile time.
+///
+/// ```
+/// let _ = dbus_native::signature!("a{sv}");
+/// ```
+///
+/// An invalid literal does not compile:
+///
+/// ```compile_fail
+/// let _ = dbus_native::signature!("a{vs}");
+/// ```
+#[macro_export]
+macro_rules! signature {
+    ($s:expr) => {{
+        const SIGNATURE: $crate::type_system::Signature = $crate::type_system::Signature::from_static($s);
+        SIGNATURE
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::names::{BusName, ErrorName, InterfaceName, MemberName};
+    use crate::type_system::{ObjectPath, Signature};
+    use std::borrow::Cow;
+    use std::str::FromStr;
+
+    #[test]
+    fn literals_match_parsed_values() {
+        assert_eq!(InterfaceName::from_str("org.freedesktop.DBus").unwrap(), interface_name!("org.freedesktop.DBus"));
+        assert_eq!(MemberName::from_str("Hello").unwrap(), member_name!("Hello"));
+        assert_eq!(ErrorName::from_str("org.freedesktop.DBus.Error.Failed").unwrap(), error_name!("org.freedesktop.DBus.Error.Failed"));
+        assert_eq!(BusName::from_str(":1.42").unwrap(), bus_name!(":1.42"));
+        assert_eq!(BusName::from_str("org.freedesktop.DBus").unwrap(), bus_name!("org.freedesktop.DBus"));
+        assert_eq!(ObjectPath::from_str("/org/freedesktop/DBus").unwrap(), object_path!("/org/freedesktop/DBus"));
+        assert_eq!(Signature::from_str("a{sv}").unwrap(), signature!("a{sv}"));
+    }
+
+    #[test]
+    fn literals_are_borrowed() {
+        const PATH: ObjectPath = object_path!("/");
+        assert!(matches!(PATH.0, Cow::Borrowed("/")));
+        assert!(matches!(signature!("").0, Cow::Borrowed("")));
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid member name")]
+    fn invalid_name_panics_at_runtime() {
+        let s: &'static str = Box::leak("Not.A.Member".to_string().into_boxed_str());
+        MemberName::from_static(s);
+    }
+}
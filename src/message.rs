@@ -1,11 +1,15 @@
 //! https://dbus.freedesktop.org/doc/dbus-specification.html#message-protocol-marshaling
-use byteorder::{LittleEndian, BigEndian, ReadBytesExt, ByteOrder, WriteBytesExt};
+use byteorder::{LittleEndian, BigEndian, ByteOrder};
 
-use crate::names::{BusName, InterfaceName, ErrorName, MemberName};
-use crate::writer::{DbusWriter, DbusWrite};
-use crate::reader::{DbusReader, DbusRead};
-use crate::type_system::{ObjectPath, Signature, UnixFd, Serial};
+use crate::names::{BusName, InterfaceName, ErrorName, MemberName, UniqueName};
+use crate::writer::{padding, signature_size, DbusWriter, DbusWrite};
+use crate::reader::{DbusReader, DbusRead, InvalidString, Validation};
+use crate::type_system::{ObjectPath, Signature, Serial, Type, UnixFd, Value};
+use std::fmt;
 use std::io;
+use std::str::FromStr;
+#[cfg(unix)]
+use std::os::unix::io::{AsFd, BorrowedFd, OwnedFd};
 
 #[cfg(test)]
 mod tests {
@@ -17,7 +21,280 @@ mod tests {
         rev
     }
 
+    use super::*;
+    use crate::type_system::UnixFd;
+    use std::str::FromStr;
+
+    fn method_call(header_fields: Vec<HeaderField>, arguments: Vec<Value>) -> Message {
+        let header = Header {
+            endianess_flag: EndianessFlag::LittleEndian,
+            message_type: MessageType::MethodCall,
+            flags: HeaderFlags::empty(),
+            major_protocol_version: MajorProtocolVersion(1),
+            length_message_body: 0,
+            serial: Serial(1),
+            header_fields,
+        };
+        Message::new(header, Body::new(arguments))
+    }
+
+    fn hello() -> Message {
+        method_call(vec![
+            HeaderField::Path(ObjectPath("/org/freedesktop/DBus".into())),
+            HeaderField::Destination(BusName::from_str("org.freedesktop.DBus").unwrap()),
+            HeaderField::Interface(InterfaceName::from_str("org.freedesktop.DBus").unwrap()),
+            HeaderField::Member(MemberName::from_str("Hello").unwrap()),
+        ], vec![])
+    }
+
+    #[test]
+    fn hello_header() {
+        let message = hello();
+        let bytes = message.to_bytes().unwrap();
+        assert_eq!(128, message.encoded_size());
+        assert_eq!(128, bytes.len());
+        assert_eq!(&[b'l', 1, 0, 1, 0, 0, 0, 0, 1, 0, 0, 0, 110, 0, 0, 0], &bytes[..16]);
+        assert_eq!(&[1, 1, b'o', 0, 21, 0, 0, 0, b'/'], &bytes[16..25]);
+        assert_eq!(&[b'H', b'e', b'l', b'l', b'o', 0, 0, 0], &bytes[120..]);
+    }
+
+    #[test]
+    fn body_length() {
+        let message = method_call(vec![], vec![Value::String("hi".to_string()), Value::Uint32(7)]);
+        let bytes = message.to_bytes().unwrap();
+        assert_eq!(28, message.encoded_size());
+        assert_eq!(28, bytes.len());
+        // empty header field array, the header is already a multiple of 8
+        assert_eq!(&[0, 0, 0, 0], &bytes[12..16]);
+        assert_eq!(&[12, 0, 0, 0], &bytes[4..8]);
+        assert_eq!(&[2, 0, 0, 0, b'h', b'i', 0, 0, 7, 0, 0, 0], &bytes[16..]);
+    }
+
+    #[test]
+    fn container_sizes() {
+        let dict = Value::Array(Signature("{sv}".into()), vec![
+            Value::DictEntry(Box::new(Value::String("a".to_string())), Box::new(Value::Variant(Box::new(Value::Int64(-1))))),
+            Value::DictEntry(Box::new(Value::String("b".to_string())), Box::new(Value::Variant(Box::new(Value::Byte(1))))),
+        ]);
+        let arguments = vec![
+            Value::Byte(1),
+            dict,
+            Value::Array(Signature("t".into()), vec![]),
+            Value::Struct(vec![Value::Boolean(true), Value::Signature(Signature("a{sv}".into())), Value::UnixFd(UnixFd(0))]),
+        ];
+        #[allow(unused_mut)]
+        let mut message = method_call(vec![HeaderField::Signature(Body::new(arguments.clone()).signature())], arguments);
+        #[cfg(unix)]
+        message.attach_fd(dev_null());
+        assert_eq!(message.encoded_size(), message.to_bytes().unwrap().len());
+    }
+
+    #[test]
+    fn oversize_message_is_not_written() {
+        let message = method_call(vec![], vec![Value::String("x".repeat(MAX_MESSAGE_SIZE))]);
+        let mut buffer = Vec::new();
+        assert!(message.write(&mut buffer).is_err());
+        assert!(buffer.is_empty());
+    }
+
+    #[cfg(unix)]
+    fn dev_null() -> OwnedFd {
+        OwnedFd::from(std::fs::File::open("/dev/null").unwrap())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn attached_fds_update_header() {
+        let mut message = method_call(vec![], vec![]);
+        message.push_fd_argument(dev_null());
+        message.push_fd_argument(dev_null());
+        assert_eq!(2, message.unix_fds());
+        assert_eq!(vec![Value::UnixFd(UnixFd(0)), Value::UnixFd(UnixFd(1))], message.body.arguments);
+        assert_eq!(2, message.fds().unwrap().len());
+
+        let bytes = message.to_bytes().unwrap();
+        // UnixFds header field: code 9, signature "u", value 2
+        assert_eq!(&[9, 1, b'u', 0, 2, 0, 0, 0], &bytes[16..24]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn fd_argument_out_of_range() {
+        let mut message = method_call(vec![], vec![Value::Struct(vec![Value::UnixFd(UnixFd(1))])]);
+        message.attach_fd(dev_null());
+        assert!(message.to_bytes().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn received_fds() {
+        let mut message = method_call(vec![HeaderField::UnixFds(1)], vec![Value::UnixFd(UnixFd(0))]);
+        assert!(message.set_received_fds(vec![]).is_err());
+        message.set_received_fds(vec![dev_null()]).unwrap();
+
+        assert!(message.take_fd(UnixFd(0)).is_ok());
+        assert!(message.take_fd(UnixFd(0)).is_err());
+        assert!(message.take_fd(UnixFd(1)).is_err());
+        assert!(message.fds().is_err());
+    }
+
+    #[test]
+    fn unix_fds_without_descriptors() {
+        let message = method_call(vec![], vec![Value::UnixFd(UnixFd(0))]);
+        assert!(message.to_bytes().is_err());
+    }
+
+    #[test]
+    fn read_hello() {
+        let bytes = hello().to_bytes().unwrap();
+        assert_eq!(None, Message::frame_length(&bytes[..15]).unwrap());
+        assert_eq!(Some(128), Message::frame_length(&bytes[..16]).unwrap());
+        assert!(Message::from_bytes(&bytes[..120]).is_err());
+
+        let message = Message::from_bytes(&bytes).unwrap();
+        assert_eq!(MessageType::MethodCall, message.header().message_type());
+        assert_eq!(Serial(1), message.header().serial());
+        assert_eq!(4, message.header().header_fields.len());
+        assert!(matches!(message.header().field(HeaderFieldCode::Member), Some(HeaderField::Member(member)) if member.as_str() == "Hello"));
+        assert_eq!(bytes, message.to_bytes().unwrap());
+    }
+
+    #[test]
+    fn read_big_endian_body() {
+        let arguments = vec![
+            Value::String("hi".to_string()),
+            Value::Array(Signature("{sv}".into()), vec![
+                Value::DictEntry(Box::new(Value::String("a".to_string())), Box::new(Value::Variant(Box::new(Value::Int64(-1))))),
+            ]),
+            Value::Uint32(7),
+        ];
+        let header_fields = vec![
+            HeaderField::Path(ObjectPath("/a".into())),
+            HeaderField::Interface(InterfaceName::from_str("org.a").unwrap()),
+            HeaderField::Member(MemberName::from_str("B").unwrap()),
+            HeaderField::Signature(Body::new(arguments.clone()).signature()),
+        ];
+        let header = Header::new(EndianessFlag::BigEndian, MessageType::Signal, HeaderFlags::NO_AUTO_START, Serial(9), header_fields);
+        let bytes = Message::new(header, Body::new(arguments.clone())).to_bytes().unwrap();
+        assert_eq!(&[b'B', 4, 2, 1], &bytes[..4]);
+
+        let message = Message::from_bytes(&bytes).unwrap();
+        assert_eq!(EndianessFlag::BigEndian, message.header().endianess_flag());
+        assert_eq!(HeaderFlags::NO_AUTO_START, message.header().flags());
+        assert_eq!(arguments, message.into_body().arguments);
+    }
+
+    #[test]
+    fn unknown_header_fields_are_skipped() {
+        let mut call = hello();
+        call.header.set_field(HeaderField::Sender(UniqueName::from_str(":1.1").unwrap()));
+        let mut reply = Message::method_return(&call, vec![]);
+        reply.set_serial(Serial(2));
+        let mut bytes = reply.to_bytes().unwrap();
+        // the ReplySerial field is followed by the Destination field
+        assert_eq!(&[5, 1, b'u', 0, 1, 0, 0, 0, 6], &bytes[16..25]);
+        bytes[24] = 10;
+
+        let message = Message::from_bytes(&bytes).unwrap();
+        assert_eq!(Some(Serial(1)), message.header().reply_serial());
+        assert!(message.header().field(HeaderFieldCode::Destination).is_none());
+    }
+
+    #[test]
+    fn invalid_messages() {
+        let mut bytes = method_call(vec![], vec![]).to_bytes().unwrap();
+        assert!(Message::from_bytes(&bytes).is_err());
+
+        bytes = hello().to_bytes().unwrap();
+        bytes[8] = 0;
+        assert!(Message::from_bytes(&bytes).is_err());
+
+        let mut signal = Message::signal(ObjectPath("/".into()), InterfaceName::from_str("org.a").unwrap(), MemberName::from_str("B").unwrap(), vec![Value::Byte(1)]);
+        signal.set_serial(Serial(1));
+        assert!(Message::from_bytes(&signal.to_bytes().unwrap()).is_ok());
+        bytes = signal.to_bytes().unwrap();
+        bytes[4] = 2;
+        bytes.push(0);
+        assert!(Message::from_bytes(&bytes).is_err());
+
+        bytes[4..8].copy_from_slice(&(MAX_MESSAGE_SIZE as u32).to_le_bytes());
+        assert!(Message::frame_length(&bytes).is_err());
+    }
+
+    #[test]
+    fn huge_string_length() {
+        let mut signal = Message::signal(ObjectPath("/".into()), InterfaceName::from_str("org.a").unwrap(), MemberName::from_str("B").unwrap(), vec![Value::String("x".to_string())]);
+        signal.set_serial(Serial(1));
+        let mut bytes = signal.to_bytes().unwrap();
+        let len = bytes.len() - 6;
+        bytes[len..len + 4].copy_from_slice(&0xffff_fff0u32.to_ne_bytes());
+        let err = Message::from_bytes(&bytes).err().unwrap();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+
+        bytes[len..len + 4].copy_from_slice(&0x00ff_ffffu32.to_ne_bytes());
+        assert!(Message::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn lenient_validation() {
+        let path = Value::ObjectPath(ObjectPath("/a-b".into()));
+        let mut signal = Message::signal(ObjectPath("/".into()), InterfaceName::from_str("org.a").unwrap(), MemberName::from_str("B").unwrap(), vec![path.clone()]);
+        signal.set_serial(Serial(1));
+        let bytes = signal.to_bytes().unwrap();
+        assert_eq!(io::ErrorKind::InvalidData, Message::from_bytes(&bytes).err().unwrap().kind());
+        assert!(Message::from_bytes_with(&bytes, Validation::Strict).is_err());
+
+        let (message, invalid) = Message::from_bytes_with(&bytes, Validation::Lenient).unwrap();
+        assert_eq!(vec![path], message.body().arguments);
+        assert_eq!(1, invalid.len());
+        assert_eq!(b"/a-b".to_vec(), invalid[0].bytes);
+        assert_eq!(crate::reader::StringError::InvalidObjectPath(crate::type_system::ObjectPathError::InvalidCharacter('-')), invalid[0].error);
+    }
+
+    #[test]
+    fn sender_is_unique_name() {
+        let mut signal = Message::signal(ObjectPath("/".into()), InterfaceName::from_str("org.a").unwrap(), MemberName::from_str("B").unwrap(), vec![]);
+        signal.header.set_field(HeaderField::Sender(UniqueName::from_str(":1.1").unwrap()));
+        signal.set_serial(Serial(1));
+        let mut bytes = signal.to_bytes().unwrap();
+        assert!(Message::from_bytes(&bytes).is_ok());
+
+        let start = bytes.windows(4).position(|x| x == b":1.1").unwrap();
+        bytes[start..start + 4].copy_from_slice(b"a.bc");
+        assert_eq!(io::ErrorKind::InvalidData, Message::from_bytes(&bytes).err().unwrap().kind());
+    }
+
+    #[test]
+    fn error_reply() {
+        let mut call = hello();
+        call.header.set_field(HeaderField::Sender(UniqueName::from_str(":1.7").unwrap()));
+        let mut error = Message::error(&call, ErrorName::from_str("org.a.Error").unwrap(), "failed");
+        error.set_serial(Serial(2));
+        let message = Message::from_bytes(&error.to_bytes().unwrap()).unwrap();
+        assert_eq!(MessageType::Error, message.header().message_type());
+        assert_eq!(Some(Serial(1)), message.header().reply_serial());
+        assert!(matches!(message.header().field(HeaderFieldCode::Destination), Some(HeaderField::Destination(name)) if name.as_str() == ":1.7"));
+        assert_eq!(vec![Value::String("failed".to_string())], message.body().arguments);
+    }
+
+    quickcheck! {
+        fn encoded_size_matches_written_size(a: u8, b: i16, c: String, d: Vec<u32>, e: Vec<String>, position: u8) -> bool {
+            let value = Value::Struct(vec![
+                Value::Byte(a),
+                Value::Int16(b),
+                Value::Variant(Box::new(Value::String(c))),
+                Value::Array(Signature("u".into()), d.into_iter().map(Value::Uint32).collect()),
+                Value::Array(Signature("s".into()), e.into_iter().map(Value::String).collect()),
+            ]);
+            let position = position as usize;
+            let mut writer = DbusWriter::with_position(Vec::new(), position);
+            writer.write_value::<BigEndian>(&value).unwrap();
+            value.encoded_size(position) == writer.into_inner().len()
+        }
+    }
+
     #[cfg(test)]
+    #[allow(clippy::module_inception)]
     mod tests {
         use crate::message::tests::reverse;
         quickcheck! {
@@ -31,49 +308,399 @@ mod tests {
 /// The maximum length of a message, including header, header alignment padding,
 /// and body is 2 to the 27th power or 134217728 (128 MiB).
 /// Implementations must not send or accept messages exceeding this size.
-const MAX_MESSAGE_SIZE: u32 = 2^27;
+pub const MAX_MESSAGE_SIZE: usize = 1 << 27;
+
+/// Length of the fixed part of the header: endianness, type, flags, version, body length,
+/// serial and the length of the header field array.
+const FIXED_HEADER_SIZE: usize = 16;
 
 /// A message consists of a header and a body. If you think of a message as a package,
 /// the header is the address, and the body contains the package contents.
 /// Both header and body use the D-Bus [type system](https://dbus.freedesktop.org/doc/dbus-specification.html#type-system) and format for serializing data.
-struct Message {
+#[derive(Debug)]
+pub struct Message {
     /// The message delivery system uses the header information to figure out
     /// where to send the message and how to interpret it.
     header: Header,
     /// The body of the message is made up of zero or more arguments,
     /// which are typed values, such as an integer or a byte array.
     body: Body,
+    /// The Unix file descriptors that accompany the message, referenced by index from
+    /// UNIX_FD arguments. Received descriptors are `None` once they have been taken.
+    #[cfg(unix)]
+    fds: Vec<Option<OwnedFd>>,
 }
 
 impl Message {
-    fn write<T>(&self, writer:T) -> Result<(), io::Error>
+    #[inline]
+    pub fn new(header: Header, body: Body) -> Message {
+        Message {
+            header,
+            body,
+            #[cfg(unix)]
+            fds: Vec::new(),
+        }
+    }
+
+    /// A method call of `member` on the object at `path`, with the signature header field
+    /// derived from `arguments`. The serial is assigned by the connection sending it.
+    #[inline]
+    pub fn method_call(destination: Option<BusName>, path: ObjectPath, interface: Option<InterfaceName>, member: MemberName, arguments: Vec<Value>) -> Message {
+        let mut header_fields = vec![HeaderField::Path(path)];
+        header_fields.extend(interface.map(HeaderField::Interface));
+        header_fields.push(HeaderField::Member(member));
+        header_fields.extend(destination.map(HeaderField::Destination));
+        Message::with_fields(MessageType::MethodCall, header_fields, arguments)
+    }
+
+    /// A signal `interface.member` emitted from the object at `path`.
+    #[inline]
+    pub fn signal(path: ObjectPath, interface: InterfaceName, member: MemberName, arguments: Vec<Value>) -> Message {
+        let header_fields = vec![HeaderField::Path(path), HeaderField::Interface(interface), HeaderField::Member(member)];
+        Message::with_fields(MessageType::Signal, header_fields, arguments)
+    }
+
+    /// The reply to `call` returning `arguments`, addressed to its sender.
+    #[inline]
+    pub fn method_return(call: &Message, arguments: Vec<Value>) -> Message {
+        let header_fields = call.header.reply_fields();
+        Message::with_fields(MessageType::MethodReturn, header_fields, arguments)
+    }
+
+    /// The error reply `name` to `call`, with the human readable `text` as its argument.
+    #[inline]
+    pub fn error(call: &Message, name: ErrorName, text: &str) -> Message {
+        let mut header_fields = call.header.reply_fields();
+        header_fields.push(HeaderField::ErrorName(name));
+        Message::with_fields(MessageType::Error, header_fields, vec![Value::String(text.to_string())])
+    }
+
+    fn with_fields(message_type: MessageType, mut header_fields: Vec<HeaderField>, arguments: Vec<Value>) -> Message {
+        let body = Body::new(arguments);
+        if !body.arguments.is_empty() {
+            header_fields.push(HeaderField::Signature(body.signature()));
+        }
+        let endianess_flag = if cfg!(target_endian = "big") { EndianessFlag::BigEndian } else { EndianessFlag::LittleEndian };
+        Message::new(Header::new(endianess_flag, message_type, HeaderFlags::empty(), Serial(0), header_fields), body)
+    }
+
+    #[inline]
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    #[inline]
+    pub fn body(&self) -> &Body {
+        &self.body
+    }
+
+    #[inline]
+    pub fn into_body(self) -> Body {
+        self.body
+    }
+
+    /// Sets the serial, which must be unique among the messages sent on a connection.
+    #[inline]
+    pub fn set_serial(&mut self, serial: Serial) {
+        self.header.serial = serial;
+    }
+
+    /// Sets the flags of the header, e.g. `NO_REPLY_EXPECTED`.
+    #[inline]
+    pub fn set_flags(&mut self, flags: HeaderFlags) {
+        self.header.flags = flags;
+    }
+
+    /// Number of Unix file descriptors that accompany the message.
+    #[inline]
+    pub fn unix_fds(&self) -> u32 {
+        match self.header.field(HeaderFieldCode::UnixFds) {
+            Some(HeaderField::UnixFds(n)) => *n,
+            _ => 0,
+        }
+    }
+
+    /// UNIX_FD arguments must refer to one of the file descriptors accompanying the message.
+    fn check_unix_fds(&self) -> Result<(), io::Error> {
+        let unix_fds = self.unix_fds();
+        #[cfg(unix)]
+        {
+            if self.fds.len() != unix_fds as usize {
+                let str_err = format!("Message has {} file descriptors but header announces {}", self.fds.len(), unix_fds);
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, str_err));
+            }
+        }
+
+        fn check(value: &Value, unix_fds: u32) -> Result<(), io::Error> {
+            match value {
+                Value::UnixFd(UnixFd(i)) if *i >= unix_fds => {
+                    let str_err = format!("UNIX_FD argument `{}` exceeds the {} file descriptors of the message", i, unix_fds);
+                    Err(io::Error::new(io::ErrorKind::InvalidInput, str_err))
+                }
+                Value::Array(_, values) | Value::Struct(values) => values.iter().try_for_each(|x| check(x, unix_fds)),
+                Value::DictEntry(key, value) => check(key, unix_fds).and_then(|_| check(value, unix_fds)),
+                Value::Variant(value) | Value::Maybe(_, Some(value)) => check(value, unix_fds),
+                _ => Ok(()),
+            }
+        }
+        self.body.arguments.iter().try_for_each(|argument| check(argument, unix_fds))
+    }
+
+    /// Exact length in bytes of the marshaled message.
+    #[inline]
+    pub fn encoded_size(&self) -> usize {
+        let header_size = self.header.encoded_size(0);
+        header_size + self.body.encoded_size(header_size)
+    }
+
+    /// Marshals the message, the body length in the header is derived from the body.
+    /// Messages exceeding `MAX_MESSAGE_SIZE` are rejected before anything is written.
+    #[inline]
+    pub fn write<T>(&self, writer:T) -> Result<(), io::Error>
     where T: io::Write
     {
+        self.check_unix_fds()?;
+        let header_size = self.header.encoded_size(0);
+        let body_size = self.body.encoded_size(header_size);
+        if header_size + body_size > MAX_MESSAGE_SIZE {
+            let str_err = format!("Message size `{}` exceeds maximum of {} bytes", header_size + body_size, MAX_MESSAGE_SIZE);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, str_err));
+        }
+
         let mut writer = DbusWriter::new(writer);
         match self.header.endianess_flag {
             EndianessFlag::LittleEndian => {
-                self.header.write::<T, LittleEndian>(&mut writer)?;
+                self.header.write_with_body_length::<T, LittleEndian>(&mut writer, body_size as u32)?;
                 self.body.write::<T, LittleEndian>(&mut writer)?;
             },
             EndianessFlag::BigEndian => {
-                self.header.write::<T, BigEndian>(&mut writer)?;
+                self.header.write_with_body_length::<T, BigEndian>(&mut writer, body_size as u32)?;
                 self.body.write::<T, BigEndian>(&mut writer)?;
             },
         };
         Ok(())
     }
+
+    /// Marshals the message into a buffer allocated with the exact message size.
+    #[inline]
+    pub fn to_bytes(&self) -> Result<Vec<u8>, io::Error> {
+        let mut buffer = Vec::with_capacity(self.encoded_size());
+        self.write(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// The length of the marshaled message beginning with `bytes`, known once the first
+    /// 16 bytes are available. Fails for messages exceeding `MAX_MESSAGE_SIZE`.
+    #[inline]
+    pub fn frame_length(bytes: &[u8]) -> Result<Option<usize>, io::Error> {
+        if bytes.len() < FIXED_HEADER_SIZE {
+            return Ok(None);
+        }
+        let (length_message_body, length_header_fields) = match bytes[0] {
+            b'l' => (LittleEndian::read_u32(&bytes[4..8]), LittleEndian::read_u32(&bytes[12..16])),
+            b'B' => (BigEndian::read_u32(&bytes[4..8]), BigEndian::read_u32(&bytes[12..16])),
+            x => {
+                let str_err = format!("Invalid endianess `{}`", x);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, str_err));
+            }
+        };
+        let header_size = FIXED_HEADER_SIZE as u64 + u64::from(length_header_fields);
+        let length = header_size + padding(header_size as usize, 8) as u64 + u64::from(length_message_body);
+        if length > MAX_MESSAGE_SIZE as u64 {
+            let str_err = format!("Message size `{}` exceeds maximum of {} bytes", length, MAX_MESSAGE_SIZE);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, str_err));
+        }
+        Ok(Some(length as usize))
+    }
+
+    /// Unmarshals a message from exactly its `frame_length` bytes. The file descriptors
+    /// received along with it are attached with `set_received_fds`.
+    #[inline]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Message, io::Error> {
+        Message::from_bytes_with(bytes, Validation::Strict).map(|(message, _)| message)
+    }
+
+    /// Unmarshals a message like [`from_bytes`](Message::from_bytes), checking strings,
+    /// object paths and signatures according to `validation`. Returns the strings that
+    /// failed validation but were accepted along with the message.
+    #[inline]
+    pub fn from_bytes_with(bytes: &[u8], validation: Validation) -> Result<(Message, Vec<InvalidString>), io::Error> {
+        if Message::frame_length(bytes)? != Some(bytes.len()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Message length does not match its header"));
+        }
+        let mut reader = DbusReader::new(bytes);
+        reader.set_validation(validation);
+        let endianess_flag = EndianessFlag::LittleEndian.read::<_, LittleEndian>(&mut reader)?;
+        let message = match endianess_flag {
+            EndianessFlag::LittleEndian => Message::read::<LittleEndian>(&mut reader, endianess_flag)?,
+            EndianessFlag::BigEndian => Message::read::<BigEndian>(&mut reader, endianess_flag)?,
+        };
+        Ok((message, reader.take_invalid_strings()))
+    }
+
+    /// The number of file descriptors the UNIX_FDS header field of the message in exactly
+    /// its `frame_length` bytes announces, whatever its type, so the descriptors sent with
+    /// a message of an unknown type can be discarded along with it.
+    #[inline]
+    pub fn frame_unix_fds(bytes: &[u8]) -> Result<u32, io::Error> {
+        if Message::frame_length(bytes)? != Some(bytes.len()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Message length does not match its header"));
+        }
+        let mut reader = DbusReader::new(bytes);
+        let header_fields = match EndianessFlag::LittleEndian.read::<_, LittleEndian>(&mut reader)? {
+            EndianessFlag::LittleEndian => Message::skip_to_header_fields::<LittleEndian>(&mut reader)?,
+            EndianessFlag::BigEndian => Message::skip_to_header_fields::<BigEndian>(&mut reader)?,
+        };
+        let unix_fds = header_fields.iter().find_map(|field| match field {
+            HeaderField::UnixFds(unix_fds) => Some(*unix_fds),
+            _ => None,
+        });
+        Ok(unix_fds.unwrap_or(0))
+    }
+
+    fn skip_to_header_fields<T: ByteOrder>(reader: &mut DbusReader<&[u8]>) -> Result<Vec<HeaderField>, io::Error> {
+        for _ in 0..3 {
+            reader.read_u8()?;
+        }
+        reader.read_u32::<T>()?;
+        reader.read_u32::<T>()?;
+        Message::read_header_fields::<T>(reader)
+    }
+
+    fn read_header_fields<T: ByteOrder>(reader: &mut DbusReader<&[u8]>) -> Result<Vec<HeaderField>, io::Error> {
+        let end = reader.read_u32::<T>()? as usize + reader.position();
+        let mut header_fields = Vec::new();
+        while reader.position() < end {
+            reader.read_padding(8)?;
+            let code = reader.read_u8()?;
+            let signature = reader.read_signature::<T>()?;
+            header_fields.extend(HeaderField::read::<T>(reader, code, &signature)?);
+        }
+        if reader.position() != end {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Header fields exceed the header field array length"));
+        }
+        Ok(header_fields)
+    }
+
+    fn read<T: ByteOrder>(reader: &mut DbusReader<&[u8]>, endianess_flag: EndianessFlag) -> Result<Message, io::Error> {
+        let message_type = match reader.read_u8()? {
+            1 => MessageType::MethodCall,
+            2 => MessageType::MethodReturn,
+            3 => MessageType::Error,
+            4 => MessageType::Signal,
+            x => {
+                let str_err = format!("Invalid message type `{}`", x);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, str_err));
+            }
+        };
+        let flags = HeaderFlags::from_bits_truncate(reader.read_u8()?);
+        let major_protocol_version = MajorProtocolVersion(reader.read_u8()?);
+        if major_protocol_version.0 != 1 {
+            let str_err = format!("Unsupported major protocol version `{}`", major_protocol_version.0);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, str_err));
+        }
+        let length_message_body = reader.read_u32::<T>()?;
+        let serial = Serial(reader.read_u32::<T>()?);
+        if serial.0 == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Serial must not be zero"));
+        }
+
+        let header_fields = Message::read_header_fields::<T>(reader)?;
+        reader.read_padding(8)?;
+        let header = Header { endianess_flag, message_type, flags, major_protocol_version, length_message_body, serial, header_fields };
+        header.check_required_fields()?;
+
+        let signature = match header.field(HeaderFieldCode::Signature) {
+            Some(HeaderField::Signature(signature)) => signature.0.clone(),
+            _ => "".into(),
+        };
+        let types = Type::parse(&signature).map_err(|err| {
+            let str_err = format!("Invalid body signature `{}`: {}", signature, err);
+            io::Error::new(io::ErrorKind::InvalidData, str_err)
+        })?;
+        let body_end = reader.position() + length_message_body as usize;
+        let mut arguments = Vec::with_capacity(types.len());
+        for t in &types {
+            arguments.push(reader.read_value::<T>(t)?);
+        }
+        if reader.position() != body_end {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Body does not match the body length"));
+        }
+        Ok(Message::new(header, Body::new(arguments)))
+    }
+}
+
+#[cfg(unix)]
+impl Message {
+    /// Appends `fd` to the file descriptors sent along with the message and returns the
+    /// index by which UNIX_FD arguments refer to it. The UnixFds header field is updated.
+    #[inline]
+    pub fn attach_fd(&mut self, fd: OwnedFd) -> UnixFd {
+        self.fds.push(Some(fd));
+        self.header.set_field(HeaderField::UnixFds(self.fds.len() as u32));
+        UnixFd(self.fds.len() as u32 - 1)
+    }
+
+    /// Appends a UNIX_FD argument passing `fd` to the receiver. The UnixFds and
+    /// Signature header fields are updated.
+    #[inline]
+    pub fn push_fd_argument(&mut self, fd: OwnedFd) {
+        let fd = self.attach_fd(fd);
+        self.body.arguments.push(Value::UnixFd(fd));
+        self.header.set_field(HeaderField::Signature(self.body.signature()));
+    }
+
+    /// Takes ownership of the file descriptor a UNIX_FD argument refers to.
+    /// Every file descriptor can only be taken once.
+    #[inline]
+    pub fn take_fd(&mut self, fd: UnixFd) -> Result<OwnedFd, io::Error> {
+        match self.fds.get_mut(fd.0 as usize) {
+            Some(slot) => slot.take().ok_or_else(|| {
+                let str_err = format!("File descriptor `{}` has already been taken", fd.0);
+                io::Error::new(io::ErrorKind::NotFound, str_err)
+            }),
+            None => {
+                let str_err = format!("File descriptor `{}` does not accompany the message", fd.0);
+                Err(io::Error::new(io::ErrorKind::NotFound, str_err))
+            }
+        }
+    }
+
+    /// The file descriptors to send along with the marshaled message, in index order.
+    #[inline]
+    pub fn fds(&self) -> Result<Vec<BorrowedFd<'_>>, io::Error> {
+        self.fds.iter().map(|fd| match fd {
+            Some(fd) => Ok(fd.as_fd()),
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput, "File descriptor has already been taken")),
+        }).collect()
+    }
+
+    /// Attaches the file descriptors received along with the message,
+    /// their number must match the UnixFds header field.
+    #[inline]
+    pub fn set_received_fds(&mut self, fds: Vec<OwnedFd>) -> Result<(), io::Error> {
+        if fds.len() != self.unix_fds() as usize {
+            let str_err = format!("Received {} file descriptors but header announces {}", fds.len(), self.unix_fds());
+            return Err(io::Error::new(io::ErrorKind::InvalidData, str_err));
+        }
+        self.fds = fds.into_iter().map(Some).collect();
+        Ok(())
+    }
 }
 
 /// Endianness flag; ASCII 'l' for little-endian or ASCII 'B' for big-endian.
 /// Both header and body are in this endianness.
 #[repr(u8)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
-enum EndianessFlag {
+pub enum EndianessFlag {
     LittleEndian,
     BigEndian,
 }
 
 impl DbusWrite for EndianessFlag {
+    const ALIGNMENT: usize = 1;
+
+    #[inline]
     fn write<T1, T2>(&self, writer: &mut DbusWriter<T1>) -> Result<(), io::Error>
         where T1: io::Write,
               T2: ByteOrder
@@ -83,9 +710,15 @@ impl DbusWrite for EndianessFlag {
             EndianessFlag::BigEndian => writer.write_u8(b'B'),
         }
     }
+
+    #[inline]
+    fn encoded_size(&self, _offset: usize) -> usize {
+        1
+    }
 }
 
 impl DbusRead<EndianessFlag> for EndianessFlag {
+    #[inline]
     fn read<T1, T2>(&self, reader: &mut DbusReader<T1>) -> Result<EndianessFlag, io::Error>
         where T1: io::Read,
               T2: ByteOrder
@@ -104,7 +737,7 @@ impl DbusRead<EndianessFlag> for EndianessFlag {
 /// Message type. Unknown types must be ignored.
 #[repr(u8)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
-enum MessageType {
+pub enum MessageType {
     /// This is an invalid type.
     Invalid = 0,
     /// Method call. This message type may prompt a reply.
@@ -125,22 +758,30 @@ enum MessageType {
 pub struct MajorProtocolVersion(pub u8);
 
 impl DbusWrite for MajorProtocolVersion {
+    const ALIGNMENT: usize = 1;
+
+    #[inline]
     fn write<T1, T2>(&self, writer: &mut DbusWriter<T1>) -> Result<(), io::Error>
         where T1: io::Write,
               T2: ByteOrder
     {
         writer.write_u8(self.0)
     }
+
+    #[inline]
+    fn encoded_size(&self, _offset: usize) -> usize {
+        1
+    }
 }
 
 bitflags! {
-    struct HeaderFlags: u8 {
+    pub struct HeaderFlags: u8 {
         /// This message does not expect method return replies or error replies,
         /// even if it is of a type that can have a reply; the reply should be omitted.
         const NO_REPLY_EXPECTED = 0x1;
 
         /// The bus must not launch an owner for the destination name in response to this message.
-        const NO_AUTO_START = 0x1;
+        const NO_AUTO_START = 0x2;
 
         /// This flag may be set on a method call message to inform the receiving side that the caller
         /// is prepared to wait for interactive authorization, which might take a considerable time to complete.
@@ -154,7 +795,7 @@ bitflags! {
 /// and zero or more of any optional header fields.
 #[repr(u8)]
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
-enum HeaderFieldCode {
+pub enum HeaderFieldCode {
     /// Not a valid field name (error if it appears in a message)
     Invalid = 0,
     /// The object to send a call to, or the object a signal is emitted from.
@@ -194,7 +835,8 @@ enum HeaderFieldCode {
 /// and zero or more of any optional header fields.
 ///
 #[repr(u8)]
-enum HeaderField {
+#[derive(Debug)]
+pub enum HeaderField {
     /// Not a valid field name (error if it appears in a message)
     Invalid,
     /// The object to send a call to, or the object a signal is emitted from.
@@ -215,11 +857,11 @@ enum HeaderField {
     ReplySerial(Serial),
     /// The name of the connection this message is intended for.
     /// Optional.
-    Destination(String),
+    Destination(BusName),
     /// Unique name of the sending connection. This field is usually only meaningful
     /// in combination with the message bus, but other servers may define their own meanings for it.
     /// Optional.
-    Sender(String),
+    Sender(UniqueName),
     /// The signature of the message body. If omitted, it is assumed to be the empty signature "".
     /// Optional.
     Signature(Signature),
@@ -228,25 +870,124 @@ enum HeaderField {
     UnixFds(u32),
 }
 
+impl HeaderField {
+    /// The field code identifying this header field.
+    #[inline]
+    pub fn code(&self) -> HeaderFieldCode {
+        match self {
+            HeaderField::Invalid => HeaderFieldCode::Invalid,
+            HeaderField::Path(_) => HeaderFieldCode::Path,
+            HeaderField::Interface(_) => HeaderFieldCode::Interface,
+            HeaderField::Member(_) => HeaderFieldCode::Member,
+            HeaderField::ErrorName(_) => HeaderFieldCode::ErrorName,
+            HeaderField::ReplySerial(_) => HeaderFieldCode::ReplySerial,
+            HeaderField::Destination(_) => HeaderFieldCode::Destination,
+            HeaderField::Sender(_) => HeaderFieldCode::Sender,
+            HeaderField::Signature(_) => HeaderFieldCode::Signature,
+            HeaderField::UnixFds(_) => HeaderFieldCode::UnixFds,
+        }
+    }
+
+    /// Reads the value of the field with `code`, which has the given `signature`.
+    /// Fields with unknown codes are skipped and read as none.
+    fn read<T: ByteOrder>(reader: &mut DbusReader<&[u8]>, code: u8, signature: &Signature) -> Result<Option<HeaderField>, io::Error> {
+        let expected = match code {
+            0 => return Err(io::Error::new(io::ErrorKind::InvalidData, "HeaderField::Invalid in header")),
+            1 => "o",
+            2 | 3 | 4 | 6 | 7 => "s",
+            5 | 9 => "u",
+            8 => "g",
+            _ => {
+                let t = Type::parse_single(&signature.0).map_err(|err| {
+                    let str_err = format!("Invalid signature `{}` of header field {}: {}", signature.0, code, err);
+                    io::Error::new(io::ErrorKind::InvalidData, str_err)
+                })?;
+                reader.read_value::<T>(&t)?;
+                return Ok(None);
+            }
+        };
+        if signature.0 != expected {
+            let str_err = format!("Header field {} has signature `{}` instead of `{}`", code, signature.0, expected);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, str_err));
+        }
+        let field = match code {
+            1 => HeaderField::Path(reader.read_object_path::<T>()?),
+            2 => HeaderField::Interface(parse_name(reader.read_string::<T>()?)?),
+            3 => HeaderField::Member(parse_name(reader.read_string::<T>()?)?),
+            4 => HeaderField::ErrorName(parse_name(reader.read_string::<T>()?)?),
+            5 => HeaderField::ReplySerial(Serial(reader.read_u32::<T>()?)),
+            6 => HeaderField::Destination(parse_name(reader.read_string::<T>()?)?),
+            7 => HeaderField::Sender(parse_name(reader.read_string::<T>()?)?),
+            8 => HeaderField::Signature(reader.read_signature::<T>()?),
+            _ => HeaderField::UnixFds(reader.read_u32::<T>()?),
+        };
+        Ok(Some(field))
+    }
+
+    /// Signature of the variant holding the field value.
+    fn value_signature(&self) -> &'static str {
+        match self {
+            HeaderField::Invalid => "",
+            HeaderField::Path(_) => "o",
+            HeaderField::ReplySerial(_) | HeaderField::UnixFds(_) => "u",
+            HeaderField::Signature(_) => "g",
+            HeaderField::Interface(_)
+            | HeaderField::Member(_)
+            | HeaderField::ErrorName(_)
+            | HeaderField::Destination(_)
+            | HeaderField::Sender(_) => "s",
+        }
+    }
+}
+
+/// Each header field is marshaled as a STRUCT of the field code and a VARIANT holding the value.
 impl DbusWrite for HeaderField {
+    const ALIGNMENT: usize = 8;
+
+    #[inline]
     fn write<T1, T2>(&self, writer: &mut DbusWriter<T1>) -> Result<(), io::Error>
         where T1: io::Write,
               T2: ByteOrder
     {
+        if let HeaderField::Invalid = self {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "HeaderField::Invalid can not be marshaled!"));
+        }
+
+        writer.write_padding(8)?;
+        writer.write_u8(self.code() as u8)?;
+        writer.write_signature_str(self.value_signature())?;
         match self {
-            HeaderField::Invalid => return Err(io::Error::new(io::ErrorKind::InvalidInput, "HeaderField::Invalid can not be marshaled!")),
+            HeaderField::Invalid => unreachable!(),
             HeaderField::Path(object_path) => object_path.write::<_, T2>(writer)?,
             HeaderField::Interface(interface_name) => interface_name.write::<_, T2>(writer)?,
             HeaderField::Member(member_name) => member_name.write::<_, T2>(writer)?,
             HeaderField::ErrorName(error_name) => error_name.write::<_, T2>(writer)?,
             HeaderField::ReplySerial(serial) => serial.write::<_, T2>(writer)?,
-            HeaderField::Destination(destination) => writer.write_string::<T2>(destination)?,
-            HeaderField::Sender(sender) => writer.write_string::<T2>(sender)?,
+            HeaderField::Destination(destination) => destination.write::<_, T2>(writer)?,
+            HeaderField::Sender(sender) => sender.write::<_, T2>(writer)?,
             HeaderField::Signature(signature) => signature.write::<_, T2>(writer)?,
             HeaderField::UnixFds(fd) => writer.write_u32::<T2>(*fd)?,
         };
         Ok(())
     }
+
+    #[inline]
+    fn encoded_size(&self, offset: usize) -> usize {
+        let start = offset + padding(offset, 8) + 1 + signature_size(self.value_signature().len());
+        let value_size = match self {
+            HeaderField::Invalid => 0,
+            HeaderField::Path(object_path) => object_path.encoded_size(start),
+            HeaderField::Interface(interface_name) => interface_name.encoded_size(start),
+            HeaderField::Member(member_name) => member_name.encoded_size(start),
+            HeaderField::ErrorName(error_name) => error_name.encoded_size(start),
+            HeaderField::ReplySerial(serial) => serial.encoded_size(start),
+            HeaderField::Destination(destination) => destination.encoded_size(start),
+            HeaderField::Sender(sender) => sender.encoded_size(start),
+            HeaderField::Signature(signature) => signature.encoded_size(start),
+            HeaderField::UnixFds(_) => padding(start, 4) + 4,
+        };
+        start + value_size - offset
+    }
 }
 
 
@@ -255,7 +996,8 @@ impl DbusWrite for HeaderField {
 /// If the header does not naturally end on an 8-byte boundary up to 7 bytes of
 /// nul-initialized alignment padding must be added.
 /// https://dbus.freedesktop.org/doc/dbus-specification.html#message-protocol-header-fields
-struct Header {
+#[derive(Debug)]
+pub struct Header {
     endianess_flag: EndianessFlag,
     /// Message type. Unknown types must be ignored.
     message_type: MessageType,
@@ -273,39 +1015,184 @@ struct Header {
     serial: Serial,
     /// An array of zero or more header fields where the byte is the field code,
     /// and the variant is the field value. The message type determines which fields are required.
-    header_fields: Vec<(HeaderFieldCode, HeaderField)>,
+    header_fields: Vec<HeaderField>,
 }
 
-impl DbusWrite for Header {
-    fn write<T1, T2>(&self, writer: &mut DbusWriter<T1>) -> Result<(), io::Error>
+impl Header {
+    /// A header of major protocol version 1, the body length is derived when the message is written.
+    #[inline]
+    pub fn new(endianess_flag: EndianessFlag, message_type: MessageType, flags: HeaderFlags, serial: Serial, header_fields: Vec<HeaderField>) -> Header {
+        Header {
+            endianess_flag,
+            message_type,
+            flags,
+            major_protocol_version: MajorProtocolVersion(1),
+            length_message_body: 0,
+            serial,
+            header_fields,
+        }
+    }
+
+    #[inline]
+    pub fn endianess_flag(&self) -> EndianessFlag {
+        self.endianess_flag
+    }
+
+    #[inline]
+    pub fn message_type(&self) -> MessageType {
+        self.message_type
+    }
+
+    #[inline]
+    pub fn flags(&self) -> HeaderFlags {
+        self.flags
+    }
+
+    #[inline]
+    pub fn serial(&self) -> Serial {
+        self.serial
+    }
+
+    /// The serial of the call this message replies to, for method returns and errors.
+    #[inline]
+    pub fn reply_serial(&self) -> Option<Serial> {
+        match self.field(HeaderFieldCode::ReplySerial) {
+            Some(HeaderField::ReplySerial(serial)) => Some(*serial),
+            _ => None,
+        }
+    }
+
+    /// The header field with the given code, if present.
+    #[inline]
+    pub fn field(&self, code: HeaderFieldCode) -> Option<&HeaderField> {
+        self.header_fields.iter().find(|field| field.code() == code)
+    }
+
+    /// Replaces the header field with the same code or appends it.
+    #[inline]
+    pub fn set_field(&mut self, field: HeaderField) {
+        match self.header_fields.iter_mut().find(|x| x.code() == field.code()) {
+            Some(x) => *x = field,
+            None => self.header_fields.push(field),
+        }
+    }
+
+    /// The fields of a reply to the message with this header.
+    fn reply_fields(&self) -> Vec<HeaderField> {
+        let mut header_fields = vec![HeaderField::ReplySerial(self.serial)];
+        if let Some(HeaderField::Sender(sender)) = self.field(HeaderFieldCode::Sender) {
+            header_fields.push(HeaderField::Destination(BusName::from(sender.clone())));
+        }
+        header_fields
+    }
+
+    /// Fails if a header field required for the message type is missing.
+    fn check_required_fields(&self) -> Result<(), io::Error> {
+        let required: &[HeaderFieldCode] = match self.message_type {
+            MessageType::Invalid => &[],
+            MessageType::MethodCall => &[HeaderFieldCode::Path, HeaderFieldCode::Member],
+            MessageType::MethodReturn => &[HeaderFieldCode::ReplySerial],
+            MessageType::Error => &[HeaderFieldCode::ErrorName, HeaderFieldCode::ReplySerial],
+            MessageType::Signal => &[HeaderFieldCode::Path, HeaderFieldCode::Interface, HeaderFieldCode::Member],
+        };
+        match required.iter().find(|code| self.field(**code).is_none()) {
+            Some(code) => {
+                let str_err = format!("{:?} message lacks the required {:?} header field", self.message_type, code);
+                Err(io::Error::new(io::ErrorKind::InvalidData, str_err))
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn write_with_body_length<T1, T2>(&self, writer: &mut DbusWriter<T1>, length_message_body: u32) -> Result<(), io::Error>
         where T1: io::Write,
               T2: ByteOrder
     {
-         writer.write_u8(self.endianess_flag as u8)?;
+         self.endianess_flag.write::<T1, T2>(writer)?;
          writer.write_u8(self.message_type as u8)?;
          writer.write_u8(self.flags.bits())?;
-         writer.write_u8(self.major_protocol_version.0)?;
+         self.major_protocol_version.write::<T1, T2>(writer)?;
+
+         writer.write_u32::<T2>(length_message_body)?;
+         self.serial.write::<T1, T2>(writer)?;
+
+         writer.write_array::<T2, HeaderField>(&self.header_fields)?;
+         writer.write_padding(8)
+    }
+}
+
+impl DbusWrite for Header {
+    const ALIGNMENT: usize = 8;
 
-         writer.write_u32::<T2>(self.length_message_body)?;
-         writer.write_u32::<T2>(self.serial.0)?;
+    #[inline]
+    fn write<T1, T2>(&self, writer: &mut DbusWriter<T1>) -> Result<(), io::Error>
+        where T1: io::Write,
+              T2: ByteOrder
+    {
+        self.write_with_body_length::<T1, T2>(writer, self.length_message_body)
+    }
 
-         for (ref code, ref field) in self.header_fields.iter().by_ref() {
-              writer.write_u8(code.clone() as u8)?;
-              field.write::<T1, T2>(writer)?;
-         }
-         Ok(())
+    /// The fixed part of 16 bytes, the header field array and the padding to an 8-boundary.
+    #[inline]
+    fn encoded_size(&self, offset: usize) -> usize {
+        let start = offset + padding(offset, 8);
+        let fields_start = start + 16;
+        let end = fields_start + HeaderField::elements_size(&self.header_fields, fields_start);
+        end + padding(end, 8) - offset
     }
 }
 
 
-struct Body {
+/// The body of the message is made up of zero or more arguments,
+/// which are typed values, such as an integer or a byte array.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Body {
+    pub arguments: Vec<Value>,
+}
+
+impl Body {
+    #[inline]
+    pub fn new(arguments: Vec<Value>) -> Body {
+        Body {
+            arguments
+        }
+    }
 
+    /// The signature of the body are the signatures of all arguments concatenated.
+    #[inline]
+    pub fn signature(&self) -> Signature {
+        let signature: String = self.arguments.iter().map(|argument| argument.signature().0).collect();
+        Signature(signature.into())
+    }
 }
 
 impl DbusWrite for Body {
+    const ALIGNMENT: usize = 8;
+
+    #[inline]
     fn write<T1, T2>(&self, writer: &mut DbusWriter<T1>) -> Result<(), io::Error>
         where T1: io::Write,
-              T2: ByteOrder {
-                  unimplemented!();
+              T2: ByteOrder
+    {
+        for argument in self.arguments.iter() {
+            writer.write_value::<T2>(argument)?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn encoded_size(&self, offset: usize) -> usize {
+        Value::sequence_end(&self.arguments, offset) - offset
     }
-}
\ No newline at end of file
+}
+
+/// Parses a name read from a header field.
+fn parse_name<N>(s: String) -> Result<N, io::Error>
+    where N: FromStr,
+          N::Err: fmt::Debug
+{
+    N::from_str(&s).map_err(|err| {
+        let str_err = format!("Invalid name `{}` in header field: {:?}", s, err);
+        io::Error::new(io::ErrorKind::InvalidData, str_err)
+    })
+}
@@ -1,6 +1,12 @@
-use crate::writer::{DbusWrite, DbusWriter};
-use byteorder::{BigEndian, ByteOrder, ReadBytesExt, WriteBytesExt};
+use crate::type_system::char_at;
+use crate::writer::{string_size, DbusWrite, DbusWriter};
+use byteorder::ByteOrder;
+use std::borrow::{Borrow, Cow};
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::io;
+use std::ops::Deref;
 use std::str::FromStr;
 
 #[cfg(test)]
@@ -41,9 +47,24 @@ mod tests {
             InterfaceName::from_str("Invalid.C|har")
         );
 
+        assert_eq!(
+            Err(InterfaceNameError::ElementMustNotBeginWithDigit),
+            InterfaceName::from_str("9Digit.First")
+        );
+
+        assert_eq!(
+            Err(InterfaceNameError::ElementsMustContainChars),
+            InterfaceName::from_str("Trailing.Period.")
+        );
+
+        assert_eq!(
+            Err(InterfaceNameError::InvalidCharacter('ä')),
+            InterfaceName::from_str("Non.Asciä")
+        );
+
         let valid_string = "Valid.Interface";
         assert_eq!(
-            Ok(InterfaceName(valid_string.to_string())),
+            Ok(InterfaceName(valid_string.into())),
             InterfaceName::from_str(valid_string)
         );
     }
@@ -76,13 +97,100 @@ mod tests {
             BusName::from_str("Invalid.C|har")
         );
 
-        let valid_string = "Valid.BusName";
         assert_eq!(
-            Ok(BusName(valid_string.to_string())),
+            Err(BusNameError::ElementMustNotBeginWithDigit),
+            BusName::from_str("Must.Not.Start.With.9Digit")
+        );
+
+        assert_eq!(
+            Err(BusNameError::ElementsMustContainChars),
+            BusName::from_str("Trailing.Period.")
+        );
+
+        let valid_string = "Valid.Bus_Name-1";
+        assert_eq!(
+            Ok(BusName::WellKnown(WellKnownName(valid_string.into()))),
             BusName::from_str(valid_string)
         );
     }
 
+    #[test]
+    fn unique_name() {
+        assert_eq!(
+            Ok(BusName::Unique(UniqueName(":1.42".into()))),
+            BusName::from_str(":1.42")
+        );
+
+        assert_eq!(
+            Err(BusNameError::UniqueNameMustBeginWithColon),
+            UniqueName::from_str("org.freedesktop.DBus")
+        );
+
+        assert_eq!(
+            Err(BusNameError::MustContainPeriod),
+            UniqueName::from_str(":1")
+        );
+
+        assert_eq!(
+            Err(BusNameError::MustNotBeginWithPeriod),
+            UniqueName::from_str(":.1")
+        );
+
+        assert_eq!(
+            Err(BusNameError::InvalidCharacter(':')),
+            WellKnownName::from_str(":1.42")
+        );
+    }
+
+    #[test]
+    fn borrowed_names() {
+        use std::collections::{BTreeSet, HashMap};
+
+        let mut owners = HashMap::new();
+        owners.insert(BusName::from_str("org.freedesktop.DBus").unwrap(), 1);
+        owners.insert(BusName::from_str(":1.42").unwrap(), 2);
+        assert_eq!(Some(&1), owners.get(BusNameRef::new("org.freedesktop.DBus").unwrap()));
+        let unique: &BusNameRef = UniqueNameRef::new(":1.42").unwrap().into();
+        assert!(unique.is_unique());
+        assert_eq!(Some(&2), owners.get(unique));
+
+        let interfaces: BTreeSet<InterfaceName> = ["org.b.B", "org.a.A"].iter().map(|s| InterfaceName::from_str(s).unwrap()).collect();
+        assert!(interfaces.contains(InterfaceNameRef::new("org.a.A").unwrap()));
+        assert_eq!(Some("org.a.A"), interfaces.iter().next().map(|name| name.as_str()));
+
+        let member = MemberNameRef::new("Hello").unwrap();
+        let owned: Cow<MemberNameRef> = Cow::Borrowed(member);
+        assert_eq!(MemberName::from_str("Hello").unwrap(), owned.into_owned());
+        assert_eq!(Err(ErrorNameError::MustContainPeriod), ErrorNameRef::new("Failed").map(|_| ()));
+        assert_eq!(BusName::from_str(":1.7").unwrap(), *BusNameRef::new(":1.7").unwrap());
+        assert_eq!("org.a.A", InterfaceNameRef::new("org.a.A").unwrap().to_string());
+    }
+
+    #[test]
+    fn escaped_elements() {
+        assert_eq!("_", escape_element(b""));
+        assert_eq!("_31abc_2d123", escape_element(b"1abc-123"));
+        assert_eq!("sda_5f1_2fx_c3_a4", escape_element("sda_1/xä".as_bytes()));
+        assert_eq!(Ok(b"1abc-123".to_vec()), unescape_element("_31abc_2D123"));
+        assert_eq!(Ok(Vec::new()), unescape_element("_"));
+        assert_eq!(Err(UnescapeError::InvalidEscape(3)), unescape_element("abc_4"));
+        assert_eq!(Err(UnescapeError::InvalidEscape(0)), unescape_element("_xy"));
+        assert_eq!(Err(UnescapeError::InvalidCharacter('-')), unescape_element("a-b"));
+
+        let element = escape_element(b"0.serial:42");
+        assert!(WellKnownName::from_str(&format!("org.example.{}", element)).is_ok());
+        assert!(InterfaceName::from_str(&format!("org.example.{}", element)).is_ok());
+    }
+
+    quickcheck! {
+        fn escape_element_round_trip(bytes: Vec<u8>) -> bool {
+            let element = escape_element(&bytes);
+            let leading_digit = element.starts_with(|c: char| c.is_ascii_digit());
+            let valid = element.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_');
+            !leading_digit && valid && unescape_element(&element) == Ok(bytes)
+        }
+    }
+
     #[test]
     fn member_name() {
         let large_string = String::from_utf8(vec![b'X'; 256]).unwrap();
@@ -108,7 +216,7 @@ mod tests {
 
         let valid_string = "ValidMemberName";
         assert_eq!(
-            Ok(MemberName(valid_string.to_string())),
+            Ok(MemberName(valid_string.into())),
             MemberName::from_str(valid_string)
         );
     }
@@ -148,7 +256,7 @@ mod tests {
 
         let valid_string = "Valid.Error.Name";
         assert_eq!(
-            Ok(ErrorName(valid_string.to_string())),
+            Ok(ErrorName(valid_string.into())),
             ErrorName::from_str(valid_string)
         );
     }
@@ -159,17 +267,191 @@ pub const MAX_NAME_LENGHT: usize = 255;
 
 lazy_static! {
     /// The special message bus name org.freedesktop.DBus responds to a number of additional messages at the object path /org/freedesktop/DBus.
-    static ref ORG_FREEDESKTOP_DBUS: BusName = BusName("org.freedesktop.DBus".to_string());
+    static ref ORG_FREEDESKTOP_DBUS: BusName = BusName::WellKnown(WellKnownName("org.freedesktop.DBus".into()));
+}
+
+/// Implements the borrowed counterpart `$borrowed` of the name type `$owned`, a newtype
+/// over `str` that relates to `$owned` like `str` to `String`, and the conversions between them.
+macro_rules! impl_name_ref {
+    ($owned:ident, $borrowed:ident, $error:ident, $validate:ident) => {
+        #[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        #[repr(transparent)]
+        pub struct $borrowed(str);
+
+        impl $borrowed {
+            /// Validates `s` and borrows it without copying.
+            #[inline]
+            pub fn new(s: &str) -> Result<&$borrowed, $error> {
+                $validate(s)?;
+                Ok($borrowed::new_unchecked(s))
+            }
+
+            fn new_unchecked(s: &str) -> &$borrowed {
+                // SAFETY: `$borrowed` is a `repr(transparent)` wrapper of `str`.
+                unsafe { &*(s as *const str as *const $borrowed) }
+            }
+
+            #[inline]
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl $owned {
+            #[inline]
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl Deref for $owned {
+            type Target = $borrowed;
+
+            #[inline]
+            fn deref(&self) -> &$borrowed {
+                $borrowed::new_unchecked(&self.0)
+            }
+        }
+
+        impl Borrow<$borrowed> for $owned {
+            #[inline]
+            fn borrow(&self) -> &$borrowed {
+                self
+            }
+        }
+
+        impl AsRef<$borrowed> for $owned {
+            #[inline]
+            fn as_ref(&self) -> &$borrowed {
+                self
+            }
+        }
+
+        impl AsRef<str> for $owned {
+            #[inline]
+            fn as_ref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl AsRef<str> for $borrowed {
+            #[inline]
+            fn as_ref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl ToOwned for $borrowed {
+            type Owned = $owned;
+
+            #[inline]
+            fn to_owned(&self) -> $owned {
+                $owned(Cow::Owned(self.0.to_string()))
+            }
+        }
+
+        impl From<&$borrowed> for $owned {
+            #[inline]
+            fn from(name: &$borrowed) -> $owned {
+                name.to_owned()
+            }
+        }
+
+        impl PartialEq<$borrowed> for $owned {
+            #[inline]
+            fn eq(&self, other: &$borrowed) -> bool {
+                self.as_str() == other.as_str()
+            }
+        }
+
+        impl PartialEq<$owned> for $borrowed {
+            #[inline]
+            fn eq(&self, other: &$owned) -> bool {
+                self.as_str() == other.as_str()
+            }
+        }
+
+        impl fmt::Display for $owned {
+            #[inline]
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+
+        impl fmt::Display for $borrowed {
+            #[inline]
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+    };
+}
+
+/// Violations of the rules shared by names composed of period separated elements.
+#[derive(Copy, Clone)]
+enum ElementsError {
+    ExceedsMaxSize,
+    ElementsMustContainChars,
+    InvalidCharacter(char),
+    MustContainPeriod,
+    MustNotBeginWithPeriod,
+    ElementMustNotBeginWithDigit,
+}
+
+/// Checks that `s[start..]` is composed of 2 or more non-empty elements separated by
+/// a period, made of the ASCII characters "[A-Z][a-z][0-9]_" and, if `hyphen`, "-".
+/// Elements may only begin with a digit if `leading_digit`.
+const fn validate_elements(s: &str, start: usize, hyphen: bool, leading_digit: bool) -> Result<(), ElementsError> {
+    let bytes = s.as_bytes();
+    if bytes.len() > MAX_NAME_LENGHT {
+        return Err(ElementsError::ExceedsMaxSize);
+    }
+    if start < bytes.len() && bytes[start] == b'.' {
+        return Err(ElementsError::MustNotBeginWithPeriod);
+    }
+
+    let mut periods = 0;
+    let mut element_start = true;
+    let mut i = start;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'.' => {
+                if element_start {
+                    return Err(ElementsError::ElementsMustContainChars);
+                }
+                periods += 1;
+                element_start = true;
+            }
+            b'0'..=b'9' if element_start && !leading_digit => {
+                return Err(ElementsError::ElementMustNotBeginWithDigit);
+            }
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'_' => element_start = false,
+            b'-' if hyphen => element_start = false,
+            _ => return Err(ElementsError::InvalidCharacter(char_at(bytes, i))),
+        }
+        i += 1;
+    }
+
+    if periods == 0 {
+        return Err(ElementsError::MustContainPeriod);
+    }
+    if element_start {
+        return Err(ElementsError::ElementsMustContainChars);
+    }
+    Ok(())
 }
 
 /// Connections have one or more bus names associated with them.
 /// A connection has exactly one bus name that is a unique connection name.
 /// The unique connection name remains with the connection for its entire lifetime.
 /// A bus name is of type STRING, meaning that it must be valid UTF-8.
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub struct InterfaceName(String);
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct InterfaceName(Cow<'static, str>);
 
 impl DbusWrite for InterfaceName {
+    const ALIGNMENT: usize = 4;
+
+    #[inline]
     fn write<T1, T2>(&self, writer: &mut DbusWriter<T1>) -> Result<(), io::Error>
     where
         T1: io::Write,
@@ -177,6 +459,11 @@ impl DbusWrite for InterfaceName {
     {
         writer.write_string::<T2>(&self.0)
     }
+
+    #[inline]
+    fn encoded_size(&self, offset: usize) -> usize {
+        string_size(offset, self.0.len())
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -188,83 +475,234 @@ pub enum InterfaceNameError {
     /// All elements must contain at least one character.
     ElementsMustContainChars,
 
-    /// Each element must only contain the ASCII characters "[A-Z][a-z][0-9]_-", with "-" discouraged in new bus names.
-    /// Only elements that are part of a unique connection name may begin with a digit, elements in other bus names must not begin with a digit.
+    /// Each element must only contain the ASCII characters "[A-Z][a-z][0-9]_".
     InvalidCharacter(char),
 
-    /// Bus names must contain at least one '.' (period) character (and thus at least two elements).
+    /// Interface names must contain at least one '.' (period) character (and thus at least two elements).
     MustContainPeriod,
 
-    /// Bus names must not begin with a '.' (period) character.
+    /// Interface names must not begin with a '.' (period) character.
     MustNotBeginWithPeriod,
 
     /// Elements must not begin with digit.
     ElementMustNotBeginWithDigit,
 }
 
-fn is_valid_interface_name_char(c: char) -> bool {
-    match c {
-        'A'..='Z' => true,
-        'a'..='z' => true,
-        '0'..='9' => true,
-        '-' => true,
-        '.' => true,
-        _ => false,
+const fn validate_interface_name(s: &str) -> Result<(), InterfaceNameError> {
+    match validate_elements(s, 0, false, false) {
+        Ok(()) => Ok(()),
+        Err(ElementsError::ExceedsMaxSize) => Err(InterfaceNameError::ExceedsMaxSize),
+        Err(ElementsError::ElementsMustContainChars) => Err(InterfaceNameError::ElementsMustContainChars),
+        Err(ElementsError::InvalidCharacter(c)) => Err(InterfaceNameError::InvalidCharacter(c)),
+        Err(ElementsError::MustContainPeriod) => Err(InterfaceNameError::MustContainPeriod),
+        Err(ElementsError::MustNotBeginWithPeriod) => Err(InterfaceNameError::MustNotBeginWithPeriod),
+        Err(ElementsError::ElementMustNotBeginWithDigit) => Err(InterfaceNameError::ElementMustNotBeginWithDigit),
+    }
+}
+
+impl InterfaceName {
+    /// Borrows `s` without copying it, for use in constant expressions such as [`interface_name!`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `s` is not a valid interface name, which fails compilation in a constant.
+    #[inline]
+    pub const fn from_static(s: &'static str) -> InterfaceName {
+        match validate_interface_name(s) {
+            Ok(()) => InterfaceName(Cow::Borrowed(s)),
+            Err(_) => panic!("invalid interface name"),
+        }
     }
 }
 
 impl FromStr for InterfaceName {
     type Err = InterfaceNameError;
+
+    #[inline]
     fn from_str(s: &str) -> Result<InterfaceName, InterfaceNameError> {
-        if s.len() > MAX_NAME_LENGHT {
-            return Err(InterfaceNameError::ExceedsMaxSize);
-        }
+        validate_interface_name(s)?;
+        Ok(InterfaceName(Cow::Owned(s.to_string())))
+    }
+}
 
-        if s.starts_with('.') {
-            return Err(InterfaceNameError::MustNotBeginWithPeriod);
+impl_name_ref!(InterfaceName, InterfaceNameRef, InterfaceNameError, validate_interface_name);
+
+/// A unique connection name, assigned by the message bus when a connection is opened.
+/// Unique names begin with a ':' (colon) character, e.g. ":1.42", and are never reused
+/// during the lifetime of the message bus.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct UniqueName(Cow<'static, str>);
+
+impl UniqueName {
+    /// Borrows `s` without copying it, for use in constant expressions.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `s` is not a valid unique connection name, which fails compilation in a constant.
+    #[inline]
+    pub const fn from_static(s: &'static str) -> UniqueName {
+        match validate_unique_name(s) {
+            Ok(()) => UniqueName(Cow::Borrowed(s)),
+            Err(_) => panic!("invalid unique connection name"),
         }
+    }
+}
 
-        let mut last_period_position = 0;
-        for (i, c) in s.char_indices() {
-            if !is_valid_interface_name_char(c) {
-                return Err(InterfaceNameError::InvalidCharacter(c));
-            }
+impl DbusWrite for UniqueName {
+    const ALIGNMENT: usize = 4;
 
-            if c == '.' {
-                if last_period_position + 1 == i {
-                    return Err(InterfaceNameError::ElementsMustContainChars);
-                }
-                last_period_position = i;
-            } else {
-                // start of new element
-                if last_period_position + 1 == i && c.is_digit(10) {
-                    return Err(InterfaceNameError::ElementMustNotBeginWithDigit);
-                }
-            }
-        }
+    #[inline]
+    fn write<T1, T2>(&self, writer: &mut DbusWriter<T1>) -> Result<(), io::Error>
+    where
+        T1: io::Write,
+        T2: ByteOrder,
+    {
+        writer.write_string::<T2>(&self.0)
+    }
+
+    #[inline]
+    fn encoded_size(&self, offset: usize) -> usize {
+        string_size(offset, self.0.len())
+    }
+}
+
+const fn validate_unique_name(s: &str) -> Result<(), BusNameError> {
+    if s.is_empty() || s.as_bytes()[0] != b':' {
+        return Err(BusNameError::UniqueNameMustBeginWithColon);
+    }
+    bus_name_error(validate_elements(s, 1, true, true))
+}
 
-        if last_period_position == 0 {
-            return Err(InterfaceNameError::MustContainPeriod);
+impl FromStr for UniqueName {
+    type Err = BusNameError;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<UniqueName, BusNameError> {
+        validate_unique_name(s)?;
+        Ok(UniqueName(Cow::Owned(s.to_string())))
+    }
+}
+
+impl_name_ref!(UniqueName, UniqueNameRef, BusNameError, validate_unique_name);
+
+/// A well-known name requested by a connection, e.g. "org.freedesktop.DBus".
+/// Well-known names are reverse domain names which may be owned by different
+/// connections over time.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct WellKnownName(Cow<'static, str>);
+
+impl WellKnownName {
+    /// Borrows `s` without copying it, for use in constant expressions.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `s` is not a valid well-known bus name, which fails compilation in a constant.
+    #[inline]
+    pub const fn from_static(s: &'static str) -> WellKnownName {
+        match validate_well_known_name(s) {
+            Ok(()) => WellKnownName(Cow::Borrowed(s)),
+            Err(_) => panic!("invalid well-known bus name"),
         }
+    }
+}
+
+impl DbusWrite for WellKnownName {
+    const ALIGNMENT: usize = 4;
+
+    #[inline]
+    fn write<T1, T2>(&self, writer: &mut DbusWriter<T1>) -> Result<(), io::Error>
+    where
+        T1: io::Write,
+        T2: ByteOrder,
+    {
+        writer.write_string::<T2>(&self.0)
+    }
+
+    #[inline]
+    fn encoded_size(&self, offset: usize) -> usize {
+        string_size(offset, self.0.len())
+    }
+}
 
-        Ok(InterfaceName(s.to_string()))
+const fn validate_well_known_name(s: &str) -> Result<(), BusNameError> {
+    bus_name_error(validate_elements(s, 0, true, false))
+}
+
+impl FromStr for WellKnownName {
+    type Err = BusNameError;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<WellKnownName, BusNameError> {
+        validate_well_known_name(s)?;
+        Ok(WellKnownName(Cow::Owned(s.to_string())))
     }
 }
 
+impl_name_ref!(WellKnownName, WellKnownNameRef, BusNameError, validate_well_known_name);
+
 /// Connections have one or more bus names associated with them.
 /// A connection has exactly one bus name that is a unique connection name.
 /// The unique connection name remains with the connection for its entire lifetime.
 /// A bus name is of type STRING, meaning that it must be valid UTF-8.
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub struct BusName(String);
+#[derive(Clone, Debug)]
+pub enum BusName {
+    Unique(UniqueName),
+    WellKnown(WellKnownName),
+}
+
+impl BusName {
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        match self {
+            BusName::Unique(name) => name.as_str(),
+            BusName::WellKnown(name) => name.as_str(),
+        }
+    }
+
+    /// Borrows `s` without copying it, for use in constant expressions such as [`bus_name!`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `s` is not a valid bus name, which fails compilation in a constant.
+    #[inline]
+    pub const fn from_static(s: &'static str) -> BusName {
+        if !s.is_empty() && s.as_bytes()[0] == b':' {
+            BusName::Unique(UniqueName::from_static(s))
+        } else {
+            BusName::WellKnown(WellKnownName::from_static(s))
+        }
+    }
+}
+
+impl From<UniqueName> for BusName {
+    #[inline]
+    fn from(name: UniqueName) -> BusName {
+        BusName::Unique(name)
+    }
+}
+
+impl From<WellKnownName> for BusName {
+    #[inline]
+    fn from(name: WellKnownName) -> BusName {
+        BusName::WellKnown(name)
+    }
+}
 
 impl DbusWrite for BusName {
+    const ALIGNMENT: usize = 4;
+
+    #[inline]
     fn write<T1, T2>(&self, writer: &mut DbusWriter<T1>) -> Result<(), io::Error>
     where
         T1: io::Write,
         T2: ByteOrder,
     {
-        writer.write_string::<T2>(&self.0)
+        writer.write_string::<T2>(self.as_str())
+    }
+
+    #[inline]
+    fn encoded_size(&self, offset: usize) -> usize {
+        string_size(offset, self.as_str().len())
     }
 }
 
@@ -278,7 +716,6 @@ pub enum BusNameError {
     ElementsMustContainChars,
 
     /// Each element must only contain the ASCII characters "[A-Z][a-z][0-9]_-", with "-" discouraged in new bus names.
-    /// Only elements that are part of a unique connection name may begin with a digit, elements in other bus names must not begin with a digit.
     InvalidCharacter(char),
 
     /// Bus names must contain at least one '.' (period) character (and thus at least two elements).
@@ -286,59 +723,213 @@ pub enum BusNameError {
 
     /// Bus names must not begin with a '.' (period) character.
     MustNotBeginWithPeriod,
-    // TODO
-    // Only elements that are part of a unique connection name may begin with a digit, elements in other bus names must not begin with a digit.
+
+    /// Only elements that are part of a unique connection name may begin with a digit,
+    /// elements in other bus names must not begin with a digit.
+    ElementMustNotBeginWithDigit,
+
+    /// Unique connection names must begin with the character ':' (ASCII colon character).
+    UniqueNameMustBeginWithColon,
 }
 
-fn is_valid_bus_name_char(c: char) -> bool {
-    match c {
-        'A'..='Z' => true,
-        'a'..='z' => true,
-        '0'..='9' => true,
-        '-' => true,
-        '.' => true,
-        _ => false,
+const fn bus_name_error(result: Result<(), ElementsError>) -> Result<(), BusNameError> {
+    match result {
+        Ok(()) => Ok(()),
+        Err(ElementsError::ExceedsMaxSize) => Err(BusNameError::ExceedsMaxSize),
+        Err(ElementsError::ElementsMustContainChars) => Err(BusNameError::ElementsMustContainChars),
+        Err(ElementsError::InvalidCharacter(c)) => Err(BusNameError::InvalidCharacter(c)),
+        Err(ElementsError::MustContainPeriod) => Err(BusNameError::MustContainPeriod),
+        Err(ElementsError::MustNotBeginWithPeriod) => Err(BusNameError::MustNotBeginWithPeriod),
+        Err(ElementsError::ElementMustNotBeginWithDigit) => Err(BusNameError::ElementMustNotBeginWithDigit),
     }
 }
 
 impl FromStr for BusName {
     type Err = BusNameError;
+
+    #[inline]
     fn from_str(s: &str) -> Result<BusName, BusNameError> {
-        if s.len() > MAX_NAME_LENGHT {
-            return Err(BusNameError::ExceedsMaxSize);
+        if s.starts_with(':') {
+            UniqueName::from_str(s).map(BusName::Unique)
+        } else {
+            WellKnownName::from_str(s).map(BusName::WellKnown)
         }
+    }
+}
 
-        if s.starts_with('.') {
-            return Err(BusNameError::MustNotBeginWithPeriod);
+/// A borrowed bus name, either unique or well-known.
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[repr(transparent)]
+pub struct BusNameRef(str);
+
+impl BusNameRef {
+    /// Validates `s` and borrows it without copying.
+    #[inline]
+    pub fn new(s: &str) -> Result<&BusNameRef, BusNameError> {
+        if s.starts_with(':') {
+            validate_unique_name(s)?;
+        } else {
+            validate_well_known_name(s)?;
         }
+        Ok(BusNameRef::new_unchecked(s))
+    }
 
-        let mut last_period_position = 0;
-        for (i, c) in s.char_indices() {
-            if !is_valid_bus_name_char(c) {
-                return Err(BusNameError::InvalidCharacter(c));
-            }
+    fn new_unchecked(s: &str) -> &BusNameRef {
+        // SAFETY: `BusNameRef` is a `repr(transparent)` wrapper of `str`.
+        unsafe { &*(s as *const str as *const BusNameRef) }
+    }
 
-            if c == '.' {
-                if last_period_position + 1 == i {
-                    return Err(BusNameError::ElementsMustContainChars);
-                }
-                last_period_position = i;
-            }
-        }
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Whether this is a unique connection name rather than a well-known name.
+    #[inline]
+    pub fn is_unique(&self) -> bool {
+        self.0.starts_with(':')
+    }
+}
+
+/// Bus names compare and hash as their string, consistent with [`BusNameRef`].
+impl PartialEq for BusName {
+    #[inline]
+    fn eq(&self, other: &BusName) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for BusName {}
+
+impl Hash for BusName {
+    #[inline]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state)
+    }
+}
+
+impl PartialOrd for BusName {
+    #[inline]
+    fn partial_cmp(&self, other: &BusName) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for BusName {
+    #[inline]
+    fn cmp(&self, other: &BusName) -> Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl Deref for BusName {
+    type Target = BusNameRef;
+
+    #[inline]
+    fn deref(&self) -> &BusNameRef {
+        BusNameRef::new_unchecked(self.as_str())
+    }
+}
+
+impl Borrow<BusNameRef> for BusName {
+    #[inline]
+    fn borrow(&self) -> &BusNameRef {
+        self
+    }
+}
+
+impl AsRef<BusNameRef> for BusName {
+    #[inline]
+    fn as_ref(&self) -> &BusNameRef {
+        self
+    }
+}
+
+impl AsRef<str> for BusName {
+    #[inline]
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl AsRef<str> for BusNameRef {
+    #[inline]
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
 
-        if last_period_position == 0 {
-            return Err(BusNameError::MustContainPeriod);
+impl ToOwned for BusNameRef {
+    type Owned = BusName;
+
+    #[inline]
+    fn to_owned(&self) -> BusName {
+        let name = Cow::Owned(self.0.to_string());
+        if self.is_unique() {
+            BusName::Unique(UniqueName(name))
+        } else {
+            BusName::WellKnown(WellKnownName(name))
         }
+    }
+}
+
+impl From<&BusNameRef> for BusName {
+    #[inline]
+    fn from(name: &BusNameRef) -> BusName {
+        name.to_owned()
+    }
+}
+
+impl PartialEq<BusNameRef> for BusName {
+    #[inline]
+    fn eq(&self, other: &BusNameRef) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl PartialEq<BusName> for BusNameRef {
+    #[inline]
+    fn eq(&self, other: &BusName) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl<'a> From<&'a UniqueNameRef> for &'a BusNameRef {
+    #[inline]
+    fn from(name: &'a UniqueNameRef) -> &'a BusNameRef {
+        BusNameRef::new_unchecked(name.as_str())
+    }
+}
 
-        Ok(BusName(s.to_string()))
+impl<'a> From<&'a WellKnownNameRef> for &'a BusNameRef {
+    #[inline]
+    fn from(name: &'a WellKnownNameRef) -> &'a BusNameRef {
+        BusNameRef::new_unchecked(name.as_str())
+    }
+}
+
+impl fmt::Display for BusName {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Display for BusNameRef {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
     }
 }
 
 /// Member (i.e. method or signal) names.
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub struct MemberName(String);
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MemberName(Cow<'static, str>);
 
 impl DbusWrite for MemberName {
+    const ALIGNMENT: usize = 4;
+
+    #[inline]
     fn write<T1, T2>(&self, writer: &mut DbusWriter<T1>) -> Result<(), io::Error>
     where
         T1: io::Write,
@@ -346,6 +937,11 @@ impl DbusWrite for MemberName {
     {
         writer.write_string::<T2>(&self.0)
     }
+
+    #[inline]
+    fn encoded_size(&self, offset: usize) -> usize {
+        string_size(offset, self.0.len())
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -363,50 +959,68 @@ pub enum MemberNameError {
     MustNotBeginWithDigit,
 
     /// Must be at least 1 byte in length.
-    // TODO verify this
     MustBeAtLeastOneByte,
 }
 
-fn is_valid_member_name_char(c: char) -> bool {
-    match c {
-        'A'..='Z' => true,
-        'a'..='z' => true,
-        '0'..='9' => true,
-        '-' => true,
-        _ => false,
+const fn validate_member_name(s: &str) -> Result<(), MemberNameError> {
+    let bytes = s.as_bytes();
+    if bytes.len() > MAX_NAME_LENGHT {
+        return Err(MemberNameError::ExceedsMaxSize);
+    }
+    if bytes.is_empty() {
+        return Err(MemberNameError::MustBeAtLeastOneByte);
+    }
+    if bytes[0].is_ascii_digit() {
+        return Err(MemberNameError::MustNotBeginWithDigit);
     }
-}
 
-impl FromStr for MemberName {
-    type Err = MemberNameError;
-    fn from_str(s: &str) -> Result<MemberName, MemberNameError> {
-        if s.len() > MAX_NAME_LENGHT {
-            return Err(MemberNameError::ExceedsMaxSize);
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'.' => return Err(MemberNameError::MustNotContainPeriod),
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'_' => {}
+            _ => return Err(MemberNameError::InvalidCharacter(char_at(bytes, i))),
         }
+        i += 1;
+    }
+    Ok(())
+}
 
-        if s.starts_with(|c: char| c.is_digit(10)) {
-            return Err(MemberNameError::MustNotBeginWithDigit);
+impl MemberName {
+    /// Borrows `s` without copying it, for use in constant expressions such as [`member_name!`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `s` is not a valid member name, which fails compilation in a constant.
+    #[inline]
+    pub const fn from_static(s: &'static str) -> MemberName {
+        match validate_member_name(s) {
+            Ok(()) => MemberName(Cow::Borrowed(s)),
+            Err(_) => panic!("invalid member name"),
         }
+    }
+}
 
-        for c in s.chars() {
-            if c == '.' {
-                return Err(MemberNameError::MustNotContainPeriod);
-            }
-
-            if !is_valid_member_name_char(c) {
-                return Err(MemberNameError::InvalidCharacter(c));
-            }
-        }
+impl FromStr for MemberName {
+    type Err = MemberNameError;
 
-        Ok(MemberName(s.to_string()))
+    #[inline]
+    fn from_str(s: &str) -> Result<MemberName, MemberNameError> {
+        validate_member_name(s)?;
+        Ok(MemberName(Cow::Owned(s.to_string())))
     }
 }
 
+impl_name_ref!(MemberName, MemberNameRef, MemberNameError, validate_member_name);
+
 /// Error names have the same restrictions as interface names.
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub struct ErrorName(String);
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ErrorName(Cow<'static, str>);
 
 impl DbusWrite for ErrorName {
+    const ALIGNMENT: usize = 4;
+
+    #[inline]
     fn write<T1, T2>(&self, writer: &mut DbusWriter<T1>) -> Result<(), io::Error>
     where
         T1: io::Write,
@@ -414,6 +1028,11 @@ impl DbusWrite for ErrorName {
     {
         writer.write_string::<T2>(&self.0)
     }
+
+    #[inline]
+    fn encoded_size(&self, offset: usize) -> usize {
+        string_size(offset, self.0.len())
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -425,10 +1044,7 @@ pub enum ErrorNameError {
     /// All elements must contain at least one character.
     ElementsMustContainChars,
 
-    /// Each element must only contain the ASCII characters "[A-Z][a-z][0-9]_-",
-    /// with "-" discouraged in new bus names. Only elements that are part of a
-    /// unique connection name may begin with a digit, elements in other bus names
-    /// must not begin with a digit.
+    /// Each element must only contain the ASCII characters "[A-Z][a-z][0-9]_".
     InvalidCharacter(char),
 
     /// Error names must contain at least one '.' (period) character (and thus at least two elements).
@@ -441,25 +1057,113 @@ pub enum ErrorNameError {
     ElementMustNotBeginWithDigit,
 }
 
+const fn validate_error_name(s: &str) -> Result<(), ErrorNameError> {
+    match validate_interface_name(s) {
+        Ok(()) => Ok(()),
+        Err(InterfaceNameError::ExceedsMaxSize) => Err(ErrorNameError::ExceedsMaxSize),
+        Err(InterfaceNameError::ElementsMustContainChars) => Err(ErrorNameError::ElementsMustContainChars),
+        Err(InterfaceNameError::InvalidCharacter(c)) => Err(ErrorNameError::InvalidCharacter(c)),
+        Err(InterfaceNameError::MustContainPeriod) => Err(ErrorNameError::MustContainPeriod),
+        Err(InterfaceNameError::MustNotBeginWithPeriod) => Err(ErrorNameError::MustNotBeginWithPeriod),
+        Err(InterfaceNameError::ElementMustNotBeginWithDigit) => Err(ErrorNameError::ElementMustNotBeginWithDigit),
+    }
+}
+
+impl ErrorName {
+    /// Borrows `s` without copying it, for use in constant expressions such as [`error_name!`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `s` is not a valid error name, which fails compilation in a constant.
+    #[inline]
+    pub const fn from_static(s: &'static str) -> ErrorName {
+        match validate_error_name(s) {
+            Ok(()) => ErrorName(Cow::Borrowed(s)),
+            Err(_) => panic!("invalid error name"),
+        }
+    }
+}
+
 impl FromStr for ErrorName {
     type Err = ErrorNameError;
+
+    #[inline]
     fn from_str(s: &str) -> Result<ErrorName, ErrorNameError> {
-        match InterfaceName::from_str(s) {
-            Ok(iface) => Ok(ErrorName(iface.0)),
-            Err(InterfaceNameError::ExceedsMaxSize) => Err(ErrorNameError::ExceedsMaxSize),
-            Err(InterfaceNameError::ElementsMustContainChars) => {
-                Err(ErrorNameError::ElementsMustContainChars)
-            }
-            Err(InterfaceNameError::InvalidCharacter(c)) => {
-                Err(ErrorNameError::InvalidCharacter(c))
-            }
-            Err(InterfaceNameError::MustContainPeriod) => Err(ErrorNameError::MustContainPeriod),
-            Err(InterfaceNameError::MustNotBeginWithPeriod) => {
-                Err(ErrorNameError::MustNotBeginWithPeriod)
+        validate_error_name(s)?;
+        Ok(ErrorName(Cow::Owned(s.to_string())))
+    }
+}
+
+impl_name_ref!(ErrorName, ErrorNameRef, ErrorNameError, validate_error_name);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum UnescapeError {
+    /// Escaped elements only contain the ASCII characters "[A-Z][a-z][0-9]_".
+    InvalidCharacter(char),
+
+    /// The '_' at the given index is not followed by two hexadecimal digits.
+    InvalidEscape(usize),
+}
+
+impl fmt::Display for UnescapeError {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UnescapeError::InvalidCharacter(c) => write!(f, "invalid character `{}` in escaped element", c),
+            UnescapeError::InvalidEscape(i) => write!(f, "invalid escape sequence at index {}", i),
+        }
+    }
+}
+
+impl std::error::Error for UnescapeError {}
+
+/// Escapes arbitrary bytes into a single element valid in object paths as well as in
+/// bus, interface and error names, compatible with systemd's `bus_label_escape`.
+/// Every byte other than an ASCII letter, or a digit not at the start, is written as
+/// '_' followed by two lowercase hexadecimal digits. The empty string becomes "_".
+#[inline]
+pub fn escape_element(bytes: &[u8]) -> String {
+    if bytes.is_empty() {
+        return "_".to_string();
+    }
+
+    let mut escaped = String::with_capacity(bytes.len());
+    for (i, b) in bytes.iter().enumerate() {
+        if b.is_ascii_alphabetic() || (i > 0 && b.is_ascii_digit()) {
+            escaped.push(*b as char);
+        } else {
+            escaped.push_str(&format!("_{:02x}", b));
+        }
+    }
+    escaped
+}
+
+/// Reverses [`escape_element`].
+#[inline]
+pub fn unescape_element(element: &str) -> Result<Vec<u8>, UnescapeError> {
+    if element == "_" {
+        return Ok(Vec::new());
+    }
+
+    let bytes = element.as_bytes();
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'_' => {
+                let hex = element.get(i + 1..i + 3).ok_or(UnescapeError::InvalidEscape(i))?;
+                if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    return Err(UnescapeError::InvalidEscape(i));
+                }
+                unescaped.push(u8::from_str_radix(hex, 16).map_err(|_| UnescapeError::InvalidEscape(i))?);
+                i += 3;
             }
-            Err(InterfaceNameError::ElementMustNotBeginWithDigit) => {
-                Err(ErrorNameError::ElementMustNotBeginWithDigit)
+            b if b.is_ascii_alphanumeric() => {
+                unescaped.push(b);
+                i += 1;
             }
+            _ => return Err(UnescapeError::InvalidCharacter(char_at(bytes, i))),
         }
     }
+    Ok(unescaped)
 }
@@ -0,0 +1,241 @@
+//! Parsing and serializing the commands of the authentication protocol, one per line
+//! terminated by CRLF, with binary data hex encoded.
+
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::guid::Guid;
+
+use super::{AuthMechanism, Protocol, MAX_LINE_LENGTH};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// The line is longer than the maximum length. The rest of the stream can not be
+    /// parsed, the connection is to be closed.
+    LineTooLong,
+    /// The line ends in a bare LF.
+    MissingCr,
+    InvalidUtf8,
+    UnknownCommand(String),
+    /// AUTH with a mechanism not known to this implementation.
+    UnknownMechanism(String),
+    /// The command takes fewer arguments.
+    UnexpectedArgument(&'static str),
+    /// The command's data is not valid hex.
+    InvalidHex(&'static str),
+    /// OK without a GUID of 32 hex digits.
+    InvalidGuid(String),
+}
+
+impl fmt::Display for ParseError {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::LineTooLong => write!(f, "line too long"),
+            ParseError::MissingCr => write!(f, "line not terminated by CRLF"),
+            ParseError::InvalidUtf8 => write!(f, "line is not valid UTF-8"),
+            ParseError::UnknownCommand(command) => write!(f, "unknown command `{}`", command),
+            ParseError::UnknownMechanism(mechanism) => write!(f, "unknown mechanism `{}`", mechanism),
+            ParseError::UnexpectedArgument(command) => write!(f, "too many arguments to {}", command),
+            ParseError::InvalidHex(command) => write!(f, "invalid hex data in {}", command),
+            ParseError::InvalidGuid(guid) => write!(f, "invalid GUID `{}`", guid),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+impl Protocol {
+    /// Parses a command from a line without its CRLF. REJECTED lists only the mechanisms
+    /// known to this implementation.
+    #[inline]
+    pub fn parse(line: &str) -> Result<Protocol, ParseError> {
+        let (command, args) = match line.find(' ') {
+            Some(i) => (&line[..i], Some(&line[i + 1..])),
+            None => (line, None),
+        };
+        let no_args = |command: &'static str, protocol: Protocol| match args {
+            None => Ok(protocol),
+            Some(_) => Err(ParseError::UnexpectedArgument(command)),
+        };
+        match command {
+            "AUTH" => {
+                let mut args = args.unwrap_or("").split(' ').filter(|arg| !arg.is_empty());
+                let mechanism = match args.next() {
+                    Some(name) => Some(name.parse::<AuthMechanism>().map_err(ParseError::UnknownMechanism)?),
+                    None => None,
+                };
+                let initial_response = match args.next() {
+                    Some(response) => Some(hex_decode(response).ok_or(ParseError::InvalidHex("AUTH"))?),
+                    None => None,
+                };
+                if args.next().is_some() {
+                    return Err(ParseError::UnexpectedArgument("AUTH"));
+                }
+                Ok(Protocol::Auth { mechanism, initial_response })
+            }
+            "CANCEL" => no_args("CANCEL", Protocol::Cancel),
+            "BEGIN" => no_args("BEGIN", Protocol::Begin),
+            "DATA" => Ok(Protocol::Data { data: hex_decode(args.unwrap_or("").trim()).ok_or(ParseError::InvalidHex("DATA"))? }),
+            "ERROR" => Ok(Protocol::Error { error_explanation: args.unwrap_or("").to_string() }),
+            "NEGOTIATE_UNIX_FD" => no_args("NEGOTIATE_UNIX_FD", Protocol::NegotiateUnixFd),
+            "REJECTED" => {
+                let mechanisms = args.unwrap_or("").split(' ').filter_map(|name| name.parse().ok()).collect();
+                Ok(Protocol::Rejected { mechanisms })
+            }
+            "OK" => {
+                let guid = args.unwrap_or("").trim();
+                let guid = Guid::from_str(guid).map_err(|_| ParseError::InvalidGuid(guid.to_string()))?;
+                Ok(Protocol::Ok { guid })
+            }
+            "AGREE_UNIX_FD" => no_args("AGREE_UNIX_FD", Protocol::AgreeUnixFd),
+            _ => Err(ParseError::UnknownCommand(command.to_string())),
+        }
+    }
+
+    /// Appends the command as a line terminated by CRLF.
+    #[inline]
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        let line = match self {
+            Protocol::Auth { mechanism: None, .. } => "AUTH".to_string(),
+            Protocol::Auth { mechanism: Some(mechanism), initial_response: None } => format!("AUTH {}", mechanism),
+            Protocol::Auth { mechanism: Some(mechanism), initial_response: Some(response) } => format!("AUTH {} {}", mechanism, hex_encode(response)),
+            Protocol::Cancel => "CANCEL".to_string(),
+            Protocol::Begin => "BEGIN".to_string(),
+            Protocol::Data { data } if data.is_empty() => "DATA".to_string(),
+            Protocol::Data { data } => format!("DATA {}", hex_encode(data)),
+            Protocol::Error { error_explanation } if error_explanation.is_empty() => "ERROR".to_string(),
+            Protocol::Error { error_explanation } => format!("ERROR {}", error_explanation.replace(['\r', '\n'], " ")),
+            Protocol::NegotiateUnixFd => "NEGOTIATE_UNIX_FD".to_string(),
+            Protocol::Rejected { mechanisms } => mechanisms.iter().fold("REJECTED".to_string(), |line, mechanism| line + " " + mechanism.as_str()),
+            Protocol::Ok { guid } => format!("OK {}", guid),
+            Protocol::AgreeUnixFd => "AGREE_UNIX_FD".to_string(),
+        };
+        out.extend_from_slice(line.as_bytes());
+        out.extend_from_slice(b"\r\n");
+    }
+}
+
+/// Splits received bytes into lines and parses the commands, buffering partial lines.
+#[derive(Debug)]
+pub struct AuthCodec {
+    buffer: Vec<u8>,
+    max_line_length: usize,
+}
+
+impl Default for AuthCodec {
+    #[inline]
+    fn default() -> AuthCodec {
+        AuthCodec::new(MAX_LINE_LENGTH)
+    }
+}
+
+impl AuthCodec {
+    /// A codec rejecting lines longer than `max_line_length` bytes, including the CRLF.
+    #[inline]
+    pub fn new(max_line_length: usize) -> AuthCodec {
+        AuthCodec { buffer: Vec::new(), max_line_length }
+    }
+
+    /// Consumes `bytes` up to the end of the first line, returning how many were consumed
+    /// and the command if the line is complete. Errors other than
+    /// [`LineTooLong`](ParseError::LineTooLong) only affect the line they are reported for.
+    #[inline]
+    pub fn decode(&mut self, bytes: &[u8]) -> (usize, Option<Result<Protocol, ParseError>>) {
+        let (consumed, complete) = match bytes.iter().position(|&b| b == b'\n') {
+            Some(end) => (end + 1, true),
+            None => (bytes.len(), false),
+        };
+        self.buffer.extend_from_slice(&bytes[..consumed]);
+        if self.buffer.len() > self.max_line_length {
+            return (consumed, Some(Err(ParseError::LineTooLong)));
+        }
+        if !complete {
+            return (consumed, None);
+        }
+        let line = std::mem::take(&mut self.buffer);
+        let command = match line.strip_suffix(b"\r\n").map(std::str::from_utf8) {
+            Some(Ok(line)) => Protocol::parse(line),
+            Some(Err(_)) => Err(ParseError::InvalidUtf8),
+            None => Err(ParseError::MissingCr),
+        };
+        (consumed, Some(command))
+    }
+}
+
+pub(crate) fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub(crate) fn hex_decode(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len()).step_by(2).map(|i| s.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok())).collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn encode(command: &Protocol) -> String {
+        let mut out = Vec::new();
+        command.encode(&mut out);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let commands = vec![
+            (Protocol::Auth { mechanism: None, initial_response: None }, "AUTH"),
+            (Protocol::Auth { mechanism: Some(AuthMechanism::External), initial_response: None }, "AUTH EXTERNAL"),
+            (Protocol::Auth { mechanism: Some(AuthMechanism::External), initial_response: Some(b"1000".to_vec()) }, "AUTH EXTERNAL 31303030"),
+            (Protocol::Cancel, "CANCEL"),
+            (Protocol::Begin, "BEGIN"),
+            (Protocol::Data { data: vec![] }, "DATA"),
+            (Protocol::Data { data: vec![0x00, 0xff, 0x10] }, "DATA 00ff10"),
+            (Protocol::Error { error_explanation: String::new() }, "ERROR"),
+            (Protocol::Error { error_explanation: "not supported".to_string() }, "ERROR not supported"),
+            (Protocol::NegotiateUnixFd, "NEGOTIATE_UNIX_FD"),
+            (Protocol::Rejected { mechanisms: vec![] }, "REJECTED"),
+            (Protocol::Rejected { mechanisms: vec![AuthMechanism::External, AuthMechanism::DbusCookieSha1, AuthMechanism::Anonymous] }, "REJECTED EXTERNAL DBUS_COOKIE_SHA1 ANONYMOUS"),
+            (Protocol::Ok { guid: "1234deadbeef5678deadbeef9abcdef0".parse().unwrap() }, "OK 1234deadbeef5678deadbeef9abcdef0"),
+            (Protocol::AgreeUnixFd, "AGREE_UNIX_FD"),
+        ];
+        for (command, line) in commands {
+            assert_eq!(format!("{}\r\n", line), encode(&command));
+            assert_eq!(Ok(command), Protocol::parse(line));
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(Err(ParseError::UnknownCommand("HELLO".to_string())), Protocol::parse("HELLO"));
+        assert_eq!(Err(ParseError::UnknownCommand("auth".to_string())), Protocol::parse("auth"));
+        assert_eq!(Err(ParseError::UnknownMechanism("KERBEROS_V4".to_string())), Protocol::parse("AUTH KERBEROS_V4"));
+        assert_eq!(Err(ParseError::InvalidHex("AUTH")), Protocol::parse("AUTH EXTERNAL 3"));
+        assert_eq!(Err(ParseError::UnexpectedArgument("AUTH")), Protocol::parse("AUTH EXTERNAL 30 30"));
+        assert_eq!(Err(ParseError::InvalidHex("DATA")), Protocol::parse("DATA xy"));
+        assert_eq!(Err(ParseError::UnexpectedArgument("BEGIN")), Protocol::parse("BEGIN now"));
+        assert_eq!(Err(ParseError::InvalidGuid("1234".to_string())), Protocol::parse("OK 1234"));
+        assert_eq!(Ok(Protocol::Rejected { mechanisms: vec![AuthMechanism::External] }), Protocol::parse("REJECTED KERBEROS_V4 EXTERNAL"));
+        assert_eq!("unknown mechanism `KERBEROS_V4`", ParseError::UnknownMechanism("KERBEROS_V4".to_string()).to_string());
+    }
+
+    #[test]
+    fn framing() {
+        let mut codec = AuthCodec::default();
+        assert_eq!((4, None), codec.decode(b"BEGI"));
+        assert_eq!((3, Some(Ok(Protocol::Begin))), codec.decode(b"N\r\nDATA\r\n"));
+        assert_eq!((6, Some(Ok(Protocol::Data { data: vec![] }))), codec.decode(b"DATA\r\n"));
+        assert_eq!((6, Some(Err(ParseError::MissingCr))), codec.decode(b"BEGIN\nBEGIN\r\n"));
+        assert_eq!((3, Some(Err(ParseError::InvalidUtf8))), codec.decode(b"\xff\r\n"));
+        assert_eq!((0, None), codec.decode(b""));
+
+        let mut codec = AuthCodec::new(8);
+        assert_eq!((8, Some(Ok(Protocol::Cancel))), codec.decode(b"CANCEL\r\n"));
+        assert_eq!((19, Some(Err(ParseError::LineTooLong))), codec.decode(b"NEGOTIATE_UNIX_FD\r\n"));
+    }
+}
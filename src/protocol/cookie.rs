@@ -0,0 +1,550 @@
+//! The keyring of the DBUS_COOKIE_SHA1 mechanism. Client and server prove they share a
+//! home directory by both reading a secret cookie from `~/.dbus-keyrings/<context>`.
+//! https://dbus.freedesktop.org/doc/dbus-specification.html#auth-mechanisms-sha
+
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::codec::{hex_decode, hex_encode};
+use crate::crypto::{constant_time_eq, random};
+
+/// The directory holding keyrings, relative to the home directory.
+pub const KEYRING_DIR: &str = ".dbus-keyrings";
+
+/// The context used by the reference implementation.
+pub const DEFAULT_CONTEXT: &str = "org_freedesktop_general";
+
+/// A new cookie is created once the newest one is older than this.
+pub const NEW_COOKIE_TIMEOUT: u64 = 5 * 60;
+/// Cookies older than this are removed, leaving clients time to use the previous one.
+pub const EXPIRE_COOKIE_TIMEOUT: u64 = NEW_COOKIE_TIMEOUT + 2 * 60;
+/// Cookies created further in the future than this are removed.
+pub const MAX_TIME_TRAVEL: u64 = 5 * 60;
+/// The oldest cookies are removed beyond this number.
+pub const MAX_COOKIES: usize = 256;
+
+/// Attempts to create the lock file before it is considered stale and removed.
+const LOCK_ATTEMPTS: u32 = 32;
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Whether `context` may name a keyring, being non-empty ASCII without a path separator,
+/// period or whitespace.
+#[inline]
+pub fn is_valid_context(context: &str) -> bool {
+    !context.is_empty() && context.bytes().all(|b| b.is_ascii() && !b"/\\. \n\r\t".contains(&b) && !b.is_ascii_control())
+}
+
+/// A cookie as stored in a keyring file, one per line as `<id> <creation time> <cookie>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    pub id: u32,
+    /// Seconds since the Unix epoch.
+    pub created: u64,
+    /// The secret, hex encoded.
+    pub cookie: String,
+}
+
+impl Cookie {
+    fn generate(id: u32, created: u64) -> io::Result<Cookie> {
+        let mut bytes = [0; 24];
+        random(&mut bytes)?;
+        Ok(Cookie { id, created, cookie: hex_encode(&bytes) })
+    }
+
+    fn parse(line: &str) -> Option<Cookie> {
+        let mut fields = line.split(' ');
+        let id = fields.next()?.parse().ok()?;
+        let created = fields.next()?.parse().ok()?;
+        let cookie = fields.next()?;
+        if fields.next().is_some() || cookie.is_empty() || !cookie.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        Some(Cookie { id, created, cookie: cookie.to_string() })
+    }
+}
+
+/// The keyring directory of a user.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keyring {
+    dir: PathBuf,
+    owner: u32,
+}
+
+impl Keyring {
+    /// The keyring in `home`, belonging to the current user.
+    #[inline]
+    pub fn in_home(home: &Path) -> Keyring {
+        Keyring { dir: home.join(KEYRING_DIR), owner: current_uid() }
+    }
+
+    /// The keyring in the home directory from `HOME`.
+    #[inline]
+    pub fn for_current_user() -> io::Result<Keyring> {
+        match env::var_os("HOME") {
+            Some(home) if !home.is_empty() => Ok(Keyring::in_home(Path::new(&home))),
+            _ => Err(io::Error::new(io::ErrorKind::NotFound, "HOME is not set")),
+        }
+    }
+
+    /// The keyring of the user `uid`, the current user's from `HOME` and any other's in
+    /// their home directory from the user database.
+    #[inline]
+    pub fn for_user(uid: u32) -> io::Result<Keyring> {
+        if uid == current_uid() {
+            return Keyring::for_current_user();
+        }
+        let home = home_dir(uid)?;
+        Ok(Keyring { dir: home.join(KEYRING_DIR), owner: uid })
+    }
+
+    #[inline]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The uid of the user the keyring belongs to, who must own its directory.
+    #[inline]
+    pub fn owner(&self) -> u32 {
+        self.owner
+    }
+
+    /// The cookies of `context`, failing if the directory is accessible by other users
+    /// as the cookies could not be trusted to be secret then. Malformed lines are skipped.
+    #[inline]
+    pub fn read(&self, context: &str) -> io::Result<Vec<Cookie>> {
+        let path = self.context_path(context)?;
+        self.check_permissions()?;
+        let content = fs::read_to_string(path)?;
+        Ok(content.lines().filter_map(Cookie::parse).collect())
+    }
+
+    /// The cookie of `context` with the given `id`.
+    #[inline]
+    pub fn find(&self, context: &str, id: u32) -> io::Result<Cookie> {
+        self.read(context)?.into_iter().find(|cookie| cookie.id == id).ok_or_else(|| {
+            let str_err = format!("No cookie {} in keyring `{}`", id, context);
+            io::Error::new(io::ErrorKind::NotFound, str_err)
+        })
+    }
+
+    /// The newest cookie of `context` for a server to challenge clients with. Under the
+    /// lock of the keyring, expired cookies are removed and a new one is created if the
+    /// newest is too old to be used, creating the keyring directory if needed. The keyring
+    /// of another user is only read, as files written there would not belong to them.
+    #[inline]
+    pub fn current_cookie(&self, context: &str) -> io::Result<Cookie> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        self.current_cookie_at(context, now)
+    }
+
+    fn current_cookie_at(&self, context: &str, now: u64) -> io::Result<Cookie> {
+        let usable = |cookie: &&Cookie| cookie.created <= now && now - cookie.created < NEW_COOKIE_TIMEOUT;
+        if self.owner != current_uid() {
+            let mut cookies = self.read(context)?;
+            cookies.sort_by_key(|cookie| cookie.created);
+            return cookies.iter().rev().find(usable).cloned().ok_or_else(|| {
+                let str_err = format!("No usable cookie in keyring `{}` of user {}", context, self.owner);
+                io::Error::new(io::ErrorKind::NotFound, str_err)
+            });
+        }
+
+        let path = self.context_path(context)?;
+        self.create_dir()?;
+        let _lock = LockFile::acquire(self.dir.join(format!("{}.lock", context)))?;
+
+        let mut cookies = match self.read(context) {
+            Ok(cookies) => cookies,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err),
+        };
+        let count = cookies.len();
+        cookies.retain(|cookie| cookie.created <= now + MAX_TIME_TRAVEL && now.saturating_sub(cookie.created) < EXPIRE_COOKIE_TIMEOUT);
+        let mut changed = cookies.len() != count;
+
+        cookies.sort_by_key(|cookie| cookie.created);
+        let current = match cookies.iter().rev().find(usable) {
+            Some(cookie) => cookie.clone(),
+            None => {
+                let mut id = [0; 4];
+                let id = loop {
+                    random(&mut id)?;
+                    let id = u32::from_ne_bytes(id) & 0x7fff_ffff;
+                    if cookies.iter().all(|cookie| cookie.id != id) {
+                        break id;
+                    }
+                };
+                changed = true;
+                Cookie::generate(id, now)?
+            }
+        };
+        // The oldest cookies beyond the maximum are dropped, but never the current one,
+        // even if cookies from the future sort after it.
+        cookies.retain(|cookie| cookie.id != current.id);
+        if cookies.len() >= MAX_COOKIES {
+            cookies.drain(..cookies.len() + 1 - MAX_COOKIES);
+            changed = true;
+        }
+        cookies.push(current.clone());
+        cookies.sort_by_key(|cookie| cookie.created);
+        if changed {
+            self.write(&path, &cookies)?;
+        }
+        Ok(current)
+    }
+
+    /// Replaces the keyring file atomically, readable only by the current user.
+    fn write(&self, path: &Path, cookies: &[Cookie]) -> io::Result<()> {
+        let content: String = cookies.iter().map(|cookie| format!("{} {} {}\n", cookie.id, cookie.created, cookie.cookie)).collect();
+        let tmp = path.with_extension("tmp");
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        options.open(&tmp)?.write_all(content.as_bytes())?;
+        fs::rename(&tmp, path)
+    }
+
+    fn create_dir(&self) -> io::Result<()> {
+        let mut builder = fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirBuilderExt;
+            builder.mode(0o700);
+        }
+        builder.create(&self.dir)
+    }
+
+    fn context_path(&self, context: &str) -> io::Result<PathBuf> {
+        if !is_valid_context(context) {
+            let str_err = format!("Invalid keyring context `{}`", context);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, str_err));
+        }
+        Ok(self.dir.join(context))
+    }
+
+    /// The directory must be owned by the owner of the keyring and inaccessible to others.
+    #[cfg(unix)]
+    fn check_permissions(&self) -> io::Result<()> {
+        use std::os::unix::fs::MetadataExt;
+
+        let metadata = fs::symlink_metadata(&self.dir)?;
+        if !metadata.is_dir() || metadata.uid() != self.owner || metadata.mode() & 0o077 != 0 {
+            let str_err = format!("Keyring directory `{}` is not a directory private to user {}", self.dir.display(), self.owner);
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, str_err));
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn check_permissions(&self) -> io::Result<()> {
+        if !fs::metadata(&self.dir)?.is_dir() {
+            let str_err = format!("Keyring directory `{}` is not a directory", self.dir.display());
+            return Err(io::Error::new(io::ErrorKind::InvalidData, str_err));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn current_uid() -> u32 {
+    // SAFETY: geteuid has no preconditions and can not fail.
+    unsafe { libc::geteuid() }
+}
+
+#[cfg(not(unix))]
+fn current_uid() -> u32 {
+    0
+}
+
+/// The home directory of the user `uid` from the user database.
+#[cfg(unix)]
+fn home_dir(uid: u32) -> io::Result<PathBuf> {
+    use std::ffi::{CStr, OsStr};
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut buffer = vec![0 as libc::c_char; 1024];
+    loop {
+        // SAFETY: passwd is plain data for which all zeroes is a valid value.
+        let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        // SAFETY: the pointers are valid for the call and the buffer length is passed along.
+        let err = unsafe { libc::getpwuid_r(uid, &mut passwd, buffer.as_mut_ptr(), buffer.len(), &mut result) };
+        match err {
+            0 if !result.is_null() && !passwd.pw_dir.is_null() => {
+                // SAFETY: pw_dir points to a nul terminated string in the buffer.
+                let home = unsafe { CStr::from_ptr(passwd.pw_dir) };
+                if home.to_bytes().is_empty() {
+                    break;
+                }
+                return Ok(PathBuf::from(OsStr::from_bytes(home.to_bytes())));
+            }
+            0 => break,
+            libc::ERANGE if buffer.len() < 1 << 20 => {
+                let len = buffer.len() * 2;
+                buffer.resize(len, 0);
+            }
+            err => return Err(io::Error::from_raw_os_error(err)),
+        }
+    }
+    let str_err = format!("No home directory for user {}", uid);
+    Err(io::Error::new(io::ErrorKind::NotFound, str_err))
+}
+
+#[cfg(not(unix))]
+fn home_dir(uid: u32) -> io::Result<PathBuf> {
+    let str_err = format!("No home directory for user {}", uid);
+    Err(io::Error::new(io::ErrorKind::NotFound, str_err))
+}
+
+/// A lock on a keyring, held while its file exists. A lock that can not be acquired for
+/// several seconds is assumed to be left over by a crashed process and is broken.
+#[derive(Debug)]
+struct LockFile {
+    path: PathBuf,
+}
+
+impl LockFile {
+    fn acquire(path: PathBuf) -> io::Result<LockFile> {
+        let create = || OpenOptions::new().write(true).create_new(true).open(&path);
+        for _ in 0..LOCK_ATTEMPTS {
+            match create() {
+                Ok(_) => return Ok(LockFile { path }),
+                Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => thread::sleep(LOCK_RETRY_INTERVAL),
+                Err(err) => return Err(err),
+            }
+        }
+        fs::remove_file(&path)?;
+        create()?;
+        Ok(LockFile { path })
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// The server half of a DBUS_COOKIE_SHA1 exchange, challenging the client to prove it
+/// can read the current cookie.
+#[derive(Debug, Clone)]
+pub struct CookieChallenge {
+    context: String,
+    cookie: Cookie,
+    server_challenge: String,
+}
+
+impl CookieChallenge {
+    /// A new challenge with the current cookie of `context` in `keyring`.
+    #[inline]
+    pub fn new(keyring: &Keyring, context: &str) -> io::Result<CookieChallenge> {
+        Ok(CookieChallenge {
+            context: context.to_string(),
+            cookie: keyring.current_cookie(context)?,
+            server_challenge: challenge()?,
+        })
+    }
+
+    /// The data sent to the client, `<context> <cookie id> <server challenge>`.
+    #[inline]
+    pub fn data(&self) -> Vec<u8> {
+        format!("{} {} {}", self.context, self.cookie.id, self.server_challenge).into_bytes()
+    }
+
+    /// Whether the client's `<client challenge> <digest>` proves it knows the cookie.
+    #[inline]
+    pub fn verify(&self, response: &[u8]) -> bool {
+        let response = match std::str::from_utf8(response) {
+            Ok(response) => response,
+            Err(_) => return false,
+        };
+        let mut fields = response.split(' ');
+        match (fields.next(), fields.next(), fields.next()) {
+            (Some(client_challenge), Some(response_digest), None) if !client_challenge.is_empty() && hex_decode(client_challenge).is_some() => {
+                let expected = digest(&self.server_challenge, client_challenge, &self.cookie.cookie);
+                constant_time_eq(expected.as_bytes(), response_digest.as_bytes())
+            }
+            _ => false,
+        }
+    }
+}
+
+/// A random challenge, hex encoded.
+pub(crate) fn challenge() -> io::Result<String> {
+    let mut bytes = [0; 16];
+    random(&mut bytes)?;
+    Ok(hex_encode(&bytes))
+}
+
+/// The hex encoded SHA-1 of `<server challenge>:<client challenge>:<cookie>`.
+pub(crate) fn digest(server_challenge: &str, client_challenge: &str, cookie: &str) -> String {
+    let mut sha1 = sha1_smol::Sha1::new();
+    sha1.update(format!("{}:{}:{}", server_challenge, client_challenge, cookie).as_bytes());
+    hex_encode(&sha1.digest().bytes())
+}
+
+/// Answers the server's `<context> <cookie id> <server challenge>` with
+/// `<client challenge> <digest>`.
+pub(crate) fn client_response(keyring: &Keyring, data: &[u8]) -> io::Result<Vec<u8>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Malformed DBUS_COOKIE_SHA1 challenge");
+    let data = std::str::from_utf8(data).map_err(|_| invalid())?;
+    let mut fields = data.split(' ');
+    let (context, id, server_challenge) = match (fields.next(), fields.next(), fields.next(), fields.next()) {
+        (Some(context), Some(id), Some(challenge), None) if hex_decode(challenge).is_some() => (context, id, challenge),
+        _ => return Err(invalid()),
+    };
+    let id = id.parse().map_err(|_| invalid())?;
+    let cookie = keyring.find(context, id)?;
+
+    let client_challenge = challenge()?;
+    let digest = digest(server_challenge, &client_challenge, &cookie.cookie);
+    Ok(format!("{} {}", client_challenge, digest).into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn temp_home(name: &str) -> PathBuf {
+        let home = std::env::temp_dir().join(format!("dbus-native-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&home);
+        fs::create_dir_all(home.join(KEYRING_DIR)).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(home.join(KEYRING_DIR), fs::Permissions::from_mode(0o700)).unwrap();
+        }
+        home
+    }
+
+    #[test]
+    fn contexts() {
+        assert!(is_valid_context("org_freedesktop_general"));
+        assert!(!is_valid_context(""));
+        assert!(!is_valid_context("../etc"));
+        assert!(!is_valid_context("a b"));
+        assert!(!is_valid_context("a\\b"));
+        assert!(!is_valid_context("kéy"));
+    }
+
+    #[test]
+    fn response() {
+        let home = temp_home("cookie-client");
+        let keyring = Keyring::in_home(&home);
+        fs::write(home.join(KEYRING_DIR).join("test"), "1 1600000000 0123\nmalformed\n7 1600000001 abcdef\n").unwrap();
+        assert_eq!(2, keyring.read("test").unwrap().len());
+        assert_eq!("abcdef", keyring.find("test", 7).unwrap().cookie);
+        assert_eq!(io::ErrorKind::NotFound, keyring.find("test", 8).unwrap_err().kind());
+        assert_eq!(io::ErrorKind::InvalidInput, keyring.find("../test", 7).unwrap_err().kind());
+
+        let response = String::from_utf8(client_response(&keyring, b"test 7 5eed").unwrap()).unwrap();
+        let (client_challenge, sha1) = response.split_at(response.find(' ').unwrap());
+        assert_eq!(32, client_challenge.len());
+        assert_eq!(digest("5eed", client_challenge, "abcdef"), sha1[1..]);
+        assert_eq!(io::ErrorKind::InvalidData, client_response(&keyring, b"test 7").unwrap_err().kind());
+        fs::remove_dir_all(&home).unwrap();
+    }
+
+    #[test]
+    fn known_digest() {
+        // sha1("a:b:c")
+        assert_eq!("70bce09e827a98fe6acf7c3e9b0bcf136bc382ed", digest("a", "b", "c"));
+    }
+
+    #[test]
+    fn server_verifies_client() {
+        let home = std::env::temp_dir().join(format!("dbus-native-cookie-server-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&home);
+        let keyring = Keyring::in_home(&home);
+        let challenge = CookieChallenge::new(&keyring, DEFAULT_CONTEXT).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(0o700, fs::metadata(keyring.dir()).unwrap().permissions().mode() & 0o777);
+            assert_eq!(0o600, fs::metadata(keyring.dir().join(DEFAULT_CONTEXT)).unwrap().permissions().mode() & 0o777);
+        }
+        assert!(!keyring.dir().join("org_freedesktop_general.lock").exists());
+
+        let response = client_response(&keyring, &challenge.data()).unwrap();
+        assert!(challenge.verify(&response));
+        let other = CookieChallenge::new(&keyring, DEFAULT_CONTEXT).unwrap();
+        assert_eq!(challenge.cookie, other.cookie);
+        assert!(!other.verify(&response));
+        assert!(!challenge.verify(b"00 0000"));
+        assert!(!challenge.verify(b"garbage"));
+        fs::remove_dir_all(&home).unwrap();
+    }
+
+    #[test]
+    fn rotation() {
+        let home = temp_home("cookie-rotation");
+        let keyring = Keyring::in_home(&home);
+        let now = 1_600_000_000;
+        let first = keyring.current_cookie_at("test", now).unwrap();
+        assert_eq!(first, keyring.current_cookie_at("test", now + NEW_COOKIE_TIMEOUT - 1).unwrap());
+
+        let second = keyring.current_cookie_at("test", now + NEW_COOKIE_TIMEOUT).unwrap();
+        assert_ne!(first.id, second.id);
+        assert_eq!(2, keyring.read("test").unwrap().len());
+
+        keyring.current_cookie_at("test", now + EXPIRE_COOKIE_TIMEOUT).unwrap();
+        assert_eq!(vec![second.clone()], keyring.read("test").unwrap());
+
+        // Cookies from the future are dropped after the clock was set back.
+        let past = keyring.current_cookie_at("test", now - MAX_TIME_TRAVEL - NEW_COOKIE_TIMEOUT - 1).unwrap();
+        assert_eq!(vec![past], keyring.read("test").unwrap());
+        fs::remove_dir_all(&home).unwrap();
+    }
+
+    #[test]
+    fn max_cookies() {
+        let home = temp_home("cookie-max");
+        let keyring = Keyring::in_home(&home);
+        let now = 1_600_000_000;
+        let content: String = (0..MAX_COOKIES as u64 + 10).map(|i| format!("{} {} 00\n", i, now - EXPIRE_COOKIE_TIMEOUT + 1 + i % 60)).collect();
+        fs::write(keyring.dir().join("test"), content).unwrap();
+        keyring.current_cookie_at("test", now).unwrap();
+        assert_eq!(MAX_COOKIES, keyring.read("test").unwrap().len());
+
+        // A full keyring of cookies from the near future keeps the new current cookie.
+        let content: String = (0..MAX_COOKIES as u64).map(|i| format!("{} {} 00\n", i, now + 1 + i % 60)).collect();
+        fs::write(keyring.dir().join("test"), content).unwrap();
+        let current = keyring.current_cookie_at("test", now).unwrap();
+        assert_eq!(now, current.created);
+        let cookies = keyring.read("test").unwrap();
+        assert_eq!(MAX_COOKIES, cookies.len());
+        assert!(cookies.contains(&current));
+        fs::remove_dir_all(&home).unwrap();
+    }
+
+    #[test]
+    fn other_user_keyring() {
+        let home = std::env::temp_dir().join(format!("dbus-native-cookie-other-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&home);
+        let keyring = Keyring { owner: current_uid().wrapping_add(1000), ..Keyring::in_home(&home) };
+        assert!(keyring.current_cookie("test").is_err());
+        assert!(!home.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shared_keyring_rejected() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let home = temp_home("cookie-shared");
+        let keyring = Keyring::in_home(&home);
+        fs::write(home.join(KEYRING_DIR).join("test"), "1 1600000000 0123\n").unwrap();
+        fs::set_permissions(keyring.dir(), fs::Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(io::ErrorKind::PermissionDenied, keyring.read("test").unwrap_err().kind());
+        fs::remove_dir_all(&home).unwrap();
+    }
+}
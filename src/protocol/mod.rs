@@ -0,0 +1,500 @@
+//! The authentication protocol preceding message exchange, a line-based SASL profile.
+//! https://dbus.freedesktop.org/doc/dbus-specification.html#auth-protocol
+//!
+//! [`ClientAuth`] is a state machine without any IO: bytes received from the server are
+//! passed to [`ClientAuth::receive`] and the bytes it queues in response are written
+//! with [`ClientAuth::take_output`], until it is authenticated and the connection is
+//! handed off to message mode. Only answering a DBUS_COOKIE_SHA1 challenge reads the
+//! cookie from the keyring file. [`ServerAuth`](server::ServerAuth) does no IO at all,
+//! it leaves creating the DBUS_COOKIE_SHA1 challenge, which locks and updates the
+//! keyring, to its caller.
+
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+pub mod codec;
+pub mod cookie;
+pub mod server;
+
+use self::codec::{AuthCodec, ParseError};
+use self::cookie::Keyring;
+use crate::guid::Guid;
+
+/// The maximum length of a line, including the CRLF.
+pub const MAX_LINE_LENGTH: usize = 16384;
+
+/// https://dbus.freedesktop.org/doc/dbus-specification.html#auth-mechanisms
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AuthMechanism {
+    /// The EXTERNAL mechanism is defined in RFC 4422 "Simple Authentication and Security Layer (SASL)",
+    /// appendix A "The SASL EXTERNAL Mechanism". This is the recommended authentication mechanism
+    /// on platforms where credentials can be transferred out-of-band,
+    /// in particular Unix platforms that can perform credentials-passing over the unix: transport.
+    External,
+    /// DBUS_COOKIE_SHA1 is a D-Bus-specific SASL mechanism.
+    /// Its reference implementation is part of the reference implementation of D-Bus.
+    DbusCookieSha1,
+    /// The ANONYMOUS mechanism is defined in RFC 4505
+    /// "Anonymous Simple Authentication and Security Layer (SASL) Mechanism".
+    /// It does not perform any authentication at all, and should not be accepted by message buses.
+    /// However, it might sometimes be useful for non-message-bus uses of D-Bus.
+    Anonymous,
+}
+
+impl AuthMechanism {
+    #[inline]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AuthMechanism::External => "EXTERNAL",
+            AuthMechanism::DbusCookieSha1 => "DBUS_COOKIE_SHA1",
+            AuthMechanism::Anonymous => "ANONYMOUS",
+        }
+    }
+}
+
+impl FromStr for AuthMechanism {
+    type Err = String;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "EXTERNAL" => Ok(AuthMechanism::External),
+            "DBUS_COOKIE_SHA1" => Ok(AuthMechanism::DbusCookieSha1),
+            "ANONYMOUS" => Ok(AuthMechanism::Anonymous),
+            _ => Err(s.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for AuthMechanism {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Protocol {
+    /// The AUTH command is sent by the client to the server. The server replies with DATA, OK or REJECTED.
+    /// If an AUTH command has no arguments, it is a request to list available mechanisms.
+    /// The server must respond with a REJECTED command listing the mechanisms it understands, or with an error.
+    Auth {
+        mechanism: Option<AuthMechanism>,
+        initial_response: Option<Vec<u8>>,
+    },
+    /// The CANCEL command is sent by the client to the server.
+    /// The server replies with REJECTED.
+    /// At any time up to sending the BEGIN command, the client may send a CANCEL command.
+    /// On receiving the CANCEL command, the server must send a REJECTED command and abort the current authentication exchange.
+    Cancel,
+    /// The BEGIN command is sent by the client to the server. The server does not reply.
+    Begin,
+    /// The DATA command may come from either client or server, and simply contains a hex-encoded block of data to be interpreted
+    /// according to the SASL mechanism in use. If sent by the client, the server replies with DATA, OK or REJECTED.
+    Data {
+        /// data, hex encoded on the wire
+        data: Vec<u8>,
+    },
+    Error {
+        /// human-readable error explanation
+        error_explanation: String,
+    },
+    /// The NEGOTIATE_UNIX_FD command is sent by the client to the server. The server replies with AGREE_UNIX_FD or ERROR.
+    /// The NEGOTIATE_UNIX_FD command indicates that the client supports Unix file descriptor passing.
+    NegotiateUnixFd,
+    /// The REJECTED command is sent by the server to the client, listing the mechanisms
+    /// it supports. Mechanisms not known to this implementation are left out.
+    Rejected {
+        mechanisms: Vec<AuthMechanism>,
+    },
+    /// The OK command is sent by the server to the client.
+    /// The OK command indicates that the client has been authenticated. The client may now proceed with negotiating Unix file descriptor passing.
+    Ok {
+        /// The GUID of the server, formatted as 32 hex digits.
+        guid: Guid,
+    },
+    /// The AGREE_UNIX_FD command is sent by the server to the client, in reply to NEGOTIATE_UNIX_FD.
+    AgreeUnixFd,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthError {
+    /// The server rejected every mechanism the client was willing to try.
+    Rejected(Vec<AuthMechanism>),
+    /// The peer sent a command not valid in the current state.
+    Protocol(String),
+    /// The peer sent a malformed line.
+    Parse(ParseError),
+    /// The client failed too many attempts to authenticate.
+    TooManyFailures,
+    /// Bytes were received after authentication completed.
+    Finished,
+}
+
+impl fmt::Display for AuthError {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuthError::Rejected(mechanisms) => {
+                write!(f, "authentication rejected, server supports")?;
+                for mechanism in mechanisms {
+                    write!(f, " {}", mechanism)?;
+                }
+                Ok(())
+            }
+            AuthError::Protocol(line) => write!(f, "unexpected line `{}`", line),
+            AuthError::Parse(err) => write!(f, "malformed line: {}", err),
+            AuthError::TooManyFailures => write!(f, "too many failed authentication attempts"),
+            AuthError::Finished => write!(f, "authentication already finished"),
+        }
+    }
+}
+
+impl Error for AuthError {
+    #[inline]
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            AuthError::Parse(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ClientState {
+    /// Waiting for OK, REJECTED, DATA or ERROR after AUTH or DATA.
+    WaitingForData,
+    /// Waiting for REJECTED after CANCEL.
+    WaitingForReject,
+    /// Waiting for AGREE_UNIX_FD or ERROR after NEGOTIATE_UNIX_FD.
+    WaitingForAgreeUnixFd,
+    /// BEGIN was sent, the connection is in message mode.
+    Authenticated,
+}
+
+/// The client side of authentication. Tries the given mechanisms in order, skipping those
+/// the server rejected, optionally negotiates Unix file descriptor passing, and ends by
+/// sending BEGIN.
+#[derive(Debug)]
+pub struct ClientAuth {
+    state: ClientState,
+    /// The decimal uid claimed with EXTERNAL and DBUS_COOKIE_SHA1.
+    identity: String,
+    /// The mechanisms left to try, the first one is in progress.
+    mechanisms: Vec<AuthMechanism>,
+    /// Defaults to the keyring of the current user.
+    keyring: Option<Keyring>,
+    negotiate_unix_fd: bool,
+    unix_fd: bool,
+    guid: Option<Guid>,
+    input: AuthCodec,
+    output: Vec<u8>,
+}
+
+impl ClientAuth {
+    /// A client authenticating as `uid` with the first of `mechanisms` the server accepts.
+    /// Its first output is the nul byte preceding authentication followed by AUTH.
+    ///
+    /// Panics if `mechanisms` is empty.
+    #[inline]
+    pub fn new(uid: u32, mechanisms: Vec<AuthMechanism>) -> ClientAuth {
+        assert!(!mechanisms.is_empty(), "no authentication mechanism to try");
+        let mut auth = ClientAuth {
+            state: ClientState::WaitingForData,
+            identity: uid.to_string(),
+            mechanisms,
+            keyring: None,
+            negotiate_unix_fd: false,
+            unix_fd: false,
+            guid: None,
+            input: AuthCodec::default(),
+            output: vec![0],
+        };
+        auth.start_mechanism();
+        auth
+    }
+
+    /// A client authenticating with EXTERNAL as `uid`, sending `AUTH EXTERNAL <hex uid>`.
+    #[inline]
+    pub fn external(uid: u32) -> ClientAuth {
+        ClientAuth::new(uid, vec![AuthMechanism::External])
+    }
+
+    /// A client authenticating with EXTERNAL as the effective uid of the current process.
+    #[cfg(unix)]
+    #[inline]
+    pub fn external_current_user() -> ClientAuth {
+        // SAFETY: geteuid has no preconditions and can not fail.
+        ClientAuth::external(unsafe { libc::geteuid() })
+    }
+
+    /// The keyring to read DBUS_COOKIE_SHA1 cookies from, instead of the one in `HOME`.
+    #[inline]
+    pub fn set_keyring(&mut self, keyring: Keyring) {
+        self.keyring = Some(keyring);
+    }
+
+    /// Whether to send NEGOTIATE_UNIX_FD once authenticated, for transports able to
+    /// pass file descriptors.
+    #[inline]
+    pub fn set_negotiate_unix_fd(&mut self, negotiate: bool) {
+        self.negotiate_unix_fd = negotiate;
+    }
+
+    /// Drops the leading nul byte from the output, for transports sending it themselves
+    /// along with credentials, see `UnixTransport::send_nul_byte`.
+    #[inline]
+    pub fn skip_nul_byte(&mut self) {
+        if self.output.first() == Some(&0) {
+            self.output.remove(0);
+        }
+    }
+
+    /// Takes the bytes to write to the server.
+    #[inline]
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+
+    /// Handles bytes received from the server, returning how many were consumed. Once
+    /// authenticated no more bytes are consumed, the remaining ones belong to messages.
+    #[inline]
+    pub fn receive(&mut self, bytes: &[u8]) -> Result<usize, AuthError> {
+        if self.is_authenticated() {
+            return if bytes.is_empty() { Ok(0) } else { Err(AuthError::Finished) };
+        }
+        let mut consumed = 0;
+        while !self.is_authenticated() && consumed < bytes.len() {
+            let (n, command) = self.input.decode(&bytes[consumed..]);
+            consumed += n;
+            if let Some(command) = command {
+                self.handle(command.map_err(AuthError::Parse)?)?;
+            }
+        }
+        Ok(consumed)
+    }
+
+    /// Whether BEGIN was sent and the connection is in message mode.
+    #[inline]
+    pub fn is_authenticated(&self) -> bool {
+        self.state == ClientState::Authenticated
+    }
+
+    /// The GUID of the server, known once it accepted the client.
+    #[inline]
+    pub fn guid(&self) -> Option<&Guid> {
+        self.guid.as_ref()
+    }
+
+    /// Whether the server agreed to pass Unix file descriptors.
+    #[inline]
+    pub fn unix_fd(&self) -> bool {
+        self.unix_fd
+    }
+
+    fn send(&mut self, command: Protocol) {
+        command.encode(&mut self.output);
+    }
+
+    fn start_mechanism(&mut self) {
+        let initial_response = match self.mechanisms[0] {
+            AuthMechanism::External | AuthMechanism::DbusCookieSha1 => Some(self.identity.as_bytes().to_vec()),
+            AuthMechanism::Anonymous => Some(b"dbus-native".to_vec()),
+        };
+        self.send(Protocol::Auth { mechanism: Some(self.mechanisms[0]), initial_response });
+        self.state = ClientState::WaitingForData;
+    }
+
+    /// Tries the next mechanism the server supports, or fails.
+    fn next_mechanism(&mut self, supported: Vec<AuthMechanism>) -> Result<(), AuthError> {
+        self.mechanisms.remove(0);
+        self.mechanisms.retain(|mechanism| supported.contains(mechanism));
+        if self.mechanisms.is_empty() {
+            return Err(AuthError::Rejected(supported));
+        }
+        self.start_mechanism();
+        Ok(())
+    }
+
+    fn handle(&mut self, command: Protocol) -> Result<(), AuthError> {
+        match (self.state, command) {
+            (ClientState::WaitingForData, Protocol::Ok { guid }) => {
+                self.guid = Some(guid);
+                if self.negotiate_unix_fd {
+                    self.send(Protocol::NegotiateUnixFd);
+                    self.state = ClientState::WaitingForAgreeUnixFd;
+                } else {
+                    self.begin();
+                }
+            }
+            (ClientState::WaitingForData, Protocol::Data { data }) => match self.respond(&data) {
+                Some(response) => self.send(Protocol::Data { data: response }),
+                None => {
+                    self.send(Protocol::Cancel);
+                    self.state = ClientState::WaitingForReject;
+                }
+            },
+            (ClientState::WaitingForData, Protocol::Error { .. }) => {
+                self.send(Protocol::Cancel);
+                self.state = ClientState::WaitingForReject;
+            }
+            (ClientState::WaitingForData, Protocol::Rejected { mechanisms }) | (ClientState::WaitingForReject, Protocol::Rejected { mechanisms }) => {
+                self.next_mechanism(mechanisms)?;
+            }
+            (ClientState::WaitingForAgreeUnixFd, Protocol::AgreeUnixFd) => {
+                self.unix_fd = true;
+                self.begin();
+            }
+            (ClientState::WaitingForAgreeUnixFd, Protocol::Error { .. }) => self.begin(),
+            (_, command) => {
+                let mut line = Vec::new();
+                command.encode(&mut line);
+                return Err(AuthError::Protocol(String::from_utf8_lossy(&line).trim_end().to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    /// The response to a challenge of the current mechanism, none if it can not answer.
+    fn respond(&self, challenge: &[u8]) -> Option<Vec<u8>> {
+        match self.mechanisms[0] {
+            AuthMechanism::DbusCookieSha1 => {
+                let keyring = match &self.keyring {
+                    Some(keyring) => keyring.clone(),
+                    None => Keyring::for_current_user().ok()?,
+                };
+                cookie::client_response(&keyring, challenge).ok()
+            }
+            // The identity was the initial response, there are no challenges.
+            AuthMechanism::External | AuthMechanism::Anonymous => None,
+        }
+    }
+
+    fn begin(&mut self) {
+        self.send(Protocol::Begin);
+        self.state = ClientState::Authenticated;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::codec::{hex_decode, hex_encode};
+    use super::*;
+
+    const GUID: &str = "1234deadbeef5678deadbeef9abcdef0";
+
+    #[test]
+    fn external() {
+        let mut auth = ClientAuth::external(1000);
+        assert_eq!(b"\0AUTH EXTERNAL 31303030\r\n", &auth.take_output()[..]);
+        assert!(auth.take_output().is_empty());
+
+        assert_eq!(10, auth.receive(b"OK 1234dea").unwrap());
+        assert!(auth.take_output().is_empty());
+        let rest = format!("{}\r\n\x6c\x01", &GUID[7..]);
+        assert_eq!(rest.len() - 2, auth.receive(rest.as_bytes()).unwrap());
+        assert_eq!(b"BEGIN\r\n", &auth.take_output()[..]);
+        assert!(auth.is_authenticated());
+        assert_eq!(Some(GUID), auth.guid().map(ToString::to_string).as_deref());
+        assert!(!auth.unix_fd());
+        assert_eq!(Err(AuthError::Finished), auth.receive(b"l"));
+    }
+
+    #[test]
+    fn negotiate_unix_fd() {
+        let mut auth = ClientAuth::external(0);
+        auth.set_negotiate_unix_fd(true);
+        auth.skip_nul_byte();
+        assert_eq!(b"AUTH EXTERNAL 30\r\n", &auth.take_output()[..]);
+        auth.receive(format!("OK {}\r\n", GUID).as_bytes()).unwrap();
+        assert_eq!(b"NEGOTIATE_UNIX_FD\r\n", &auth.take_output()[..]);
+        assert!(!auth.is_authenticated());
+        auth.receive(b"AGREE_UNIX_FD\r\n").unwrap();
+        assert_eq!(b"BEGIN\r\n", &auth.take_output()[..]);
+        assert!(auth.unix_fd());
+
+        let mut auth = ClientAuth::external(0);
+        auth.set_negotiate_unix_fd(true);
+        auth.receive(format!("OK {}\r\nERROR not supported\r\n", GUID).as_bytes()).unwrap();
+        assert_eq!(b"\0AUTH EXTERNAL 30\r\nNEGOTIATE_UNIX_FD\r\nBEGIN\r\n", &auth.take_output()[..]);
+        assert!(auth.is_authenticated());
+        assert!(!auth.unix_fd());
+    }
+
+    #[test]
+    fn rejected() {
+        let mut auth = ClientAuth::external(1000);
+        auth.take_output();
+        let err = auth.receive(b"REJECTED DBUS_COOKIE_SHA1 KERBEROS_V4\r\n").unwrap_err();
+        assert_eq!(AuthError::Rejected(vec![AuthMechanism::DbusCookieSha1]), err);
+        assert_eq!("authentication rejected, server supports DBUS_COOKIE_SHA1", err.to_string());
+    }
+
+    #[test]
+    fn data_and_error_cancel() {
+        let mut auth = ClientAuth::external(1000);
+        auth.take_output();
+        auth.receive(b"DATA\r\n").unwrap();
+        assert_eq!(b"CANCEL\r\n", &auth.take_output()[..]);
+        assert!(matches!(auth.receive(b"REJECTED EXTERNAL\r\n"), Err(AuthError::Rejected(_))));
+
+        let mut auth = ClientAuth::external(1000);
+        auth.take_output();
+        auth.receive(b"ERROR unknown command\r\n").unwrap();
+        assert_eq!(b"CANCEL\r\n", &auth.take_output()[..]);
+        assert_eq!(Err(AuthError::Protocol("AGREE_UNIX_FD".to_string())), auth.receive(b"AGREE_UNIX_FD\r\n"));
+    }
+
+    #[test]
+    fn cookie_sha1_after_external() {
+        let home = std::env::temp_dir().join(format!("dbus-native-cookie-auth-{}", std::process::id()));
+        let dir = home.join(cookie::KEYRING_DIR);
+        std::fs::create_dir_all(&dir).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o700)).unwrap();
+        }
+        std::fs::write(dir.join("org_freedesktop_general"), "3 1600000000 c00c1e\n").unwrap();
+
+        let mut auth = ClientAuth::new(1000, vec![AuthMechanism::External, AuthMechanism::DbusCookieSha1]);
+        auth.set_keyring(Keyring::in_home(&home));
+        assert_eq!(b"\0AUTH EXTERNAL 31303030\r\n", &auth.take_output()[..]);
+        auth.receive(b"REJECTED DBUS_COOKIE_SHA1 ANONYMOUS\r\n").unwrap();
+        assert_eq!(b"AUTH DBUS_COOKIE_SHA1 31303030\r\n", &auth.take_output()[..]);
+
+        let challenge = format!("DATA {}\r\n", hex_encode(b"org_freedesktop_general 3 0badc0de"));
+        auth.receive(challenge.as_bytes()).unwrap();
+        let output = String::from_utf8(auth.take_output()).unwrap();
+        let response = String::from_utf8(hex_decode(output.trim_end().strip_prefix("DATA ").unwrap()).unwrap()).unwrap();
+        let (client_challenge, digest) = response.split_at(response.find(' ').unwrap());
+        assert_eq!(cookie::digest("0badc0de", client_challenge, "c00c1e"), digest[1..]);
+
+        auth.receive(format!("OK {}\r\n", GUID).as_bytes()).unwrap();
+        assert!(auth.is_authenticated());
+
+        // A challenge for a cookie missing from the keyring is cancelled.
+        let mut auth = ClientAuth::new(1000, vec![AuthMechanism::DbusCookieSha1]);
+        auth.set_keyring(Keyring::in_home(&home));
+        auth.take_output();
+        auth.receive(format!("DATA {}\r\n", hex_encode(b"org_freedesktop_general 4 0badc0de")).as_bytes()).unwrap();
+        assert_eq!(b"CANCEL\r\n", &auth.take_output()[..]);
+        std::fs::remove_dir_all(&home).unwrap();
+    }
+
+    #[test]
+    fn malformed_lines() {
+        let parse_error = |bytes: &[u8]| match ClientAuth::external(0).receive(bytes) {
+            Err(AuthError::Parse(err)) => err,
+            result => panic!("unexpected result {:?}", result),
+        };
+        assert_eq!(ParseError::InvalidGuid("nothex".to_string()), parse_error(b"OK nothex\r\n"));
+        assert_eq!(ParseError::InvalidHex("DATA"), parse_error(b"DATA 123\r\n"));
+        assert_eq!(ParseError::UnknownCommand("HELLO".to_string()), parse_error(b"HELLO\r\n"));
+        assert_eq!(ParseError::MissingCr, parse_error(b"OK\n"));
+        assert_eq!(ParseError::LineTooLong, parse_error(&[b'A'; MAX_LINE_LENGTH + 1]));
+        assert_eq!(Err(AuthError::Protocol("BEGIN".to_string())), ClientAuth::external(0).receive(b"BEGIN\r\n"));
+    }
+}
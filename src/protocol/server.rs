@@ -0,0 +1,540 @@
+//! The server side of authentication, accepting clients according to an [`AuthPolicy`].
+
+use super::cookie::{CookieChallenge, Keyring, DEFAULT_CONTEXT};
+use super::codec::{AuthCodec, ParseError};
+use super::{AuthError, AuthMechanism, Protocol, MAX_LINE_LENGTH};
+use crate::guid::Guid;
+use std::io;
+
+/// Which clients a server accepts.
+#[derive(Debug, Clone)]
+pub struct AuthPolicy {
+    /// The mechanisms offered, in the order advertised with REJECTED.
+    pub mechanisms: Vec<AuthMechanism>,
+    /// The uids allowed to authenticate with EXTERNAL or DBUS_COOKIE_SHA1, any if none.
+    /// ANONYMOUS clients are accepted regardless if it is offered.
+    pub allowed_uids: Option<Vec<u32>>,
+    /// Whether to answer NEGOTIATE_UNIX_FD with AGREE_UNIX_FD, for transports able to
+    /// pass file descriptors.
+    pub unix_fd: bool,
+    /// Failed attempts and invalid commands after which the client is disconnected.
+    pub max_failures: u32,
+    pub max_line_length: usize,
+    /// The keyring DBUS_COOKIE_SHA1 cookies are kept in, only used for clients claiming to
+    /// be its owner. If none, the keyring of the claimed user is used.
+    pub keyring: Option<Keyring>,
+    pub cookie_context: String,
+}
+
+impl Default for AuthPolicy {
+    /// Offers EXTERNAL to any uid.
+    #[inline]
+    fn default() -> AuthPolicy {
+        AuthPolicy {
+            mechanisms: vec![AuthMechanism::External],
+            allowed_uids: None,
+            unix_fd: false,
+            max_failures: 8,
+            max_line_length: MAX_LINE_LENGTH,
+            keyring: None,
+            cookie_context: DEFAULT_CONTEXT.to_string(),
+        }
+    }
+}
+
+impl AuthPolicy {
+    fn allows_uid(&self, uid: u32) -> bool {
+        self.allowed_uids.as_ref().is_none_or(|uids| uids.contains(&uid))
+    }
+}
+
+#[derive(Debug)]
+enum ServerState {
+    /// Waiting for the nul byte preceding authentication.
+    WaitingForNulByte,
+    WaitingForAuth,
+    /// Waiting for the caller to create the DBUS_COOKIE_SHA1 challenge.
+    WaitingForCookie(CookieRequest),
+    /// Waiting for DATA answering a challenge of the mechanism in progress.
+    WaitingForData(Challenge),
+    /// The client was accepted with OK, waiting for BEGIN.
+    WaitingForBegin,
+    /// BEGIN was received, the connection is in message mode.
+    Authenticated,
+}
+
+#[derive(Debug)]
+enum Challenge {
+    /// EXTERNAL without initial response, the identity is sent as DATA.
+    External,
+    Cookie { uid: u32, challenge: CookieChallenge },
+}
+
+/// A DBUS_COOKIE_SHA1 challenge to create before authentication can go on, which reads
+/// and possibly updates the keyring of the claimed user.
+#[derive(Debug, Clone)]
+pub struct CookieRequest {
+    uid: u32,
+    keyring: Option<Keyring>,
+    context: String,
+}
+
+impl CookieRequest {
+    /// The uid the client claims to be.
+    #[inline]
+    pub fn uid(&self) -> u32 {
+        self.uid
+    }
+
+    /// Creates the challenge with the current cookie of the keyring, looking up the home
+    /// directory of the claimed user if the policy names no keyring. This blocks on disk
+    /// IO and for up to several seconds on the lock of a keyring other processes hold.
+    #[inline]
+    pub fn challenge(&self) -> io::Result<CookieChallenge> {
+        let keyring = match &self.keyring {
+            Some(keyring) => keyring.clone(),
+            None => Keyring::for_user(self.uid)?,
+        };
+        CookieChallenge::new(&keyring, &self.context)
+    }
+}
+
+/// The server side of authentication, without any IO like
+/// [`ClientAuth`](super::ClientAuth). Bytes received from the client are passed to
+/// [`receive`](ServerAuth::receive) and the responses it queues are written with
+/// [`take_output`](ServerAuth::take_output), until BEGIN is received. The keyring of
+/// DBUS_COOKIE_SHA1 is left to the caller, see [`cookie_request`](ServerAuth::cookie_request).
+#[derive(Debug)]
+pub struct ServerAuth {
+    policy: AuthPolicy,
+    guid: Guid,
+    peer_uid: Option<u32>,
+    state: ServerState,
+    failures: u32,
+    uid: Option<u32>,
+    mechanism: Option<AuthMechanism>,
+    unix_fd: bool,
+    input: AuthCodec,
+    output: Vec<u8>,
+}
+
+impl ServerAuth {
+    /// A server identified by `guid` accepting clients according to `policy`. The uid of
+    /// the peer from its credentials is required for EXTERNAL.
+    #[inline]
+    pub fn new(guid: Guid, policy: AuthPolicy, peer_uid: Option<u32>) -> ServerAuth {
+        ServerAuth {
+            input: AuthCodec::new(policy.max_line_length),
+            policy,
+            guid,
+            peer_uid,
+            state: ServerState::WaitingForNulByte,
+            failures: 0,
+            uid: None,
+            mechanism: None,
+            unix_fd: false,
+            output: Vec::new(),
+        }
+    }
+
+    /// Expects no nul byte, for transports receiving it themselves along with
+    /// credentials, see `UnixTransport::recv_nul_byte`.
+    #[inline]
+    pub fn skip_nul_byte(&mut self) {
+        if let ServerState::WaitingForNulByte = self.state {
+            self.state = ServerState::WaitingForAuth;
+        }
+    }
+
+    /// Takes the bytes to write to the client.
+    #[inline]
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+
+    /// Handles bytes received from the client, returning how many were consumed. Once
+    /// BEGIN is received no more bytes are consumed, the remaining ones belong to messages.
+    /// Neither are they while a [`cookie_request`](ServerAuth::cookie_request) is pending,
+    /// the remaining ones are to be passed again once it is answered.
+    /// Errors are fatal, the connection is to be closed.
+    #[inline]
+    pub fn receive(&mut self, bytes: &[u8]) -> Result<usize, AuthError> {
+        let mut consumed = 0;
+        if let ServerState::WaitingForNulByte = self.state {
+            match bytes.first() {
+                Some(0) => {
+                    consumed = 1;
+                    self.state = ServerState::WaitingForAuth;
+                }
+                Some(_) => return Err(AuthError::Protocol("expected a nul byte".to_string())),
+                None => return Ok(0),
+            }
+        }
+        if self.is_authenticated() {
+            return if bytes.is_empty() { Ok(0) } else { Err(AuthError::Finished) };
+        }
+        while !self.is_authenticated() && self.cookie_request().is_none() && consumed < bytes.len() {
+            let (n, command) = self.input.decode(&bytes[consumed..]);
+            consumed += n;
+            match command {
+                Some(Ok(command)) => self.handle(command)?,
+                Some(Err(ParseError::LineTooLong)) => return Err(AuthError::Parse(ParseError::LineTooLong)),
+                // Answered like AUTH without mechanism, listing the supported ones.
+                Some(Err(ParseError::UnknownMechanism(_))) if matches!(self.state, ServerState::WaitingForAuth) => self.reject()?,
+                Some(Err(err)) => self.error(&err.to_string())?,
+                None => {}
+            }
+        }
+        Ok(consumed)
+    }
+
+    /// The DBUS_COOKIE_SHA1 challenge to create, e.g. on a thread allowed to block, and
+    /// hand to [`set_cookie_challenge`](ServerAuth::set_cookie_challenge) before more
+    /// bytes are consumed.
+    #[inline]
+    pub fn cookie_request(&self) -> Option<&CookieRequest> {
+        match &self.state {
+            ServerState::WaitingForCookie(request) => Some(request),
+            _ => None,
+        }
+    }
+
+    /// Continues DBUS_COOKIE_SHA1 with the challenge created for the pending
+    /// [`cookie_request`](ServerAuth::cookie_request), rejecting the client if it could
+    /// not be created. Does nothing if no challenge is requested.
+    #[inline]
+    pub fn set_cookie_challenge(&mut self, challenge: io::Result<CookieChallenge>) -> Result<(), AuthError> {
+        let uid = match &self.state {
+            ServerState::WaitingForCookie(request) => request.uid,
+            _ => return Ok(()),
+        };
+        match challenge {
+            Ok(challenge) => {
+                self.send(Protocol::Data { data: challenge.data() });
+                self.state = ServerState::WaitingForData(Challenge::Cookie { uid, challenge });
+                Ok(())
+            }
+            Err(_) => self.reject(),
+        }
+    }
+
+    /// Whether BEGIN was received and the connection is in message mode.
+    #[inline]
+    pub fn is_authenticated(&self) -> bool {
+        matches!(self.state, ServerState::Authenticated)
+    }
+
+    /// The uid the client authenticated as, none for ANONYMOUS.
+    #[inline]
+    pub fn uid(&self) -> Option<u32> {
+        self.uid
+    }
+
+    /// The mechanism the client authenticated with.
+    #[inline]
+    pub fn mechanism(&self) -> Option<AuthMechanism> {
+        self.mechanism
+    }
+
+    /// Whether file descriptor passing was agreed on.
+    #[inline]
+    pub fn unix_fd(&self) -> bool {
+        self.unix_fd
+    }
+
+    fn send(&mut self, command: Protocol) {
+        command.encode(&mut self.output);
+    }
+
+    fn handle(&mut self, command: Protocol) -> Result<(), AuthError> {
+        let state = std::mem::replace(&mut self.state, ServerState::WaitingForAuth);
+        match (state, command) {
+            (ServerState::WaitingForAuth, Protocol::Auth { mechanism: None, .. }) => self.reject(),
+            (ServerState::WaitingForAuth, Protocol::Auth { mechanism: Some(mechanism), initial_response }) => {
+                if !self.policy.mechanisms.contains(&mechanism) {
+                    return self.reject();
+                }
+                self.start(mechanism, initial_response)
+            }
+            (ServerState::WaitingForData(challenge), Protocol::Data { data }) => self.answer(challenge, data),
+            (ServerState::WaitingForData(_), Protocol::Cancel) | (ServerState::WaitingForData(_), Protocol::Error { .. }) => self.reject(),
+            (ServerState::WaitingForBegin, Protocol::Cancel) | (ServerState::WaitingForBegin, Protocol::Error { .. }) => {
+                self.uid = None;
+                self.mechanism = None;
+                self.reject()
+            }
+            (ServerState::WaitingForAuth, Protocol::Cancel) | (ServerState::WaitingForAuth, Protocol::Error { .. }) => self.reject(),
+            (ServerState::WaitingForBegin, Protocol::NegotiateUnixFd) => {
+                self.state = ServerState::WaitingForBegin;
+                if self.policy.unix_fd {
+                    self.unix_fd = true;
+                    self.send(Protocol::AgreeUnixFd);
+                } else {
+                    self.send(Protocol::Error { error_explanation: "Unix file descriptor passing is not supported".to_string() });
+                }
+                Ok(())
+            }
+            (ServerState::WaitingForBegin, Protocol::Begin) => {
+                self.state = ServerState::Authenticated;
+                Ok(())
+            }
+            (ServerState::WaitingForAuth, Protocol::Begin) | (ServerState::WaitingForData(_), Protocol::Begin) => Err(AuthError::Protocol("BEGIN before authentication".to_string())),
+            (state, _) => {
+                self.state = state;
+                self.error("Command not valid in the current state")
+            }
+        }
+    }
+
+    fn start(&mut self, mechanism: AuthMechanism, initial_response: Option<Vec<u8>>) -> Result<(), AuthError> {
+        match (mechanism, initial_response) {
+            (AuthMechanism::External, Some(identity)) => self.external(&identity),
+            (AuthMechanism::External, None) => {
+                self.send(Protocol::Data { data: Vec::new() });
+                self.state = ServerState::WaitingForData(Challenge::External);
+                Ok(())
+            }
+            (AuthMechanism::DbusCookieSha1, Some(identity)) => {
+                let uid = match parse_uid(&identity) {
+                    Some(uid) if self.policy.allows_uid(uid) => uid,
+                    _ => return self.reject(),
+                };
+                // Reading the cookie only proves the identity of the owner of the keyring.
+                let keyring = match &self.policy.keyring {
+                    Some(keyring) if keyring.owner() == uid => Some(keyring.clone()),
+                    Some(_) => return self.reject(),
+                    None => None,
+                };
+                self.state = ServerState::WaitingForCookie(CookieRequest { uid, keyring, context: self.policy.cookie_context.clone() });
+                Ok(())
+            }
+            (AuthMechanism::DbusCookieSha1, None) => self.reject(),
+            (AuthMechanism::Anonymous, _) => self.accept(AuthMechanism::Anonymous, None),
+        }
+    }
+
+    fn answer(&mut self, challenge: Challenge, data: Vec<u8>) -> Result<(), AuthError> {
+        match challenge {
+            Challenge::External => self.external(&data),
+            Challenge::Cookie { uid, challenge } if challenge.verify(&data) => self.accept(AuthMechanism::DbusCookieSha1, Some(uid)),
+            Challenge::Cookie { .. } => self.reject(),
+        }
+    }
+
+    /// Accepts the claimed identity if it matches the peer's credentials, an empty
+    /// identity claiming whatever they are.
+    fn external(&mut self, identity: &[u8]) -> Result<(), AuthError> {
+        let uid = match (self.peer_uid, identity.is_empty()) {
+            (Some(peer_uid), true) => Some(peer_uid),
+            (Some(peer_uid), false) => parse_uid(identity).filter(|&uid| uid == peer_uid),
+            (None, _) => None,
+        };
+        match uid {
+            Some(uid) if self.policy.allows_uid(uid) => self.accept(AuthMechanism::External, Some(uid)),
+            _ => self.reject(),
+        }
+    }
+
+    fn accept(&mut self, mechanism: AuthMechanism, uid: Option<u32>) -> Result<(), AuthError> {
+        self.uid = uid;
+        self.mechanism = Some(mechanism);
+        self.send(Protocol::Ok { guid: self.guid });
+        self.state = ServerState::WaitingForBegin;
+        Ok(())
+    }
+
+    /// Sends REJECTED with the offered mechanisms, counting a failed attempt. Listing the
+    /// mechanisms with a bare AUTH counts too, so no command can be repeated forever.
+    fn reject(&mut self) -> Result<(), AuthError> {
+        self.fail()?;
+        self.send(Protocol::Rejected { mechanisms: self.policy.mechanisms.clone() });
+        self.state = ServerState::WaitingForAuth;
+        Ok(())
+    }
+
+    fn error(&mut self, explanation: &str) -> Result<(), AuthError> {
+        self.fail()?;
+        self.send(Protocol::Error { error_explanation: explanation.to_string() });
+        Ok(())
+    }
+
+    fn fail(&mut self) -> Result<(), AuthError> {
+        self.failures += 1;
+        if self.failures >= self.policy.max_failures {
+            return Err(AuthError::TooManyFailures);
+        }
+        Ok(())
+    }
+}
+
+/// A uid claimed as identity, in decimal.
+fn parse_uid(identity: &[u8]) -> Option<u32> {
+    std::str::from_utf8(identity).ok().filter(|s| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()))?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::protocol::ClientAuth;
+
+    const GUID: &str = "1234deadbeef5678deadbeef9abcdef0";
+
+    /// Runs `client` against `server` until neither has anything to send.
+    fn run(client: &mut ClientAuth, server: &mut ServerAuth) -> Result<(), AuthError> {
+        loop {
+            let to_server = client.take_output();
+            let n = server.receive(&to_server)?;
+            assert_eq!(to_server.len(), n);
+            if let Some(request) = server.cookie_request() {
+                let challenge = request.challenge();
+                server.set_cookie_challenge(challenge)?;
+            }
+            let to_client = server.take_output();
+            if to_server.is_empty() && to_client.is_empty() {
+                return Ok(());
+            }
+            client.receive(&to_client)?;
+        }
+    }
+
+    #[test]
+    fn external() {
+        let mut client = ClientAuth::external(1000);
+        client.set_negotiate_unix_fd(true);
+        let mut server = ServerAuth::new(GUID.parse().unwrap(), AuthPolicy { unix_fd: true, ..AuthPolicy::default() }, Some(1000));
+        run(&mut client, &mut server).unwrap();
+        assert!(client.is_authenticated() && server.is_authenticated());
+        assert_eq!(Some(GUID), client.guid().map(ToString::to_string).as_deref());
+        assert!(client.unix_fd() && server.unix_fd());
+        assert_eq!(Some(1000), server.uid());
+        assert_eq!(Some(AuthMechanism::External), server.mechanism());
+    }
+
+    #[test]
+    fn external_wrong_uid() {
+        let mut client = ClientAuth::external(0);
+        let mut server = ServerAuth::new(GUID.parse().unwrap(), AuthPolicy::default(), Some(1000));
+        assert_eq!(Err(AuthError::Rejected(vec![AuthMechanism::External])), run(&mut client, &mut server));
+
+        let mut client = ClientAuth::external(1000);
+        let policy = AuthPolicy { allowed_uids: Some(vec![0]), ..AuthPolicy::default() };
+        let mut server = ServerAuth::new(GUID.parse().unwrap(), policy, Some(1000));
+        assert!(matches!(run(&mut client, &mut server), Err(AuthError::Rejected(_))));
+
+        let mut client = ClientAuth::external(1000);
+        let mut server = ServerAuth::new(GUID.parse().unwrap(), AuthPolicy::default(), None);
+        assert!(matches!(run(&mut client, &mut server), Err(AuthError::Rejected(_))));
+    }
+
+    #[test]
+    fn unix_fd_refused() {
+        let mut client = ClientAuth::external(1000);
+        client.set_negotiate_unix_fd(true);
+        let mut server = ServerAuth::new(GUID.parse().unwrap(), AuthPolicy::default(), Some(1000));
+        run(&mut client, &mut server).unwrap();
+        assert!(client.is_authenticated() && server.is_authenticated());
+        assert!(!client.unix_fd() && !server.unix_fd());
+    }
+
+    #[test]
+    fn cookie_sha1_fallback() {
+        let home = std::env::temp_dir().join(format!("dbus-native-cookie-acceptor-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&home);
+        let keyring = Keyring::in_home(&home);
+        let uid = keyring.owner();
+        let mut client = ClientAuth::new(uid, vec![AuthMechanism::External, AuthMechanism::DbusCookieSha1]);
+        client.set_keyring(keyring.clone());
+        let policy = AuthPolicy {
+            mechanisms: vec![AuthMechanism::DbusCookieSha1, AuthMechanism::Anonymous],
+            keyring: Some(keyring),
+            ..AuthPolicy::default()
+        };
+        let mut server = ServerAuth::new(GUID.parse().unwrap(), policy, None);
+        run(&mut client, &mut server).unwrap();
+        assert!(server.is_authenticated());
+        assert_eq!(Some(uid), server.uid());
+        assert_eq!(Some(AuthMechanism::DbusCookieSha1), server.mechanism());
+        std::fs::remove_dir_all(&home).unwrap();
+    }
+
+    #[test]
+    fn cookie_sha1_not_keyring_owner() {
+        let home = std::env::temp_dir().join(format!("dbus-native-cookie-owner-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&home);
+        let keyring = Keyring::in_home(&home);
+        let uid = keyring.owner().wrapping_add(1000);
+        let mut client = ClientAuth::new(uid, vec![AuthMechanism::DbusCookieSha1]);
+        client.set_keyring(keyring.clone());
+        let policy = AuthPolicy { mechanisms: vec![AuthMechanism::DbusCookieSha1], keyring: Some(keyring), ..AuthPolicy::default() };
+        let mut server = ServerAuth::new(GUID.parse().unwrap(), policy, None);
+        assert!(matches!(run(&mut client, &mut server), Err(AuthError::Rejected(_))));
+        assert!(!server.is_authenticated() && server.uid().is_none());
+        let _ = std::fs::remove_dir_all(&home);
+    }
+
+    #[test]
+    fn cookie_request() {
+        let mut server = ServerAuth::new(GUID.parse().unwrap(), AuthPolicy { mechanisms: vec![AuthMechanism::DbusCookieSha1], ..AuthPolicy::default() }, None);
+        let auth = b"\0AUTH DBUS_COOKIE_SHA1 31303030\r\n";
+        let mut bytes = auth.to_vec();
+        bytes.extend_from_slice(b"DATA\r\n");
+        assert_eq!(auth.len(), server.receive(&bytes).unwrap());
+        assert!(server.take_output().is_empty());
+        assert_eq!(Some(1000), server.cookie_request().map(CookieRequest::uid));
+        assert_eq!(0, server.receive(b"DATA\r\n").unwrap());
+
+        let err = io::Error::new(io::ErrorKind::NotFound, "no keyring");
+        server.set_cookie_challenge(Err(err)).unwrap();
+        assert!(server.cookie_request().is_none());
+        assert_eq!(b"REJECTED DBUS_COOKIE_SHA1\r\n".to_vec(), server.take_output());
+    }
+
+    #[test]
+    fn anonymous() {
+        let mut client = ClientAuth::new(1000, vec![AuthMechanism::Anonymous]);
+        let policy = AuthPolicy { mechanisms: vec![AuthMechanism::Anonymous], ..AuthPolicy::default() };
+        let mut server = ServerAuth::new(GUID.parse().unwrap(), policy, None);
+        run(&mut client, &mut server).unwrap();
+        assert!(server.is_authenticated());
+        assert_eq!(None, server.uid());
+
+        let mut client = ClientAuth::new(1000, vec![AuthMechanism::Anonymous]);
+        let mut server = ServerAuth::new(GUID.parse().unwrap(), AuthPolicy::default(), None);
+        assert!(matches!(run(&mut client, &mut server), Err(AuthError::Rejected(_))));
+    }
+
+    #[test]
+    fn commands() {
+        let mut server = ServerAuth::new(GUID.parse().unwrap(), AuthPolicy::default(), Some(1000));
+        assert_eq!(Err(AuthError::Protocol("expected a nul byte".to_string())), server.receive(b"AUTH\r\n"));
+
+        let mut server = ServerAuth::new(GUID.parse().unwrap(), AuthPolicy::default(), Some(1000));
+        server.receive(b"\0AUTH\r\nAUTH KERBEROS_V4 00\r\nHELLO\r\nAUTH EXTERNAL\r\n").unwrap();
+        assert_eq!(b"REJECTED EXTERNAL\r\nREJECTED EXTERNAL\r\nERROR unknown command `HELLO`\r\nDATA\r\n", &server.take_output()[..]);
+        assert_eq!(13, server.receive(b"DATA\r\nBEGIN\r\nl\x01").unwrap());
+        assert!(server.is_authenticated());
+        assert_eq!(Some(1000), server.uid());
+        assert_eq!(format!("OK {}\r\n", GUID).as_bytes(), &server.take_output()[..]);
+    }
+
+    #[test]
+    fn limits() {
+        let policy = AuthPolicy { max_failures: 3, ..AuthPolicy::default() };
+        let mut server = ServerAuth::new(GUID.parse().unwrap(), policy, Some(1000));
+        server.skip_nul_byte();
+        assert_eq!(Err(AuthError::TooManyFailures), server.receive(b"AUTH EXTERNAL 30\r\nAUTH EXTERNAL 30\r\nAUTH EXTERNAL 30\r\n"));
+
+        // Unknown mechanisms, bare AUTH and CANCEL before AUTH are failures as well.
+        for command in &[&b"AUTH FOO\r\n"[..], b"AUTH\r\n", b"CANCEL\r\n", b"ERROR\r\n"] {
+            let mut server = ServerAuth::new(GUID.parse().unwrap(), AuthPolicy::default(), Some(1000));
+            server.skip_nul_byte();
+            let result = (0..8).try_for_each(|_| server.receive(command).map(|_| ()));
+            assert_eq!(Err(AuthError::TooManyFailures), result);
+        }
+
+        let policy = AuthPolicy { max_line_length: 16, ..AuthPolicy::default() };
+        let mut server = ServerAuth::new(GUID.parse().unwrap(), policy, Some(1000));
+        assert_eq!(Err(AuthError::Parse(ParseError::LineTooLong)), server.receive(b"\0AUTH EXTERNAL 31303030\r\n"));
+    }
+}
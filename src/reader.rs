@@ -1,39 +1,177 @@
-use std::io;
+use std::{fmt, io, str};
+use std::io::Read;
+use std::str::FromStr;
 use byteorder::{ReadBytesExt, ByteOrder};
-use crate::type_system::{ObjectPath, Signature};
-use crate::writer::DbusWrite;
+use crate::message::MAX_MESSAGE_SIZE;
+use crate::type_system::{FixedSizeType, ObjectPath, ObjectPathError, Signature, SignatureError, Type, UnixFd, Value};
+use crate::writer::{padding, MAX_ARRAY_LENGTH};
 
 type Result<T> = std::result::Result<T, std::io::Error>;
 
+/// The maximum total depth of container nesting, 32 array type codes and 32 open parentheses.
+const MAX_VALUE_DEPTH: usize = 64;
+
+/// Bytes allocated up front for array data, before knowing it arrives in full.
+const MAX_INITIAL_CAPACITY: usize = 4096;
+
 pub trait DbusRead<T> {
     fn read<T1, T2>(&self, reader: &mut DbusReader<T1>) -> Result<T>
         where T1: io::Read,
               T2: ByteOrder;
 }
 
+/// How the content of strings, object paths and signatures is checked when it is read.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum Validation {
+    /// Invalid content is rejected with an `InvalidData` error wrapping a [`StringError`].
+    #[default]
+    Strict,
+    /// Invalid content is accepted, with invalid UTF-8 replaced by U+FFFD in the value.
+    /// The raw bytes are kept in [`DbusReader::invalid_strings`], e.g. for forensic tools.
+    Lenient,
+}
+
+/// Why the content of a string, object path or signature is invalid.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StringError {
+    /// The string data contains a nul byte at the given index.
+    InteriorNul(usize),
+    /// The string data is not valid UTF-8.
+    InvalidUtf8(str::Utf8Error),
+    InvalidObjectPath(ObjectPathError),
+    InvalidSignature(SignatureError),
+}
+
+impl fmt::Display for StringError {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StringError::InteriorNul(index) => write!(f, "interior nul byte at index {}", index),
+            StringError::InvalidUtf8(err) => write!(f, "invalid UTF-8: {}", err),
+            StringError::InvalidObjectPath(err) => write!(f, "invalid object path: {}", err),
+            StringError::InvalidSignature(err) => write!(f, "invalid signature: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for StringError {
+    #[inline]
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StringError::InteriorNul(_) => None,
+            StringError::InvalidUtf8(err) => Some(err),
+            StringError::InvalidObjectPath(err) => Some(err),
+            StringError::InvalidSignature(err) => Some(err),
+        }
+    }
+}
+
+/// A string that failed validation but was accepted by a lenient reader.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InvalidString {
+    /// Position of the string data, after its length prefix.
+    pub position: usize,
+    /// The string data as read, excluding the terminating nul.
+    pub bytes: Vec<u8>,
+    pub error: StringError,
+}
+
 pub struct DbusReader<T: io::Read> {
     reader: T,
+    /// Number of bytes read so far, used to skip alignment padding.
+    position: usize,
+    validation: Validation,
+    invalid_strings: Vec<InvalidString>,
 }
 
 impl<T: io::Read> DbusReader<T> {
+    #[inline]
     pub fn new(reader: T) -> DbusReader<T> {
+        DbusReader::with_position(reader, 0)
+    }
+
+    /// Creates a reader that continues unmarshaling at `position`, e.g. for reading
+    /// a message body after a header that has been read separately.
+    #[inline]
+    pub fn with_position(reader: T, position: usize) -> DbusReader<T> {
         DbusReader {
-            reader
+            reader,
+            position,
+            validation: Validation::default(),
+            invalid_strings: Vec::new(),
         }
     }
 
+    #[inline]
+    pub fn validation(&self) -> Validation {
+        self.validation
+    }
+
+    #[inline]
+    pub fn set_validation(&mut self, validation: Validation) {
+        self.validation = validation;
+    }
+
+    /// The strings accepted despite failing validation, in the order they were read.
+    /// Always empty in `Validation::Strict` mode.
+    #[inline]
+    pub fn invalid_strings(&self) -> &[InvalidString] {
+        &self.invalid_strings
+    }
+
+    #[inline]
+    pub fn take_invalid_strings(&mut self) -> Vec<InvalidString> {
+        std::mem::take(&mut self.invalid_strings)
+    }
+
+    /// Number of bytes read so far.
+    #[inline]
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.reader
+    }
+
+    #[inline]
     pub fn read_invalid(&self) -> Result<()> {
         Err(io::Error::new(io::ErrorKind::InvalidInput, "HeaderField::Invalid can not be marshaled!"))
     }
 
+    /// Skips the alignment padding up to the next multiple of `alignment`.
+    /// The padding bytes must be nul.
+    #[inline]
+    pub fn read_padding(&mut self, alignment: usize) -> Result<()> {
+        let mut buffer = [0; 8];
+        let padding = &mut buffer[..padding(self.position, alignment)];
+        self.read_exact(padding)?;
+        if padding.iter().any(|b| *b != 0) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Non-nul alignment padding"));
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        self.reader.read_exact(buf)?;
+        self.position += buf.len();
+        Ok(())
+    }
+
     /// A single 8-bit byte.
+    #[inline]
     pub fn read_u8(&mut self) -> Result<u8> {
-        self.reader.read_u8()
+        let n = self.reader.read_u8()?;
+        self.position += 1;
+        Ok(n)
     }
 
     /// As for UINT32, but only 0 and 1 are valid values.
+    #[inline]
     pub fn read_boolean<T1: ByteOrder>(&mut self) -> Result<bool> {
-        let val = self.reader.read_u32::<T1>()?;
+        let val = self.read_u32::<T1>()?;
         match val {
             0 => Ok(false),
             1 => Ok(true),
@@ -45,65 +183,245 @@ impl<T: io::Read> DbusReader<T> {
     }
 
     /// 16-bit signed integer in the message's byte order.
+    #[inline]
     pub fn read_i16<T1: ByteOrder>(&mut self) -> Result<i16> {
-        self.reader.read_i16::<T1>()
+        self.read_padding(2)?;
+        let n = self.reader.read_i16::<T1>()?;
+        self.position += 2;
+        Ok(n)
     }
 
     /// 16-bit unsigned integer in the message's byte order.
+    #[inline]
     pub fn read_u16<T1: ByteOrder>(&mut self) -> Result<u16> {
-        self.reader.read_u16::<T1>()
+        self.read_padding(2)?;
+        let n = self.reader.read_u16::<T1>()?;
+        self.position += 2;
+        Ok(n)
     }
 
     /// 32-bit signed integer in the message's byte order.
+    #[inline]
     pub fn read_i32<T1: ByteOrder>(&mut self) -> Result<i32> {
-        self.reader.read_i32::<T1>()
+        self.read_padding(4)?;
+        let n = self.reader.read_i32::<T1>()?;
+        self.position += 4;
+        Ok(n)
     }
 
     /// 32-bit unsigned integer in the message's byte order.
+    #[inline]
     pub fn read_u32<T1: ByteOrder>(&mut self) -> Result<u32> {
-        self.reader.read_u32::<T1>()
+        self.read_padding(4)?;
+        let n = self.reader.read_u32::<T1>()?;
+        self.position += 4;
+        Ok(n)
     }
 
     /// 64-bit signed integer in the message's byte order.
+    #[inline]
     pub fn read_i64<T1: ByteOrder>(&mut self) -> Result<i64> {
-        self.reader.read_i64::<T1>()
+        self.read_padding(8)?;
+        let n = self.reader.read_i64::<T1>()?;
+        self.position += 8;
+        Ok(n)
     }
 
     /// 64-bit unsigned integer in the message's byte order.
+    #[inline]
     pub fn read_u64<T1: ByteOrder>(&mut self) -> Result<u64> {
-        self.reader.read_u64::<T1>()
+        self.read_padding(8)?;
+        let n = self.reader.read_u64::<T1>()?;
+        self.position += 8;
+        Ok(n)
+    }
+
+    /// 64-bit IEEE 754 double in the message's byte order.
+    #[inline]
+    pub fn read_f64<T1: ByteOrder>(&mut self) -> Result<f64> {
+        self.read_padding(8)?;
+        let n = self.reader.read_f64::<T1>()?;
+        self.position += 8;
+        Ok(n)
     }
 
     /// A UINT32 indicating the string's length in bytes excluding its terminating nul,
     /// followed by non-nul string data of the given length, followed by a terminating nul byte.
+    #[inline]
     pub fn read_string<T1: ByteOrder>(&mut self) -> Result<String> {
-        let len = self.reader.read_u32::<T1>()?;
-        let mut buffer = Vec::with_capacity(len as usize);
-        self.reader.read_exact(&mut buffer);
-
-        let str_temination = self.reader.read_u8()?;
-        if str_temination != b'\n' {
-            let str_err = format!("Invalid termination character `{}`", str_temination);
-            return Err(io::Error::new(io::ErrorKind::InvalidData, str_err));
-        }
-
-        String::from_utf8(buffer).map_err(|err| {
-            let str_err = format!("UT8 error: `{}`", err);
-            io::Error::new(io::ErrorKind::InvalidData, str_err)
-        })
+        let len = self.read_u32::<T1>()?;
+        let (position, bytes) = self.read_nul_terminated(len as usize)?;
+        self.validate(position, bytes, |_| Ok(()))
     }
 
     /// Exactly the same as STRING except the content must be a valid object path (see above).
+    #[inline]
     pub fn read_object_path<T1: ByteOrder>(&mut self) -> Result<ObjectPath> {
-        let s = self.read_string::<T1>()?;
-        Ok(ObjectPath(s))
+        let len = self.read_u32::<T1>()?;
+        let (position, bytes) = self.read_nul_terminated(len as usize)?;
+        let s = self.validate(position, bytes, |s| {
+            ObjectPath::from_str(s).map(|_| ()).map_err(StringError::InvalidObjectPath)
+        })?;
+        Ok(ObjectPath(s.into()))
     }
 
     /// The same as STRING except the length is a single byte (thus signatures
     /// have a maximum length of 255) and the content must be a valid signature (see above).
+    #[inline]
     pub fn read_signature<T1: ByteOrder>(&mut self) -> Result<Signature> {
-        let s = self.read_string::<T1>()?;
-        Ok(Signature(s))
+        let len = self.read_u8()?;
+        let (position, bytes) = self.read_nul_terminated(len as usize)?;
+        let s = self.validate(position, bytes, |s| {
+            Type::parse(s).map(|_| ()).map_err(StringError::InvalidSignature)
+        })?;
+        Ok(Signature(s.into()))
+    }
+
+    /// Reads `len` bytes of string data and the terminating nul, returning the
+    /// position of the data along with it. The buffer grows with the bytes actually
+    /// read, so an untrusted length can not force a large allocation.
+    fn read_nul_terminated(&mut self, len: usize) -> Result<(usize, Vec<u8>)> {
+        if len >= MAX_MESSAGE_SIZE {
+            let str_err = format!("String length `{}` exceeds maximum message size of {} bytes", len, MAX_MESSAGE_SIZE);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, str_err));
+        }
+        let position = self.position;
+        let mut buffer = Vec::new();
+        (&mut self.reader).take(len as u64).read_to_end(&mut buffer)?;
+        self.position += buffer.len();
+        if buffer.len() < len {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "String data ends early"));
+        }
+
+        let str_temination = self.read_u8()?;
+        if str_temination != 0 {
+            let str_err = format!("Invalid termination character `{}`", str_temination);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, str_err));
+        }
+        Ok((position, buffer))
+    }
+
+    /// Checks string data for interior nul bytes, valid UTF-8 and then with `check`,
+    /// failing or recording the raw bytes depending on the validation mode.
+    fn validate<F>(&mut self, position: usize, bytes: Vec<u8>, check: F) -> Result<String>
+        where F: FnOnce(&str) -> std::result::Result<(), StringError>
+    {
+        let result = match bytes.iter().position(|b| *b == 0) {
+            Some(index) => Err(StringError::InteriorNul(index)),
+            None => str::from_utf8(&bytes).map_err(StringError::InvalidUtf8).and_then(check),
+        };
+        let error = match result {
+            Ok(()) => return Ok(String::from_utf8(bytes).expect("validated UTF-8")),
+            Err(error) => error,
+        };
+
+        match self.validation {
+            Validation::Strict => Err(io::Error::new(io::ErrorKind::InvalidData, error)),
+            Validation::Lenient => {
+                let s = String::from_utf8_lossy(&bytes).into_owned();
+                self.invalid_strings.push(InvalidString { position, bytes, error });
+                Ok(s)
+            }
+        }
+    }
+
+    /// Unmarshals a value of the single complete type `t`.
+    #[inline]
+    pub fn read_value<T1: ByteOrder>(&mut self, t: &Type) -> Result<Value> {
+        self.read_value_at_depth::<T1>(t, 0)
+    }
+
+    fn read_value_at_depth<T1: ByteOrder>(&mut self, t: &Type, depth: usize) -> Result<Value> {
+        if depth > MAX_VALUE_DEPTH {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Value exceeds maximum nesting depth"));
+        }
+
+        let value = match t {
+            Type::Byte => Value::Byte(self.read_u8()?),
+            Type::Boolean => Value::Boolean(self.read_boolean::<T1>()?),
+            Type::Int16 => Value::Int16(self.read_i16::<T1>()?),
+            Type::Uint16 => Value::Uint16(self.read_u16::<T1>()?),
+            Type::Int32 => Value::Int32(self.read_i32::<T1>()?),
+            Type::Uint32 => Value::Uint32(self.read_u32::<T1>()?),
+            Type::Int64 => Value::Int64(self.read_i64::<T1>()?),
+            Type::Uint64 => Value::Uint64(self.read_u64::<T1>()?),
+            Type::Double => Value::Double(self.read_f64::<T1>()?),
+            Type::String => Value::String(self.read_string::<T1>()?),
+            Type::ObjectPath => Value::ObjectPath(self.read_object_path::<T1>()?),
+            Type::Signature => Value::Signature(self.read_signature::<T1>()?),
+            Type::UnixFd => Value::UnixFd(UnixFd(self.read_u32::<T1>()?)),
+            Type::Array(element) => {
+                let len = self.read_u32::<T1>()? as usize;
+                if len > MAX_ARRAY_LENGTH {
+                    let str_err = format!("Array length `{}` exceeds maximum of {} bytes", len, MAX_ARRAY_LENGTH);
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, str_err));
+                }
+                self.read_padding(element.alignment())?;
+
+                let end = self.position + len;
+                let mut elements = Vec::new();
+                while self.position < end {
+                    elements.push(self.read_value_at_depth::<T1>(element, depth + 1)?);
+                }
+                if self.position != end {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "Array elements exceed the array length"));
+                }
+                Value::Array(Signature(element.to_string().into()), elements)
+            }
+            Type::Struct(fields) => {
+                self.read_padding(8)?;
+                let mut values = Vec::with_capacity(fields.len());
+                for field in fields {
+                    values.push(self.read_value_at_depth::<T1>(field, depth + 1)?);
+                }
+                Value::Struct(values)
+            }
+            Type::DictEntry(key, value) => {
+                self.read_padding(8)?;
+                let key = self.read_value_at_depth::<T1>(key, depth + 1)?;
+                let value = self.read_value_at_depth::<T1>(value, depth + 1)?;
+                Value::DictEntry(Box::new(key), Box::new(value))
+            }
+            Type::Variant => {
+                let signature = self.read_signature::<T1>()?;
+                let t = Type::parse_single(&signature.0).map_err(|err| {
+                    let str_err = format!("Invalid variant signature `{}`: {}", signature.0, err);
+                    io::Error::new(io::ErrorKind::InvalidData, str_err)
+                })?;
+                Value::Variant(Box::new(self.read_value_at_depth::<T1>(&t, depth + 1)?))
+            }
+            Type::Maybe(_) => {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "The maybe type has no D-Bus encoding"));
+            }
+        };
+        Ok(value)
+    }
+
+    /// An ARRAY of fixed-size basic types, read with a single copy and swapping bytes
+    /// only if `T1` is not the host byte order.
+    #[inline]
+    pub fn read_fixed_array<T1: ByteOrder, T2: FixedSizeType>(&mut self) -> Result<Vec<T2>> {
+        let len = self.read_u32::<T1>()? as usize;
+        if len > MAX_ARRAY_LENGTH {
+            let str_err = format!("Array length `{}` exceeds maximum of {} bytes", len, MAX_ARRAY_LENGTH);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, str_err));
+        }
+        if !len.is_multiple_of(T2::SIZE) {
+            let str_err = format!("Array length `{}` is not a multiple of the element size {}", len, T2::SIZE);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, str_err));
+        }
+        self.read_padding(T2::SIZE)?;
+
+        // The buffer grows with the bytes that actually arrive, not with the claimed length.
+        let mut buffer = Vec::with_capacity(len.min(MAX_INITIAL_CAPACITY));
+        (&mut self.reader).take(len as u64).read_to_end(&mut buffer)?;
+        self.position += buffer.len();
+        if buffer.len() < len {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Array data ends early"));
+        }
+        let mut elements = vec![T2::default(); len / T2::SIZE];
+        T2::from_bytes::<T1>(&buffer, &mut elements);
+        Ok(elements)
     }
 
     // A UINT32 giving the length of the array data in bytes, followed by alignment
@@ -121,3 +439,69 @@ impl<T: io::Read> DbusReader<T> {
 
 }
 
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use byteorder::LittleEndian;
+
+    fn string_error<T>(result: Result<T>) -> StringError {
+        let err = result.err().unwrap();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        err.into_inner().unwrap().downcast::<StringError>().map(|err| *err).unwrap()
+    }
+
+    #[test]
+    fn strict_strings() {
+        let buf = [3, 0, 0, 0, b'a', 0, b'b', 0];
+        assert_eq!(StringError::InteriorNul(1), string_error(DbusReader::new(&buf[..]).read_string::<LittleEndian>()));
+
+        let buf = [2, 0, 0, 0, 0xc3, 0x28, 0];
+        let err = string_error(DbusReader::new(&buf[..]).read_string::<LittleEndian>());
+        assert!(matches!(err, StringError::InvalidUtf8(_)));
+
+        let buf = [4, 0, 0, 0, b'/', b'a', b'-', b'b', 0];
+        let err = string_error(DbusReader::new(&buf[..]).read_object_path::<LittleEndian>());
+        assert_eq!(StringError::InvalidObjectPath(ObjectPathError::InvalidCharacter('-')), err);
+
+        let buf = [2, b'a', b'{', 0];
+        let err = string_error(DbusReader::new(&buf[..]).read_signature::<LittleEndian>());
+        assert_eq!(StringError::InvalidSignature(SignatureError::UnbalancedBraces), err);
+    }
+
+    #[test]
+    fn valid_strings() {
+        let buf = [2, 0, 0, 0, b'/', b'a', 0, 5, b'a', b'{', b's', b'v', b'}', 0];
+        let mut reader = DbusReader::new(&buf[..]);
+        assert_eq!(ObjectPath("/a".into()), reader.read_object_path::<LittleEndian>().unwrap());
+        assert_eq!(Signature("a{sv}".into()), reader.read_signature::<LittleEndian>().unwrap());
+    }
+
+    #[test]
+    fn fixed_arrays() {
+        let buf = [8, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0];
+        assert_eq!(vec![1u32, 2], DbusReader::new(&buf[..]).read_fixed_array::<LittleEndian, u32>().unwrap());
+
+        let mut buf = (MAX_ARRAY_LENGTH as u32).to_le_bytes().to_vec();
+        buf.extend([1, 0, 0, 0]);
+        let err = DbusReader::new(&buf[..]).read_fixed_array::<LittleEndian, u32>().unwrap_err();
+        assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+    }
+
+    #[test]
+    fn lenient_strings_keep_raw_bytes() {
+        let buf = [3, 0, 0, 0, b'a', 0xff, 0, 0, 1, 0, 0, 0, b'x', 0];
+        let mut reader = DbusReader::new(&buf[..]);
+        reader.set_validation(Validation::Lenient);
+        assert_eq!(Value::ObjectPath(ObjectPath("a\u{fffd}\u{0}".into())), reader.read_value::<LittleEndian>(&Type::ObjectPath).unwrap());
+        assert_eq!("x", reader.read_string::<LittleEndian>().unwrap());
+
+        let invalid = reader.take_invalid_strings();
+        assert_eq!(1, invalid.len());
+        assert_eq!(4, invalid[0].position);
+        assert_eq!(vec![b'a', 0xff, 0], invalid[0].bytes);
+        assert_eq!(StringError::InteriorNul(2), invalid[0].error);
+        assert!(reader.invalid_strings().is_empty());
+    }
+}
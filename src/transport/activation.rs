@@ -0,0 +1,324 @@
+//! systemd socket activation, adopting the listening sockets a service is started with.
+//! They are passed as consecutive descriptors from 3 on, described by `LISTEN_PID`,
+//! `LISTEN_FDS` and optionally `LISTEN_FDNAMES`.
+
+use std::env;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::net::TcpListener;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixListener;
+use std::time::Duration;
+
+use crate::transport::tcp::TcpTransport;
+use crate::transport::unix::UnixTransport;
+use crate::transport::DbusTransport;
+
+/// The first descriptor passed by systemd.
+pub const LISTEN_FDS_START: RawFd = 3;
+
+pub const LISTEN_PID_ENV: &str = "LISTEN_PID";
+pub const LISTEN_FDS_ENV: &str = "LISTEN_FDS";
+pub const LISTEN_FDNAMES_ENV: &str = "LISTEN_FDNAMES";
+
+/// A listening socket passed by systemd, with the name given by `FileDescriptorName=`.
+#[derive(Debug)]
+pub struct ActivatedSocket {
+    pub name: Option<String>,
+    pub listener: ActivatedListener,
+}
+
+/// A listening stream socket of a family D-Bus connections can be made over.
+#[derive(Debug)]
+pub enum ActivatedListener {
+    Unix(UnixListener),
+    Tcp(TcpListener),
+}
+
+impl ActivatedListener {
+    /// Accepts a connection, to be authenticated as a D-Bus peer.
+    #[inline]
+    pub fn accept(&self) -> io::Result<ActivatedTransport> {
+        match self {
+            ActivatedListener::Unix(listener) => Ok(ActivatedTransport::Unix(UnixTransport::from(listener.accept()?.0))),
+            ActivatedListener::Tcp(listener) => {
+                let (stream, _) = listener.accept()?;
+                stream.set_nodelay(true)?;
+                Ok(ActivatedTransport::Tcp(TcpTransport::from(stream)))
+            }
+        }
+    }
+}
+
+impl AsFd for ActivatedListener {
+    #[inline]
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        match self {
+            ActivatedListener::Unix(listener) => listener.as_fd(),
+            ActivatedListener::Tcp(listener) => listener.as_fd(),
+        }
+    }
+}
+
+/// A connection accepted on an [`ActivatedListener`].
+#[derive(Debug)]
+pub enum ActivatedTransport {
+    Unix(UnixTransport),
+    Tcp(TcpTransport),
+}
+
+impl Read for ActivatedTransport {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            ActivatedTransport::Unix(transport) => transport.read(buf),
+            ActivatedTransport::Tcp(transport) => transport.read(buf),
+        }
+    }
+}
+
+impl Write for ActivatedTransport {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            ActivatedTransport::Unix(transport) => transport.write(buf),
+            ActivatedTransport::Tcp(transport) => transport.write(buf),
+        }
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ActivatedTransport::Unix(transport) => transport.flush(),
+            ActivatedTransport::Tcp(transport) => transport.flush(),
+        }
+    }
+}
+
+impl DbusTransport for ActivatedTransport {
+    #[inline]
+    fn supports_unix_fds(&self) -> bool {
+        match self {
+            ActivatedTransport::Unix(transport) => transport.supports_unix_fds(),
+            ActivatedTransport::Tcp(transport) => transport.supports_unix_fds(),
+        }
+    }
+
+    #[inline]
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            ActivatedTransport::Unix(transport) => transport.set_read_timeout(timeout),
+            ActivatedTransport::Tcp(transport) => transport.set_read_timeout(timeout),
+        }
+    }
+
+    #[inline]
+    fn send_with_fds(&mut self, buf: &[u8], fds: &[BorrowedFd<'_>]) -> io::Result<usize> {
+        match self {
+            ActivatedTransport::Unix(transport) => DbusTransport::send_with_fds(transport, buf, fds),
+            ActivatedTransport::Tcp(transport) => transport.send_with_fds(buf, fds),
+        }
+    }
+
+    #[inline]
+    fn recv_with_fds(&mut self, buf: &mut [u8], fds: &mut Vec<OwnedFd>) -> io::Result<usize> {
+        match self {
+            ActivatedTransport::Unix(transport) => DbusTransport::recv_with_fds(transport, buf, fds),
+            ActivatedTransport::Tcp(transport) => transport.recv_with_fds(buf, fds),
+        }
+    }
+}
+
+/// Adopts the sockets passed by systemd, which is none if the service was not socket
+/// activated or the variables were meant for another process. The variables are removed
+/// from the environment so they are not inherited by children, and the descriptors are
+/// made close-on-exec.
+///
+/// Fails if a descriptor is not a listening `AF_UNIX`, `AF_INET` or `AF_INET6` stream
+/// socket, or the variables are malformed. The descriptors are closed on failure.
+#[inline]
+pub fn listen_fds() -> io::Result<Vec<ActivatedSocket>> {
+    let sockets = adopt(LISTEN_FDS_START, &|key| env::var(key).ok());
+    env::remove_var(LISTEN_PID_ENV);
+    env::remove_var(LISTEN_FDS_ENV);
+    env::remove_var(LISTEN_FDNAMES_ENV);
+    sockets
+}
+
+fn invalid(str_err: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, str_err)
+}
+
+fn adopt(first_fd: RawFd, vars: &dyn Fn(&str) -> Option<String>) -> io::Result<Vec<ActivatedSocket>> {
+    let pid = match vars(LISTEN_PID_ENV) {
+        Some(pid) => pid.parse::<libc::pid_t>().map_err(|_| invalid(format!("Invalid {} `{}`", LISTEN_PID_ENV, pid)))?,
+        None => return Ok(Vec::new()),
+    };
+    // SAFETY: getpid has no preconditions and can not fail.
+    if pid != unsafe { libc::getpid() } {
+        return Ok(Vec::new());
+    }
+    let count = match vars(LISTEN_FDS_ENV) {
+        Some(count) => count.parse::<RawFd>().ok().filter(|&n| n >= 0).ok_or_else(|| invalid(format!("Invalid {} `{}`", LISTEN_FDS_ENV, count)))?,
+        None => return Ok(Vec::new()),
+    };
+
+    let end = first_fd.checked_add(count).ok_or_else(|| invalid(format!("Invalid {} `{}`", LISTEN_FDS_ENV, count)))?;
+    for fd in first_fd..end {
+        // SAFETY: F_GETFD only reads the flags of the descriptor, failing if it is not open.
+        if unsafe { libc::fcntl(fd, libc::F_GETFD) } < 0 {
+            return Err(invalid(format!("Descriptor {} passed in {} is not open", fd, LISTEN_FDS_ENV)));
+        }
+    }
+    // SAFETY: the descriptors are open and passed to this process to own, as LISTEN_PID confirms.
+    let fds: Vec<OwnedFd> = (first_fd..end).map(|fd| unsafe { OwnedFd::from_raw_fd(fd) }).collect();
+
+    let names: Vec<Option<String>> = match vars(LISTEN_FDNAMES_ENV) {
+        Some(names) => {
+            let names: Vec<Option<String>> = names.split(':').map(|name| Some(name.to_string()).filter(|name| !name.is_empty())).collect();
+            if names.len() != fds.len() {
+                return Err(invalid(format!("{} names {} sockets, but {} are passed", LISTEN_FDNAMES_ENV, names.len(), fds.len())));
+            }
+            names
+        }
+        None => vec![None; fds.len()],
+    };
+
+    fds.into_iter().zip(names).map(|(fd, name)| Ok(ActivatedSocket { name, listener: listener(fd)? })).collect()
+}
+
+fn socket_option(fd: &OwnedFd, option: libc::c_int) -> io::Result<libc::c_int> {
+    let mut value: libc::c_int = 0;
+    let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: `value` and `len` are valid for writes of the given size.
+    let res = unsafe { libc::getsockopt(fd.as_raw_fd(), libc::SOL_SOCKET, option, &mut value as *mut _ as *mut libc::c_void, &mut len) };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(value)
+}
+
+/// Checks `fd` is a listening stream socket and wraps it according to its family.
+fn listener(fd: OwnedFd) -> io::Result<ActivatedListener> {
+    let raw_fd = fd.as_raw_fd();
+    let not_listening = || invalid(format!("Descriptor {} is not a listening stream socket", raw_fd));
+
+    let socket_type = socket_option(&fd, libc::SO_TYPE).map_err(|err| match err.raw_os_error() {
+        Some(libc::ENOTSOCK) => not_listening(),
+        _ => err,
+    })?;
+    if socket_type != libc::SOCK_STREAM || socket_option(&fd, libc::SO_ACCEPTCONN)? == 0 {
+        return Err(not_listening());
+    }
+
+    // SAFETY: sockaddr_storage is a plain C struct for which all zeros is a valid value.
+    let mut address: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    // SAFETY: `address` and `len` are valid for writes of the given size.
+    if unsafe { libc::getsockname(raw_fd, &mut address as *mut _ as *mut libc::sockaddr, &mut len) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // SAFETY: `fd` is an open descriptor owned by this process.
+    if unsafe { libc::fcntl(raw_fd, libc::F_SETFD, libc::FD_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    match address.ss_family as libc::c_int {
+        libc::AF_UNIX => Ok(ActivatedListener::Unix(UnixListener::from(fd))),
+        libc::AF_INET | libc::AF_INET6 => Ok(ActivatedListener::Tcp(TcpListener::from(fd))),
+        family => Err(invalid(format!("Descriptor {} is a socket of unsupported family {}", raw_fd, family))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::collections::HashMap;
+    use std::net::TcpStream;
+    use std::os::unix::net::UnixStream;
+
+    /// Places duplicates of `fds` at consecutive descriptors from `first_fd`, far above
+    /// those other tests open, as systemd does from 3.
+    fn pass(first_fd: RawFd, fds: &[BorrowedFd<'_>]) {
+        for (i, fd) in fds.iter().enumerate() {
+            // SAFETY: both descriptors are valid, the target is owned by the test.
+            assert!(unsafe { libc::dup2(fd.as_raw_fd(), first_fd + i as RawFd) } >= 0);
+        }
+    }
+
+    fn vars(pairs: &[(&str, String)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = pairs.iter().map(|(k, v)| (k.to_string(), v.clone())).collect();
+        move |key| vars.get(key).cloned()
+    }
+
+    fn pid() -> String {
+        std::process::id().to_string()
+    }
+
+    #[test]
+    fn adopt_unix_and_tcp() {
+        let dir = std::env::temp_dir().join(format!("dbus-native-activation-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("socket");
+        let unix = UnixListener::bind(&path).unwrap();
+        let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = tcp.local_addr().unwrap().port();
+        pass(600, &[unix.as_fd(), tcp.as_fd()]);
+
+        let vars = vars(&[(LISTEN_PID_ENV, pid()), (LISTEN_FDS_ENV, "2".to_string()), (LISTEN_FDNAMES_ENV, "dbus:".to_string())]);
+        let sockets = adopt(600, &vars).unwrap();
+        assert_eq!(2, sockets.len());
+        assert_eq!(Some("dbus"), sockets[0].name.as_deref());
+        assert_eq!(None, sockets[1].name);
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        client.write_all(b"\0").unwrap();
+        let mut accepted = sockets[0].listener.accept().unwrap();
+        assert!(matches!(accepted, ActivatedTransport::Unix(_)));
+        assert!(accepted.supports_unix_fds());
+        let mut buf = [1];
+        accepted.read_exact(&mut buf).unwrap();
+        assert_eq!([0], buf);
+
+        let _client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        assert!(matches!(sockets[1].listener.accept().unwrap(), ActivatedTransport::Tcp(_)));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn other_process() {
+        let vars = vars(&[(LISTEN_PID_ENV, "1".to_string()), (LISTEN_FDS_ENV, "1".to_string())]);
+        assert!(adopt(610, &vars).unwrap().is_empty());
+        assert!(adopt(610, &|_| None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn invalid_descriptor_ranges() {
+        let err = adopt(3, &vars(&[(LISTEN_PID_ENV, pid()), (LISTEN_FDS_ENV, RawFd::MAX.to_string())])).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+
+        let err = adopt(630, &vars(&[(LISTEN_PID_ENV, pid()), (LISTEN_FDS_ENV, "2".to_string())])).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
+
+    #[test]
+    fn invalid_sockets() {
+        let (stream, _peer) = UnixStream::pair().unwrap();
+        pass(620, &[stream.as_fd()]);
+        let err = adopt(620, &vars(&[(LISTEN_PID_ENV, pid()), (LISTEN_FDS_ENV, "1".to_string())])).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+
+        let file = std::fs::File::open("/dev/null").unwrap();
+        pass(621, &[file.as_fd()]);
+        let err = adopt(621, &vars(&[(LISTEN_PID_ENV, pid()), (LISTEN_FDS_ENV, "1".to_string())])).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+
+        let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+        pass(622, &[tcp.as_fd()]);
+        let vars = vars(&[(LISTEN_PID_ENV, pid()), (LISTEN_FDS_ENV, "1".to_string()), (LISTEN_FDNAMES_ENV, "a:b".to_string())]);
+        assert_eq!(io::ErrorKind::InvalidData, adopt(622, &vars).unwrap_err().kind());
+        assert_eq!(io::ErrorKind::InvalidData, adopt(622, &|key| Some(if key == LISTEN_PID_ENV { pid() } else { "x".to_string() })).unwrap_err().kind());
+    }
+}
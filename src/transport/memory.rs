@@ -0,0 +1,384 @@
+//! An in-memory duplex pipe, letting authentication, framing and connection code be
+//! tested in-process without sockets.
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+#[cfg(unix)]
+use std::os::unix::io::{BorrowedFd, OwnedFd};
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use futures_io::{AsyncRead, AsyncWrite};
+
+use crate::transport::DbusTransport;
+
+/// Bytes written at once, along with the file descriptors passed with the first of them.
+#[derive(Debug)]
+struct Segment {
+    data: Vec<u8>,
+    #[cfg(unix)]
+    fds: Vec<OwnedFd>,
+}
+
+#[derive(Debug, Default)]
+struct Buffer {
+    segments: VecDeque<Segment>,
+    /// Bytes of the front segment already read.
+    offset: usize,
+    writer_closed: bool,
+    reader_closed: bool,
+    /// The task waiting in `poll_read` for bytes.
+    waker: Option<Waker>,
+}
+
+impl Buffer {
+    /// Moves available bytes into `buf` up to the next file descriptors passed.
+    fn take(&mut self, buf: &mut [u8], #[cfg(unix)] fds: &mut Vec<OwnedFd>) -> usize {
+        let mut read = 0;
+        while read < buf.len() {
+            let offset = self.offset;
+            let segment = match self.segments.front_mut() {
+                Some(segment) => segment,
+                None => break,
+            };
+            #[cfg(unix)]
+            {
+                if offset == 0 && !segment.fds.is_empty() {
+                    if read > 0 {
+                        break;
+                    }
+                    fds.append(&mut segment.fds);
+                }
+            }
+            let n = (segment.data.len() - offset).min(buf.len() - read);
+            buf[read..read + n].copy_from_slice(&segment.data[offset..offset + n]);
+            read += n;
+            if offset + n == segment.data.len() {
+                self.segments.pop_front();
+                self.offset = 0;
+            } else {
+                self.offset += n;
+            }
+        }
+        read
+    }
+
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// One direction of the pipe.
+#[derive(Debug, Default)]
+struct Pipe {
+    buffer: Mutex<Buffer>,
+    readable: Condvar,
+}
+
+impl Pipe {
+    fn lock(&self) -> MutexGuard<'_, Buffer> {
+        self.buffer.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// One end of an in-memory duplex pipe created by [`pair`](MemoryTransport::pair).
+///
+/// Writes never block as the pipe is unbounded. Reads block until bytes are available
+/// unless the transport is non-blocking, and return 0 once the other end is dropped and
+/// everything written by it has been read. Like a Unix domain socket a read never
+/// returns bytes from before and after file descriptors passed with a write, so the
+/// descriptors arrive with the first byte written along with them.
+///
+/// It is also a futures-io `AsyncRead` and `AsyncWrite`, which drop passed descriptors.
+#[derive(Debug)]
+pub struct MemoryTransport {
+    incoming: Arc<Pipe>,
+    outgoing: Arc<Pipe>,
+    nonblocking: bool,
+    read_timeout: Option<Duration>,
+}
+
+impl MemoryTransport {
+    /// Creates two connected ends of a pipe.
+    #[inline]
+    pub fn pair() -> (MemoryTransport, MemoryTransport) {
+        let a = Arc::new(Pipe::default());
+        let b = Arc::new(Pipe::default());
+        let first = MemoryTransport { incoming: a.clone(), outgoing: b.clone(), nonblocking: false, read_timeout: None };
+        let second = MemoryTransport { incoming: b, outgoing: a, nonblocking: false, read_timeout: None };
+        (first, second)
+    }
+
+    /// Makes reads fail with `WouldBlock` instead of waiting for bytes.
+    #[inline]
+    pub fn set_nonblocking(&mut self, nonblocking: bool) {
+        self.nonblocking = nonblocking;
+    }
+
+    /// Number of bytes written by the other end that have not been read yet.
+    #[inline]
+    pub fn available(&self) -> usize {
+        let buffer = self.incoming.lock();
+        buffer.segments.iter().map(|segment| segment.data.len()).sum::<usize>() - buffer.offset
+    }
+
+    fn receive(&mut self, buf: &mut [u8], #[cfg(unix)] fds: &mut Vec<OwnedFd>) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let deadline = self.read_timeout.map(|timeout| Instant::now() + timeout);
+        let mut buffer = self.incoming.lock();
+        while buffer.segments.is_empty() {
+            if buffer.writer_closed {
+                return Ok(0);
+            }
+            if self.nonblocking {
+                return Err(io::Error::new(io::ErrorKind::WouldBlock, "No bytes available"));
+            }
+            buffer = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(io::Error::new(io::ErrorKind::WouldBlock, "No bytes available within the read timeout"));
+                    }
+                    self.incoming.readable.wait_timeout(buffer, deadline - now).unwrap_or_else(PoisonError::into_inner).0
+                }
+                None => self.incoming.readable.wait(buffer).unwrap_or_else(PoisonError::into_inner),
+            };
+        }
+
+        #[cfg(unix)]
+        return Ok(buffer.take(buf, fds));
+        #[cfg(not(unix))]
+        Ok(buffer.take(buf))
+    }
+
+    fn send(&mut self, buf: &[u8], #[cfg(unix)] fds: Vec<OwnedFd>) -> io::Result<usize> {
+        let mut buffer = self.outgoing.lock();
+        if buffer.reader_closed {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "Other end of the pipe was dropped"));
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        #[cfg(unix)]
+        let merge = fds.is_empty();
+        #[cfg(not(unix))]
+        let merge = true;
+        match buffer.segments.back_mut() {
+            Some(last) if merge => last.data.extend_from_slice(buf),
+            _ => buffer.segments.push_back(Segment {
+                data: buf.to_vec(),
+                #[cfg(unix)]
+                fds,
+            }),
+        }
+        buffer.wake();
+        self.outgoing.readable.notify_all();
+        Ok(buf.len())
+    }
+}
+
+impl Drop for MemoryTransport {
+    #[inline]
+    fn drop(&mut self) {
+        let mut outgoing = self.outgoing.lock();
+        outgoing.writer_closed = true;
+        outgoing.wake();
+        drop(outgoing);
+        self.outgoing.readable.notify_all();
+        let mut incoming = self.incoming.lock();
+        incoming.reader_closed = true;
+        incoming.segments.clear();
+    }
+}
+
+impl Read for MemoryTransport {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        #[cfg(unix)]
+        return self.receive(buf, &mut Vec::new());
+        #[cfg(not(unix))]
+        self.receive(buf)
+    }
+}
+
+impl Write for MemoryTransport {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        #[cfg(unix)]
+        return self.send(buf, Vec::new());
+        #[cfg(not(unix))]
+        self.send(buf)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsyncRead for MemoryTransport {
+    #[inline]
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let mut buffer = self.incoming.lock();
+        if buffer.segments.is_empty() && !buffer.writer_closed && !buf.is_empty() {
+            buffer.waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        #[cfg(unix)]
+        return Poll::Ready(Ok(buffer.take(buf, &mut Vec::new())));
+        #[cfg(not(unix))]
+        Poll::Ready(Ok(buffer.take(buf)))
+    }
+}
+
+impl AsyncWrite for MemoryTransport {
+    #[inline]
+    fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Poll::Ready(self.get_mut().write(buf))
+    }
+
+    #[inline]
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    #[inline]
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl DbusTransport for MemoryTransport {
+    #[inline]
+    fn supports_unix_fds(&self) -> bool {
+        cfg!(unix)
+    }
+
+    /// Like a socket, a timed out read fails with `WouldBlock`.
+    #[inline]
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        if timeout == Some(Duration::ZERO) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Read timeout must not be zero"));
+        }
+        self.read_timeout = timeout;
+        Ok(())
+    }
+
+    /// Passes duplicates of `fds`, as the kernel does for a socket.
+    #[cfg(unix)]
+    #[inline]
+    fn send_with_fds(&mut self, buf: &[u8], fds: &[BorrowedFd<'_>]) -> io::Result<usize> {
+        if buf.is_empty() && !fds.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "File descriptors must be sent with at least one byte"));
+        }
+        let fds = fds.iter().map(|fd| fd.try_clone_to_owned()).collect::<io::Result<Vec<_>>>()?;
+        self.send(buf, fds)
+    }
+
+    #[cfg(unix)]
+    #[inline]
+    fn recv_with_fds(&mut self, buf: &mut [u8], fds: &mut Vec<OwnedFd>) -> io::Result<usize> {
+        self.receive(buf, fds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn round_trip() {
+        let (mut a, mut b) = MemoryTransport::pair();
+        a.write_all(b"\0AUTH ").unwrap();
+        a.write_all(b"EXTERNAL\r\n").unwrap();
+        assert_eq!(16, b.available());
+
+        let mut buf = [0; 4];
+        b.read_exact(&mut buf).unwrap();
+        assert_eq!(b"\0AUT", &buf);
+        let mut rest = [0; 32];
+        assert_eq!(12, b.read(&mut rest).unwrap());
+        assert_eq!(b"H EXTERNAL\r\n", &rest[..12]);
+
+        b.write_all(b"OK").unwrap();
+        a.read_exact(&mut buf[..2]).unwrap();
+        assert_eq!(b"OK", &buf[..2]);
+    }
+
+    #[test]
+    fn blocking_and_nonblocking_reads() {
+        let (mut a, mut b) = MemoryTransport::pair();
+        b.set_nonblocking(true);
+        assert_eq!(io::ErrorKind::WouldBlock, b.read(&mut [0; 1]).unwrap_err().kind());
+        b.set_nonblocking(false);
+        b.set_read_timeout(Some(Duration::from_millis(10))).unwrap();
+        assert_eq!(io::ErrorKind::WouldBlock, b.read(&mut [0; 1]).unwrap_err().kind());
+        b.set_read_timeout(None).unwrap();
+
+        let writer = thread::spawn(move || {
+            a.write_all(b"x").unwrap();
+        });
+        let mut buf = [0; 1];
+        b.read_exact(&mut buf).unwrap();
+        assert_eq!(b"x", &buf);
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn async_reads() {
+        use futures_util::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (mut a, mut b) = MemoryTransport::pair();
+        let reader = thread::spawn(move || {
+            let mut buf = [0; 5];
+            futures_executor::block_on(AsyncReadExt::read_exact(&mut b, &mut buf)).unwrap();
+            buf
+        });
+        futures_executor::block_on(AsyncWriteExt::write_all(&mut a, b"hel")).unwrap();
+        thread::sleep(Duration::from_millis(10));
+        futures_executor::block_on(AsyncWriteExt::write_all(&mut a, b"lo")).unwrap();
+        assert_eq!(b"hello", &reader.join().unwrap());
+    }
+
+    #[test]
+    fn dropping_an_end() {
+        let (mut a, mut b) = MemoryTransport::pair();
+        a.write_all(b"last").unwrap();
+        drop(a);
+        let mut content = Vec::new();
+        b.read_to_end(&mut content).unwrap();
+        assert_eq!(b"last", &content[..]);
+        assert_eq!(io::ErrorKind::BrokenPipe, b.write(b"x").unwrap_err().kind());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn pass_fds() {
+        use crate::message::{Body, EndianessFlag, Header, HeaderFlags, Message, MessageType};
+        use crate::type_system::Serial;
+        use std::fs::File;
+
+        let (mut a, mut b) = MemoryTransport::pair();
+        a.write_all(b"before").unwrap();
+        let header = Header::new(EndianessFlag::LittleEndian, MessageType::Signal, HeaderFlags::empty(), Serial(1), vec![]);
+        let mut message = Message::new(header, Body::new(vec![]));
+        message.push_fd_argument(OwnedFd::from(File::open("/dev/null").unwrap()));
+        a.send_message(&message).unwrap();
+
+        let mut buf = vec![0; 64];
+        let mut fds = Vec::new();
+        assert_eq!(6, b.recv_with_fds(&mut buf, &mut fds).unwrap());
+        assert!(fds.is_empty());
+        assert_eq!(message.encoded_size(), b.recv_with_fds(&mut buf, &mut fds).unwrap());
+        assert_eq!(message.to_bytes().unwrap(), &buf[..message.encoded_size()]);
+        assert_eq!(1, fds.len());
+    }
+}
@@ -0,0 +1,100 @@
+//! Byte stream transports over which D-Bus connections authenticate and exchange messages.
+
+use std::io::{self, Read, Write};
+#[cfg(unix)]
+use std::os::unix::io::{BorrowedFd, OwnedFd};
+use std::time::Duration;
+
+use crate::message::Message;
+
+#[cfg(unix)]
+pub mod activation;
+pub mod memory;
+pub mod tcp;
+#[cfg(unix)]
+pub mod unix;
+#[cfg(unix)]
+pub mod unixexec;
+
+/// A byte stream that may pass file descriptors along with the bytes, which is all the
+/// authentication and message framing code needs to know about a transport.
+pub trait DbusTransport: Read + Write {
+    /// Whether file descriptors can be passed, deciding if `NEGOTIATE_UNIX_FD` is sent.
+    #[inline]
+    fn supports_unix_fds(&self) -> bool {
+        false
+    }
+
+    /// Makes reads fail with `WouldBlock` or `TimedOut` once no bytes arrived within
+    /// `timeout`, or wait indefinitely if it is `None`.
+    #[inline]
+    fn set_read_timeout(&mut self, _timeout: Option<Duration>) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "Transport does not support read timeouts"))
+    }
+
+    /// Writes bytes from `buf` along with the file descriptors `fds`, which are passed
+    /// with the first byte written. Returns the number of bytes written.
+    #[cfg(unix)]
+    #[inline]
+    fn send_with_fds(&mut self, buf: &[u8], fds: &[BorrowedFd<'_>]) -> io::Result<usize> {
+        if !fds.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "Transport can not pass file descriptors"));
+        }
+        self.write(buf)
+    }
+
+    /// Reads bytes into `buf`, appending file descriptors received with them to `fds`.
+    /// Returns the number of bytes read.
+    #[cfg(unix)]
+    #[inline]
+    fn recv_with_fds(&mut self, buf: &mut [u8], _fds: &mut Vec<OwnedFd>) -> io::Result<usize> {
+        self.read(buf)
+    }
+
+    /// Writes the marshaled `message` along with its file descriptors.
+    #[inline]
+    fn send_message(&mut self, message: &Message) -> io::Result<()> {
+        let bytes = message.to_bytes()?;
+        #[cfg(unix)]
+        {
+            let fds = message.fds()?;
+            let mut written = 0;
+            while written < bytes.len() {
+                let fds: &[BorrowedFd<'_>] = if written == 0 { &fds } else { &[] };
+                match self.send_with_fds(&bytes[written..], fds) {
+                    Ok(0) => return Err(io::Error::new(io::ErrorKind::WriteZero, "Failed to write the whole message")),
+                    Ok(n) => written += n,
+                    Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+                    Err(err) => return Err(err),
+                }
+            }
+            Ok(())
+        }
+        #[cfg(not(unix))]
+        self.write_all(&bytes)
+    }
+}
+
+impl<T: DbusTransport + ?Sized> DbusTransport for Box<T> {
+    #[inline]
+    fn supports_unix_fds(&self) -> bool {
+        (**self).supports_unix_fds()
+    }
+
+    #[inline]
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        (**self).set_read_timeout(timeout)
+    }
+
+    #[cfg(unix)]
+    #[inline]
+    fn send_with_fds(&mut self, buf: &[u8], fds: &[BorrowedFd<'_>]) -> io::Result<usize> {
+        (**self).send_with_fds(buf, fds)
+    }
+
+    #[cfg(unix)]
+    #[inline]
+    fn recv_with_fds(&mut self, buf: &mut [u8], fds: &mut Vec<OwnedFd>) -> io::Result<usize> {
+        (**self).recv_with_fds(buf, fds)
+    }
+}
@@ -0,0 +1,392 @@
+//! The `tcp` and `nonce-tcp` transports. With `nonce-tcp` the client proves it may
+//! read the server's nonce file by sending its 16 bytes before authenticating.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::address::{Address, Family, NonceTcpAddress, TcpAddress, Transport};
+use crate::crypto::{constant_time_eq, random};
+use crate::transport::DbusTransport;
+
+/// Length in bytes of the nonce of the `nonce-tcp` transport.
+pub const NONCE_LENGTH: usize = 16;
+
+/// How long `verify_nonce` waits for a client to send the nonce.
+const NONCE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Resolves `host` and `port`, keeping only addresses of `family` if given.
+fn resolve(host: &str, port: u16, family: Option<Family>) -> io::Result<Vec<SocketAddr>> {
+    let addresses: Vec<SocketAddr> = (host, port)
+        .to_socket_addrs()?
+        .filter(|address| match family {
+            Some(Family::Ipv4) => address.is_ipv4(),
+            Some(Family::Ipv6) => address.is_ipv6(),
+            None => true,
+        })
+        .collect();
+    if addresses.is_empty() {
+        let str_err = format!("Host `{}` has no address of the requested family", host);
+        return Err(io::Error::new(io::ErrorKind::NotFound, str_err));
+    }
+    Ok(addresses)
+}
+
+/// A connected TCP stream.
+#[derive(Debug)]
+pub struct TcpTransport {
+    stream: TcpStream,
+}
+
+impl TcpTransport {
+    /// Connects to `host`, defaulting to `localhost`, on the mandatory `port`.
+    #[inline]
+    pub fn connect(address: &TcpAddress) -> io::Result<TcpTransport> {
+        let port = address.port.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Address has no port"))?;
+        let host = address.host.as_deref().unwrap_or("localhost");
+        let stream = TcpStream::connect(&resolve(host, port, address.family)?[..])?;
+        stream.set_nodelay(true)?;
+        Ok(TcpTransport { stream })
+    }
+
+    /// Connects like [`connect`](TcpTransport::connect) and sends the nonce read from the mandatory `noncefile`.
+    #[inline]
+    pub fn connect_nonce(address: &NonceTcpAddress) -> io::Result<TcpTransport> {
+        let noncefile = address.noncefile.as_ref().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Address has no noncefile"))?;
+        let mut nonce = [0; NONCE_LENGTH];
+        File::open(noncefile)?.read_exact(&mut nonce)?;
+
+        let mut transport = TcpTransport::connect(&address.tcp)?;
+        transport.stream.write_all(&nonce)?;
+        Ok(transport)
+    }
+
+    /// Connects to the first `tcp` or `nonce-tcp` address in `addresses` that accepts the
+    /// connection, returning the transport along with the address it is connected to.
+    #[inline]
+    pub fn connect_first(addresses: &[Address]) -> io::Result<(TcpTransport, &Address)> {
+        let mut last_err = io::Error::new(io::ErrorKind::NotFound, "No tcp address to connect to");
+        for address in addresses {
+            let transport = match &address.transport {
+                Transport::Tcp(tcp) => TcpTransport::connect(tcp),
+                Transport::NonceTcp(nonce_tcp) => TcpTransport::connect_nonce(nonce_tcp),
+                _ => continue,
+            };
+            match transport {
+                Ok(transport) => return Ok((transport, address)),
+                Err(err) => last_err = err,
+            }
+        }
+        Err(last_err)
+    }
+
+    #[inline]
+    pub fn stream(&self) -> &TcpStream {
+        &self.stream
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> TcpStream {
+        self.stream
+    }
+}
+
+impl DbusTransport for TcpTransport {
+    #[inline]
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.set_read_timeout(timeout)
+    }
+}
+
+impl From<TcpStream> for TcpTransport {
+    #[inline]
+    fn from(stream: TcpStream) -> TcpTransport {
+        TcpTransport { stream }
+    }
+}
+
+impl Read for TcpTransport {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl Write for TcpTransport {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// The nonce of a `nonce-tcp` listener and the file it is published in.
+#[derive(Debug)]
+struct Nonce {
+    nonce: [u8; NONCE_LENGTH],
+    path: PathBuf,
+    /// Whether the file was created by the listener and is removed with it.
+    created: bool,
+}
+
+impl Drop for Nonce {
+    fn drop(&mut self) {
+        if self.created {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Writes a new random nonce to `path`, or to a new file in the temporary directory,
+/// readable only by the current user.
+fn create_nonce(path: Option<&str>) -> io::Result<Nonce> {
+    let mut nonce = [0; NONCE_LENGTH];
+    random(&mut nonce)?;
+
+    let (path, created) = match path {
+        Some(path) => (PathBuf::from(path), false),
+        None => (std::env::temp_dir().join(format!("dbus-nonce-{}", random_suffix()?)), true),
+    };
+    write_nonce(&path, &nonce)?;
+    Ok(Nonce { nonce, path, created })
+}
+
+/// Writes `nonce` to a new private file next to `path` and renames it over `path`, so
+/// neither an existing file with wider permissions nor a symlink planted at `path` can
+/// expose the nonce to other users.
+fn write_nonce(path: &Path, nonce: &[u8]) -> io::Result<()> {
+    let mut name = path.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Nonce file path has no file name"))?.to_os_string();
+    name.push(format!(".{}.tmp", random_suffix()?));
+    let tmp = path.with_file_name(name);
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let result = options.open(&tmp).and_then(|mut file| file.write_all(nonce)).and_then(|()| fs::rename(&tmp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+/// Random hex digits for names of files that must not exist yet.
+fn random_suffix() -> io::Result<String> {
+    let mut suffix = [0; 8];
+    random(&mut suffix)?;
+    Ok(suffix.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// A listening TCP socket accepting `tcp` or `nonce-tcp` connections.
+#[derive(Debug)]
+pub struct TcpTransportListener {
+    listener: TcpListener,
+    nonce: Option<Nonce>,
+    nonce_timeout: Duration,
+}
+
+impl TcpTransportListener {
+    /// Listens on the interface `bind`, or `host` if not given, defaulting to `localhost`,
+    /// with `*` listening on all interfaces. Without a `port` one is chosen by the system.
+    #[inline]
+    pub fn bind(address: &TcpAddress) -> io::Result<TcpTransportListener> {
+        let host = address.bind.as_deref().or(address.host.as_deref()).unwrap_or("localhost");
+        let port = address.port.unwrap_or(0);
+        let addresses = match host {
+            "*" => match address.family {
+                Some(Family::Ipv6) => vec![SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port)],
+                _ => vec![SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port)],
+            },
+            host => resolve(host, port, address.family)?,
+        };
+        Ok(TcpTransportListener {
+            listener: TcpListener::bind(&addresses[..])?,
+            nonce: None,
+            nonce_timeout: NONCE_TIMEOUT,
+        })
+    }
+
+    /// Listens like [`bind`](TcpTransportListener::bind) and writes a new nonce to `noncefile`,
+    /// or to a file in the temporary directory that is removed along with the listener.
+    #[inline]
+    pub fn bind_nonce(address: &NonceTcpAddress) -> io::Result<TcpTransportListener> {
+        let mut listener = TcpTransportListener::bind(&address.tcp)?;
+        listener.nonce = Some(create_nonce(address.noncefile.as_deref())?);
+        Ok(listener)
+    }
+
+    /// The address clients connect to, with the port chosen by the system.
+    #[inline]
+    pub fn address(&self) -> io::Result<Address> {
+        let local = self.listener.local_addr()?;
+        let tcp = TcpAddress {
+            host: Some(local.ip().to_string()),
+            bind: None,
+            port: Some(local.port()),
+            family: Some(if local.is_ipv4() { Family::Ipv4 } else { Family::Ipv6 }),
+        };
+        let transport = match &self.nonce {
+            Some(nonce) => {
+                let noncefile = nonce.path.to_str().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Nonce file path is not valid UTF-8"))?;
+                Transport::NonceTcp(NonceTcpAddress { tcp, noncefile: Some(noncefile.to_string()) })
+            }
+            None => Transport::Tcp(tcp),
+        };
+        Ok(Address { transport, guid: None })
+    }
+
+    /// Accepts a connection. With a nonce, it must be checked with
+    /// [`verify_nonce`](TcpTransportListener::verify_nonce) before the connection is used,
+    /// which is best done apart from accepting so a silent client can not stall others.
+    #[inline]
+    pub fn accept(&self) -> io::Result<TcpTransport> {
+        let (stream, _) = self.listener.accept()?;
+        stream.set_nodelay(true)?;
+        Ok(TcpTransport { stream })
+    }
+
+    /// Reads the nonce from a connection accepted by this listener, failing with
+    /// `PermissionDenied` if it is not the listener's nonce, or with `TimedOut` if it does
+    /// not arrive within a few seconds. Does nothing for a `tcp` listener.
+    #[inline]
+    pub fn verify_nonce(&self, transport: &mut TcpTransport) -> io::Result<()> {
+        let nonce = match &self.nonce {
+            Some(nonce) => nonce,
+            None => return Ok(()),
+        };
+        let mut received = [0; NONCE_LENGTH];
+        transport.stream.set_read_timeout(Some(self.nonce_timeout))?;
+        match transport.stream.read_exact(&mut received) {
+            Ok(()) => {}
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock || err.kind() == io::ErrorKind::TimedOut => {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "Client did not send the nonce in time"));
+            }
+            Err(err) => return Err(err),
+        }
+        transport.stream.set_read_timeout(None)?;
+        if !constant_time_eq(&nonce.nonce, &received) {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "Client sent an invalid nonce"));
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub fn listener(&self) -> &TcpListener {
+        &self.listener
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::str::FromStr;
+    use std::thread;
+
+    #[test]
+    fn tcp() {
+        let listener = TcpTransportListener::bind(&TcpAddress { host: Some("127.0.0.1".to_string()), ..TcpAddress::default() }).unwrap();
+        let address = listener.address().unwrap();
+        let server = thread::spawn(move || {
+            let mut transport = listener.accept().unwrap();
+            let mut buf = [0; 5];
+            transport.read_exact(&mut buf).unwrap();
+            buf
+        });
+
+        let addresses = [Address::from_str("unix:path=/nonexistent").unwrap(), address];
+        let (mut transport, connected) = TcpTransport::connect_first(&addresses).unwrap();
+        assert_eq!(&addresses[1], connected);
+        transport.write_all(b"\0AUTH").unwrap();
+        assert_eq!(b"\0AUTH", &server.join().unwrap());
+    }
+
+    #[test]
+    fn nonce_tcp() {
+        let address = NonceTcpAddress::from(TcpAddress { host: Some("127.0.0.1".to_string()), ..TcpAddress::default() });
+        let mut listener = TcpTransportListener::bind_nonce(&address).unwrap();
+        let address = listener.address().unwrap();
+        let noncefile = match &address.transport {
+            Transport::NonceTcp(NonceTcpAddress { noncefile: Some(noncefile), .. }) => PathBuf::from(noncefile),
+            transport => panic!("unexpected transport {:?}", transport),
+        };
+        assert_eq!(NONCE_LENGTH as u64, fs::metadata(&noncefile).unwrap().len());
+        let port = match &address.transport {
+            Transport::NonceTcp(nonce_tcp) => nonce_tcp.tcp.port.unwrap(),
+            _ => unreachable!(),
+        };
+
+        // A client that connected first and stays silent does not hold up the others.
+        let _silent = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let mut silent = listener.accept().unwrap();
+        let client_address = address.clone();
+        let client = thread::spawn(move || {
+            let (mut transport, _) = TcpTransport::connect_first(&[client_address]).unwrap();
+            transport.write_all(b"\0").unwrap();
+        });
+        let mut transport = listener.accept().unwrap();
+        listener.verify_nonce(&mut transport).unwrap();
+        let mut buf = [1];
+        transport.read_exact(&mut buf).unwrap();
+        assert_eq!([0], buf);
+        client.join().unwrap();
+
+        let mut intruder = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        intruder.write_all(&[0; NONCE_LENGTH]).unwrap();
+        let mut transport = listener.accept().unwrap();
+        assert_eq!(io::ErrorKind::PermissionDenied, listener.verify_nonce(&mut transport).unwrap_err().kind());
+
+        listener.nonce_timeout = Duration::from_millis(50);
+        assert_eq!(io::ErrorKind::TimedOut, listener.verify_nonce(&mut silent).unwrap_err().kind());
+
+        drop(listener);
+        assert!(!noncefile.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn existing_noncefile() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let dir = std::env::temp_dir().join(format!("dbus-native-noncefile-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let noncefile = dir.join("nonce");
+        fs::write(&noncefile, b"old").unwrap();
+        fs::set_permissions(&noncefile, fs::Permissions::from_mode(0o644)).unwrap();
+        let target = dir.join("target");
+        fs::write(&target, b"target").unwrap();
+        let link = dir.join("link");
+        symlink(&target, &link).unwrap();
+
+        for path in &[&noncefile, &link] {
+            let tcp = TcpAddress { host: Some("127.0.0.1".to_string()), ..TcpAddress::default() };
+            let address = NonceTcpAddress { tcp, noncefile: Some(path.to_str().unwrap().to_string()) };
+            let listener = TcpTransportListener::bind_nonce(&address).unwrap();
+            let metadata = fs::symlink_metadata(path).unwrap();
+            assert!(metadata.is_file());
+            assert_eq!(0o600, metadata.permissions().mode() & 0o777);
+            assert_eq!(&listener.nonce.as_ref().unwrap().nonce[..], &fs::read(path).unwrap()[..]);
+            drop(listener);
+            assert!(path.exists());
+        }
+        assert_eq!(b"target", &fs::read(&target).unwrap()[..]);
+        assert_eq!(3, fs::read_dir(&dir).unwrap().count());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_port() {
+        let err = TcpTransport::connect(&TcpAddress::default()).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+    }
+}
@@ -0,0 +1,640 @@
+//! The `unix` transport over Unix domain sockets, the only transport able to pass
+//! file descriptors along with messages.
+
+use std::io::{self, Read, Write};
+use std::mem;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::ptr;
+use std::time::Duration;
+
+use crate::address::{Address, Transport, UnixAddress};
+use crate::transport::DbusTransport;
+
+/// The maximum number of file descriptors the kernel passes with a single `sendmsg`.
+pub const MAX_UNIX_FDS: usize = 253;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const SEND_FLAGS: libc::c_int = libc::MSG_NOSIGNAL;
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+const SEND_FLAGS: libc::c_int = 0;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const RECV_FLAGS: libc::c_int = libc::MSG_CMSG_CLOEXEC;
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+const RECV_FLAGS: libc::c_int = 0;
+
+/// Room for the `SCM_CREDENTIALS` control message attached to received bytes while
+/// `SO_PASSCRED` is enabled.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+// SAFETY: CMSG_SPACE only computes a size.
+const CREDENTIALS_SIZE: usize = unsafe { libc::CMSG_SPACE(mem::size_of::<libc::ucred>() as u32) } as usize;
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+const CREDENTIALS_SIZE: usize = 0;
+
+/// `SO_PEERPIDFD`, not yet exported by libc. Linux 6.5 and later.
+#[cfg(all(target_os = "linux", not(any(target_arch = "sparc", target_arch = "sparc64"))))]
+const SO_PEERPIDFD: libc::c_int = 77;
+
+/// Identity of a process, as reported by the kernel for the peer of a socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnixCredentials {
+    pub uid: libc::uid_t,
+    pub gid: libc::gid_t,
+    /// Only known on Linux.
+    pub pid: Option<libc::pid_t>,
+}
+
+impl UnixCredentials {
+    /// The credentials of the current process.
+    #[inline]
+    pub fn current() -> UnixCredentials {
+        // SAFETY: these calls have no preconditions and can not fail.
+        unsafe {
+            UnixCredentials {
+                uid: libc::geteuid(),
+                gid: libc::getegid(),
+                pid: Some(libc::getpid()),
+            }
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+impl From<libc::ucred> for UnixCredentials {
+    #[inline]
+    fn from(ucred: libc::ucred) -> UnixCredentials {
+        UnixCredentials { uid: ucred.uid, gid: ucred.gid, pid: Some(ucred.pid) }
+    }
+}
+
+/// A connected Unix domain stream socket.
+#[derive(Debug)]
+pub struct UnixTransport {
+    stream: UnixStream,
+}
+
+impl UnixTransport {
+    /// Connects to the socket `address` refers to. Only `path` and, on Linux, `abstract`
+    /// addresses can be connected to, the others are used for listening.
+    #[inline]
+    pub fn connect(address: &UnixAddress) -> io::Result<UnixTransport> {
+        let stream = match address {
+            UnixAddress::Path(path) => UnixStream::connect(path)?,
+            UnixAddress::Abstract(name) => connect_abstract(name.as_bytes())?,
+            UnixAddress::Dir(_) | UnixAddress::Tmpdir(_) | UnixAddress::Runtime => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "Address can only be listened on"));
+            }
+        };
+        Ok(UnixTransport { stream })
+    }
+
+    /// Connects to the first `unix` address in `addresses` that accepts the connection,
+    /// returning the transport along with the address it is connected to.
+    #[inline]
+    pub fn connect_first(addresses: &[Address]) -> io::Result<(UnixTransport, &Address)> {
+        let mut last_err = io::Error::new(io::ErrorKind::NotFound, "No unix address to connect to");
+        for address in addresses {
+            if let Transport::Unix(unix) = &address.transport {
+                match UnixTransport::connect(unix) {
+                    Ok(transport) => return Ok((transport, address)),
+                    Err(err) => last_err = err,
+                }
+            }
+        }
+        Err(last_err)
+    }
+
+    #[inline]
+    pub fn stream(&self) -> &UnixStream {
+        &self.stream
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> UnixStream {
+        self.stream
+    }
+
+    /// The credentials of the peer at the time it connected, from `SO_PEERCRED` on Linux
+    /// and `getpeereid` elsewhere, which does not report the pid.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[inline]
+    pub fn peer_credentials(&self) -> io::Result<UnixCredentials> {
+        // SAFETY: ucred is a plain C struct for which all zeros is a valid value.
+        let mut ucred: libc::ucred = unsafe { mem::zeroed() };
+        let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+        // SAFETY: `ucred` and `len` are valid for writes of the given size.
+        let res = unsafe {
+            libc::getsockopt(self.stream.as_raw_fd(), libc::SOL_SOCKET, libc::SO_PEERCRED, &mut ucred as *mut _ as *mut libc::c_void, &mut len)
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(UnixCredentials::from(ucred))
+    }
+
+    /// The credentials of the peer at the time it connected, from `SO_PEERCRED` on Linux
+    /// and `getpeereid` elsewhere, which does not report the pid.
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    #[inline]
+    pub fn peer_credentials(&self) -> io::Result<UnixCredentials> {
+        let mut uid = 0;
+        let mut gid = 0;
+        // SAFETY: `uid` and `gid` are valid for writes.
+        if unsafe { libc::getpeereid(self.stream.as_raw_fd(), &mut uid, &mut gid) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(UnixCredentials { uid, gid, pid: None })
+    }
+
+    /// A pidfd referring to the peer process, which unlike its pid can not be reused once
+    /// the process exits. Taken from `SO_PEERPIDFD` where the kernel supports it, else
+    /// opened for the pid from `SO_PEERCRED`, which is only reliable while the peer is
+    /// known to be alive, e.g. while it is waiting for a reply.
+    #[cfg(target_os = "linux")]
+    #[inline]
+    pub fn peer_pidfd(&self) -> io::Result<OwnedFd> {
+        #[cfg(not(any(target_arch = "sparc", target_arch = "sparc64")))]
+        {
+            let mut fd: libc::c_int = -1;
+            let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+            // SAFETY: `fd` and `len` are valid for writes of the given size.
+            let res = unsafe { libc::getsockopt(self.stream.as_raw_fd(), libc::SOL_SOCKET, SO_PEERPIDFD, &mut fd as *mut _ as *mut libc::c_void, &mut len) };
+            if res == 0 {
+                // SAFETY: the kernel returned a new descriptor owned by this process.
+                return Ok(unsafe { OwnedFd::from_raw_fd(fd) });
+            }
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() != Some(libc::ENOPROTOOPT) {
+                return Err(err);
+            }
+        }
+
+        let pid = self.peer_credentials()?.pid.unwrap_or(0);
+        // SAFETY: pidfd_open takes a pid and flags and returns a new descriptor or -1.
+        let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: the kernel returned a new descriptor owned by this process.
+        Ok(unsafe { OwnedFd::from_raw_fd(fd as RawFd) })
+    }
+
+    /// Enables `SO_PASSCRED`, so the kernel attaches the sender's credentials to received
+    /// bytes. Servers enable it before reading the nul byte with
+    /// [`recv_nul_byte`](UnixTransport::recv_nul_byte).
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[inline]
+    pub fn set_pass_credentials(&self, pass: bool) -> io::Result<()> {
+        let value = pass as libc::c_int;
+        // SAFETY: `value` is valid for reads of the given size.
+        let res = unsafe {
+            libc::setsockopt(self.stream.as_raw_fd(), libc::SOL_SOCKET, libc::SO_PASSCRED, &value as *const _ as *const libc::c_void, mem::size_of::<libc::c_int>() as libc::socklen_t)
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Sends the nul byte that precedes authentication, along with the credentials of
+    /// the current process as `SCM_CREDENTIALS` on Linux.
+    #[inline]
+    pub fn send_nul_byte(&self) -> io::Result<()> {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        {
+            let credentials = UnixCredentials::current();
+            let ucred = libc::ucred { pid: credentials.pid.unwrap_or(0), uid: credentials.uid, gid: credentials.gid };
+            let mut control = ControlBuffer::new(mem::size_of::<libc::ucred>());
+            let mut iov = libc::iovec {
+                iov_base: b"\0".as_ptr() as *mut libc::c_void,
+                iov_len: 1,
+            };
+            // SAFETY: msghdr is a plain C struct for which all zeros is a valid value.
+            let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+            msg.msg_iov = &mut iov;
+            msg.msg_iovlen = 1;
+            msg.msg_control = control.as_mut_ptr();
+            msg.msg_controllen = control.len() as _;
+            // SAFETY: the control buffer is aligned and large enough for one header
+            // followed by a ucred, all pointers in `msg` are valid during the call.
+            let n = unsafe {
+                let cmsg = libc::CMSG_FIRSTHDR(&msg);
+                (*cmsg).cmsg_level = libc::SOL_SOCKET;
+                (*cmsg).cmsg_type = libc::SCM_CREDENTIALS;
+                (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<libc::ucred>() as u32) as _;
+                ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut libc::ucred, ucred);
+                libc::sendmsg(self.stream.as_raw_fd(), &msg, SEND_FLAGS)
+            };
+            match n {
+                1 => Ok(()),
+                0 => Err(io::Error::new(io::ErrorKind::WriteZero, "Failed to write the nul byte")),
+                _ => Err(io::Error::last_os_error()),
+            }
+        }
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        {
+            (&self.stream).write_all(b"\0")
+        }
+    }
+
+    /// Receives the nul byte that precedes authentication, returning the credentials
+    /// passed with it, which the kernel verified or filled in. Credentials are only
+    /// received on Linux with [`set_pass_credentials`](UnixTransport::set_pass_credentials)
+    /// enabled before the byte was sent.
+    #[inline]
+    pub fn recv_nul_byte(&self) -> io::Result<Option<UnixCredentials>> {
+        let mut byte = [1];
+        let mut credentials = None;
+        // SAFETY: msghdr is a plain C struct for which all zeros is a valid value.
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        let mut control = ControlBuffer::new(CREDENTIALS_SIZE);
+        let mut iov = libc::iovec {
+            iov_base: byte.as_mut_ptr() as *mut libc::c_void,
+            iov_len: 1,
+        };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr();
+        msg.msg_controllen = control.len() as _;
+
+        // SAFETY: all pointers in `msg` are valid for the duration of the call.
+        let n = unsafe { libc::recvmsg(self.stream.as_raw_fd(), &mut msg, RECV_FLAGS) };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        } else if n == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Connection closed before the nul byte"));
+        }
+
+        // SAFETY: the kernel filled the control buffer with well-formed control messages.
+        unsafe {
+            let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+            while !cmsg.is_null() {
+                #[cfg(any(target_os = "linux", target_os = "android"))]
+                {
+                    if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_CREDENTIALS {
+                        let ucred = ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::ucred);
+                        credentials = Some(UnixCredentials::from(ucred));
+                    }
+                }
+                if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                    // Descriptors sent with the nul byte are unexpected, close them.
+                    let data = libc::CMSG_DATA(cmsg);
+                    let count = ((*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize) / mem::size_of::<RawFd>();
+                    for i in 0..count {
+                        drop(OwnedFd::from_raw_fd(ptr::read_unaligned((data as *const RawFd).add(i))));
+                    }
+                }
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+        }
+
+        if byte[0] != 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Expected a nul byte before authentication"));
+        }
+        Ok(credentials)
+    }
+
+    /// Writes bytes from `buf` along with the file descriptors `fds`, which are passed
+    /// with the first byte written. Returns the number of bytes written.
+    #[inline]
+    pub fn send_with_fds(&self, buf: &[u8], fds: &[BorrowedFd<'_>]) -> io::Result<usize> {
+        if fds.len() > MAX_UNIX_FDS {
+            let str_err = format!("Can not pass more than {} file descriptors at once", MAX_UNIX_FDS);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, str_err));
+        }
+
+        let raw_fds: Vec<RawFd> = fds.iter().map(|fd| fd.as_raw_fd()).collect();
+        let fds_size = mem::size_of_val(raw_fds.as_slice());
+        let mut control = ControlBuffer::new(if fds.is_empty() { 0 } else { fds_size });
+
+        let mut iov = libc::iovec {
+            iov_base: buf.as_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        };
+        // SAFETY: msghdr is a plain C struct for which all zeros is a valid value.
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        if !fds.is_empty() {
+            msg.msg_control = control.as_mut_ptr();
+            msg.msg_controllen = control.len() as _;
+            // SAFETY: the control buffer is aligned and large enough for one header
+            // followed by `fds_size` bytes of data.
+            unsafe {
+                let cmsg = libc::CMSG_FIRSTHDR(&msg);
+                (*cmsg).cmsg_level = libc::SOL_SOCKET;
+                (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+                (*cmsg).cmsg_len = libc::CMSG_LEN(fds_size as u32) as _;
+                ptr::copy_nonoverlapping(raw_fds.as_ptr() as *const u8, libc::CMSG_DATA(cmsg), fds_size);
+            }
+        }
+
+        // SAFETY: all pointers in `msg` are valid for the duration of the call.
+        let n = unsafe { libc::sendmsg(self.stream.as_raw_fd(), &msg, SEND_FLAGS) };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(n as usize)
+    }
+
+    /// Reads bytes into `buf`, appending file descriptors received with them to `fds`.
+    /// Returns the number of bytes read.
+    #[inline]
+    pub fn recv_with_fds(&self, buf: &mut [u8], fds: &mut Vec<OwnedFd>) -> io::Result<usize> {
+        let mut control = ControlBuffer::new(MAX_UNIX_FDS * mem::size_of::<RawFd>() + CREDENTIALS_SIZE);
+        let mut iov = libc::iovec {
+            iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        };
+        // SAFETY: msghdr is a plain C struct for which all zeros is a valid value.
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr();
+        msg.msg_controllen = control.len() as _;
+
+        // SAFETY: all pointers in `msg` are valid for the duration of the call.
+        let n = unsafe { libc::recvmsg(self.stream.as_raw_fd(), &mut msg, RECV_FLAGS) };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // SAFETY: the kernel filled the control buffer with `msg_controllen` bytes of
+        // well-formed control messages, SCM_RIGHTS data consists of open descriptors
+        // now owned by this process.
+        unsafe {
+            let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+            while !cmsg.is_null() {
+                if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                    let data = libc::CMSG_DATA(cmsg);
+                    let count = ((*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize) / mem::size_of::<RawFd>();
+                    for i in 0..count {
+                        let fd = ptr::read_unaligned((data as *const RawFd).add(i));
+                        fds.push(OwnedFd::from_raw_fd(fd));
+                        set_cloexec(fd)?;
+                    }
+                }
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+        }
+
+        if msg.msg_flags & libc::MSG_CTRUNC != 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Received file descriptors were truncated"));
+        }
+        Ok(n as usize)
+    }
+}
+
+impl DbusTransport for UnixTransport {
+    #[inline]
+    fn supports_unix_fds(&self) -> bool {
+        true
+    }
+
+    #[inline]
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.set_read_timeout(timeout)
+    }
+
+    #[inline]
+    fn send_with_fds(&mut self, buf: &[u8], fds: &[BorrowedFd<'_>]) -> io::Result<usize> {
+        UnixTransport::send_with_fds(self, buf, fds)
+    }
+
+    #[inline]
+    fn recv_with_fds(&mut self, buf: &mut [u8], fds: &mut Vec<OwnedFd>) -> io::Result<usize> {
+        UnixTransport::recv_with_fds(self, buf, fds)
+    }
+}
+
+impl From<UnixStream> for UnixTransport {
+    #[inline]
+    fn from(stream: UnixStream) -> UnixTransport {
+        UnixTransport { stream }
+    }
+}
+
+impl AsFd for UnixTransport {
+    #[inline]
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.stream.as_fd()
+    }
+}
+
+impl Read for UnixTransport {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl Write for UnixTransport {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.send_with_fds(buf, &[])
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn connect_abstract(name: &[u8]) -> io::Result<UnixStream> {
+    #[cfg(target_os = "android")]
+    use std::os::android::net::SocketAddrExt;
+    #[cfg(target_os = "linux")]
+    use std::os::linux::net::SocketAddrExt;
+
+    let address = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+    UnixStream::connect_addr(&address)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn connect_abstract(_name: &[u8]) -> io::Result<UnixStream> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Abstract sockets are only supported on Linux"))
+}
+
+/// Received descriptors are already close-on-exec where `MSG_CMSG_CLOEXEC` is supported.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn set_cloexec(_fd: RawFd) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn set_cloexec(fd: RawFd) -> io::Result<()> {
+    // SAFETY: `fd` is an open descriptor owned by the caller.
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// A buffer for control messages, aligned for `cmsghdr`.
+struct ControlBuffer(Vec<u64>);
+
+impl ControlBuffer {
+    /// A buffer for a single control message with `data_size` bytes of data.
+    fn new(data_size: usize) -> ControlBuffer {
+        // SAFETY: CMSG_SPACE only computes a size.
+        let space = unsafe { libc::CMSG_SPACE(data_size as u32) } as usize;
+        ControlBuffer(vec![0; space.div_ceil(mem::size_of::<u64>())])
+    }
+
+    fn as_mut_ptr(&mut self) -> *mut libc::c_void {
+        self.0.as_mut_ptr() as *mut libc::c_void
+    }
+
+    fn len(&self) -> usize {
+        self.0.len() * mem::size_of::<u64>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::message::{Body, EndianessFlag, Header, HeaderFlags, Message, MessageType};
+    use crate::type_system::Serial;
+    use std::fs::File;
+    use std::io::Seek;
+    use std::os::unix::net::UnixListener;
+    use std::str::FromStr;
+
+    fn pair() -> (UnixTransport, UnixTransport) {
+        let (a, b) = UnixStream::pair().unwrap();
+        (UnixTransport::from(a), UnixTransport::from(b))
+    }
+
+    #[test]
+    fn connect_path() {
+        let dir = std::env::temp_dir().join(format!("dbus-native-unix-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("socket");
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let addresses = format!("unix:path={}/missing;tcp:port=1;unix:path={}", dir.display(), path.display());
+        let addresses = crate::address::parse_addresses(&addresses).unwrap();
+        let (mut transport, address) = UnixTransport::connect_first(&addresses).unwrap();
+        assert_eq!(&addresses[2], address);
+
+        let (mut accepted, _) = listener.accept().unwrap();
+        transport.write_all(b"\0AUTH").unwrap();
+        let mut buf = [0; 5];
+        accepted.read_exact(&mut buf).unwrap();
+        assert_eq!(b"\0AUTH", &buf);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn connect_abstract() {
+        use std::os::linux::net::SocketAddrExt;
+
+        let name = format!("dbus-native-test-{}", std::process::id());
+        let address = std::os::unix::net::SocketAddr::from_abstract_name(name.as_bytes()).unwrap();
+        let listener = UnixListener::bind_addr(&address).unwrap();
+        let address = Address::from_str(&format!("unix:abstract={}", name)).unwrap();
+        assert!(UnixTransport::connect_first(&[address]).is_ok());
+        assert!(listener.accept().is_ok());
+    }
+
+    #[test]
+    fn listen_only_addresses() {
+        let err = UnixTransport::connect(&UnixAddress::Runtime).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+        assert!(UnixTransport::connect_first(&[]).is_err());
+    }
+
+    #[test]
+    fn pass_fds() {
+        let (a, b) = pair();
+        let mut file = tempfile();
+        file.write_all(b"passed").unwrap();
+
+        assert_eq!(3, a.send_with_fds(b"abc", &[file.as_fd()]).unwrap());
+        let mut buf = [0; 16];
+        let mut fds = Vec::new();
+        assert_eq!(3, b.recv_with_fds(&mut buf, &mut fds).unwrap());
+        assert_eq!(1, fds.len());
+
+        let mut received = File::from(fds.pop().unwrap());
+        received.rewind().unwrap();
+        let mut content = String::new();
+        received.read_to_string(&mut content).unwrap();
+        assert_eq!("passed", content);
+    }
+
+    #[test]
+    fn send_message_with_fds() {
+        let (mut a, b) = pair();
+        let header = Header::new(EndianessFlag::LittleEndian, MessageType::Signal, HeaderFlags::empty(), Serial(1), vec![]);
+        let mut message = Message::new(header, Body::new(vec![]));
+        message.push_fd_argument(OwnedFd::from(tempfile()));
+        message.push_fd_argument(OwnedFd::from(tempfile()));
+        a.send_message(&message).unwrap();
+
+        let mut buf = vec![0; message.encoded_size()];
+        let mut fds = Vec::new();
+        assert_eq!(buf.len(), b.recv_with_fds(&mut buf, &mut fds).unwrap());
+        assert_eq!(message.to_bytes().unwrap(), buf);
+        assert_eq!(2, fds.len());
+    }
+
+    #[test]
+    fn peer_credentials() {
+        let (a, b) = pair();
+        let current = UnixCredentials::current();
+        let peer = a.peer_credentials().unwrap();
+        assert_eq!((current.uid, current.gid), (peer.uid, peer.gid));
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        assert_eq!(current, b.peer_credentials().unwrap());
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        drop(b);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn peer_pidfd() {
+        let (a, _b) = pair();
+        match a.peer_pidfd() {
+            Ok(pidfd) => assert!(pidfd.as_raw_fd() >= 0),
+            // Kernels before 5.3 have neither SO_PEERPIDFD nor pidfd_open.
+            Err(err) => assert_eq!(Some(libc::ENOSYS), err.raw_os_error()),
+        }
+    }
+
+    #[test]
+    fn nul_byte() {
+        let (mut a, b) = pair();
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        b.set_pass_credentials(true).unwrap();
+        a.send_nul_byte().unwrap();
+        let credentials = b.recv_nul_byte().unwrap();
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        assert_eq!(Some(UnixCredentials::current()), credentials);
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        assert_eq!(None, credentials);
+
+        // Credentials attached while SO_PASSCRED stays enabled do not truncate fds.
+        let file = tempfile();
+        a.send_with_fds(b"AUTH", &[file.as_fd()]).unwrap();
+        let mut fds = Vec::new();
+        assert_eq!(4, b.recv_with_fds(&mut [0; 4], &mut fds).unwrap());
+        assert_eq!(1, fds.len());
+
+        a.write_all(b"A").unwrap();
+        assert_eq!(io::ErrorKind::InvalidData, b.recv_nul_byte().unwrap_err().kind());
+        drop(a);
+        assert_eq!(io::ErrorKind::UnexpectedEof, b.recv_nul_byte().unwrap_err().kind());
+    }
+
+    fn tempfile() -> File {
+        let path = std::env::temp_dir().join(format!("dbus-native-fd-{}-{:?}", std::process::id(), std::thread::current().id()));
+        let file = File::options().read(true).write(true).create(true).truncate(true).open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        file
+    }
+}
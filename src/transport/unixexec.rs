@@ -0,0 +1,204 @@
+//! The `unixexec` transport, running a program such as `ssh host systemd-stdio-bridge`
+//! and talking to it over its stdin and stdout. Like the reference implementation the
+//! program is given one end of a socket pair as both, so file descriptors can be passed
+//! if the program supports it.
+
+use std::io::{self, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::io::{AsFd, BorrowedFd, OwnedFd};
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::time::Duration;
+
+use crate::address::{Address, Transport, UnixExecAddress};
+use crate::transport::unix::UnixTransport;
+use crate::transport::DbusTransport;
+
+/// A connection to a spawned program. Dropping it closes the connection and kills the
+/// program if it has not exited yet, so it never outlives the transport as a zombie.
+#[derive(Debug)]
+pub struct UnixExecTransport {
+    transport: UnixTransport,
+    child: Child,
+}
+
+impl UnixExecTransport {
+    /// Spawns `path`, searched for in `PATH` if it contains no slash, with the arguments
+    /// `argv1`, `argv2`, … and `argv0`, defaulting to `path`, as its name. Its stderr is
+    /// inherited.
+    #[inline]
+    pub fn spawn(address: &UnixExecAddress) -> io::Result<UnixExecTransport> {
+        let (stream, remote) = UnixStream::pair()?;
+        let remote = OwnedFd::from(remote);
+
+        let mut command = Command::new(&address.path);
+        command.args(&address.args).stdin(Stdio::from(remote.try_clone()?)).stdout(Stdio::from(remote));
+        if let Some(argv0) = &address.argv0 {
+            command.arg0(argv0);
+        }
+        let child = command.spawn()?;
+        Ok(UnixExecTransport { transport: UnixTransport::from(stream), child })
+    }
+
+    /// Spawns the program of the first `unixexec` address in `addresses` that can be
+    /// spawned, returning the transport along with its address.
+    #[inline]
+    pub fn spawn_first(addresses: &[Address]) -> io::Result<(UnixExecTransport, &Address)> {
+        let mut last_err = io::Error::new(io::ErrorKind::NotFound, "No unixexec address to spawn");
+        for address in addresses {
+            if let Transport::UnixExec(unixexec) = &address.transport {
+                match UnixExecTransport::spawn(unixexec) {
+                    Ok(transport) => return Ok((transport, address)),
+                    Err(err) => last_err = err,
+                }
+            }
+        }
+        Err(last_err)
+    }
+
+    #[inline]
+    pub fn transport(&self) -> &UnixTransport {
+        &self.transport
+    }
+
+    #[inline]
+    pub fn child(&self) -> &Child {
+        &self.child
+    }
+
+    /// Returns the exit status if the program has exited, without waiting for it.
+    #[inline]
+    pub fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+        self.child.try_wait()
+    }
+
+    /// Closes the connection and waits for the program to exit.
+    #[inline]
+    pub fn wait(mut self) -> io::Result<ExitStatus> {
+        let _ = self.transport.stream().shutdown(Shutdown::Both);
+        self.child.wait()
+    }
+}
+
+impl Drop for UnixExecTransport {
+    #[inline]
+    fn drop(&mut self) {
+        let _ = self.transport.stream().shutdown(Shutdown::Both);
+        if let Ok(None) = self.child.try_wait() {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+impl DbusTransport for UnixExecTransport {
+    #[inline]
+    fn supports_unix_fds(&self) -> bool {
+        true
+    }
+
+    #[inline]
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.transport.set_read_timeout(timeout)
+    }
+
+    #[inline]
+    fn send_with_fds(&mut self, buf: &[u8], fds: &[BorrowedFd<'_>]) -> io::Result<usize> {
+        self.transport.send_with_fds(buf, fds)
+    }
+
+    #[inline]
+    fn recv_with_fds(&mut self, buf: &mut [u8], fds: &mut Vec<OwnedFd>) -> io::Result<usize> {
+        self.transport.recv_with_fds(buf, fds)
+    }
+}
+
+impl AsFd for UnixExecTransport {
+    #[inline]
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.transport.as_fd()
+    }
+}
+
+impl Read for UnixExecTransport {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.transport.read(buf)
+    }
+}
+
+impl Write for UnixExecTransport {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.transport.write(buf)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        self.transport.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::str::FromStr;
+
+    fn spawn(address: &str) -> UnixExecTransport {
+        match Address::from_str(address).unwrap().transport {
+            Transport::UnixExec(unixexec) => UnixExecTransport::spawn(&unixexec).unwrap(),
+            transport => panic!("unexpected transport {:?}", transport),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut transport = spawn("unixexec:path=cat");
+        transport.write_all(b"\0AUTH EXTERNAL\r\n").unwrap();
+        let mut buf = [0; 16];
+        transport.read_exact(&mut buf).unwrap();
+        assert_eq!(b"\0AUTH EXTERNAL\r\n", &buf);
+        assert!(transport.wait().unwrap().success());
+    }
+
+    #[test]
+    fn arguments() {
+        let mut transport = spawn("unixexec:path=sh,argv1=-c,argv2=printf%20%25s%3a%25s%20%22%240%22%20%22%241%22,argv3=a%2cb,argv4=c");
+        let mut output = String::new();
+        transport.read_to_string(&mut output).unwrap();
+        assert_eq!("a,b:c", output);
+    }
+
+    #[test]
+    fn argv0() {
+        let mut transport = spawn("unixexec:path=sh,argv0=bridge");
+        transport.write_all(b"printf %s \"$0\"\n").unwrap();
+        transport.transport().stream().shutdown(Shutdown::Write).unwrap();
+        let mut output = String::new();
+        transport.read_to_string(&mut output).unwrap();
+        assert_eq!("bridge", output);
+    }
+
+    #[test]
+    fn spawn_first() {
+        let addresses = [
+            Address::from_str("tcp:host=localhost,port=1").unwrap(),
+            Address::from_str("unixexec:path=/nonexistent").unwrap(),
+            Address::from_str("unixexec:path=true").unwrap(),
+        ];
+        let (transport, address) = UnixExecTransport::spawn_first(&addresses).unwrap();
+        assert_eq!(&addresses[2], address);
+        assert!(transport.wait().unwrap().success());
+    }
+
+    #[test]
+    fn drop_kills_child() {
+        let transport = spawn("unixexec:path=sleep,argv1=1000");
+        let pid = transport.child().id() as libc::pid_t;
+        drop(transport);
+        assert_eq!(-1, unsafe { libc::kill(pid, 0) });
+        assert_eq!(Some(libc::ESRCH), io::Error::last_os_error().raw_os_error());
+    }
+}
@@ -1,9 +1,13 @@
 use byteorder::ByteOrder;
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::fmt;
 use std::hash::{BuildHasher, Hash};
 use std::io;
+use std::str::FromStr;
 
-use crate::writer::{DbusWriter, DbusWrite};
+use crate::names::{escape_element, unescape_element};
+use crate::writer::{padding, signature_size, string_size, DbusWriter, DbusWrite};
 
 #[cfg(test)]
 mod tests {
@@ -23,8 +27,8 @@ mod tests {
         assert_eq!("t", (30u64).to_type_code());
         assert_eq!("d", (36.6f64).to_type_code());
         assert_eq!("s", "abc".to_type_code());
-        assert_eq!("o", ObjectPath("obj".to_string()).to_type_code());
-        assert_eq!("g", Signature("obj".to_string()).to_type_code());
+        assert_eq!("o", ObjectPath("obj".into()).to_type_code());
+        assert_eq!("g", Signature("obj".into()).to_type_code());
     }
 
     #[test]
@@ -33,6 +37,82 @@ mod tests {
         assert_eq!("ass", vec.to_type_code());
     }
 
+    #[test]
+    fn object_path() {
+        assert_eq!(Ok(ObjectPath("/".into())), ObjectPath::from_str("/"));
+        assert_eq!(Ok(ObjectPath("/org/freedesktop/DBus_1".into())), ObjectPath::from_str("/org/freedesktop/DBus_1"));
+        assert_eq!(Err(ObjectPathError::MissingLeadingSlash), ObjectPath::from_str(""));
+        assert_eq!(Err(ObjectPathError::MissingLeadingSlash), ObjectPath::from_str("org/freedesktop"));
+        assert_eq!(Err(ObjectPathError::TrailingSlash), ObjectPath::from_str("/org/"));
+        assert_eq!(Err(ObjectPathError::EmptyElement), ObjectPath::from_str("/org//freedesktop"));
+        assert_eq!(Err(ObjectPathError::InvalidCharacter('-')), ObjectPath::from_str("/org/free-desktop"));
+    }
+
+    #[test]
+    fn object_path_children() {
+        let devices = ObjectPath::from_str("/org/example/devices").unwrap();
+        let child = devices.child(b"SN-0042/a");
+        assert_eq!("/org/example/devices/SN_2d0042_2fa", child.0);
+        assert!(ObjectPath::from_str(&child.0).is_ok());
+        assert_eq!(Some(b"SN-0042/a".to_vec()), child.child_id(&devices));
+        assert_eq!(None, devices.child_id(&devices));
+        assert_eq!(None, ObjectPath::from_str("/org/example/devices/a/b").unwrap().child_id(&devices));
+        assert_eq!(None, ObjectPath::from_str("/org/example/devicesX").unwrap().child_id(&devices));
+
+        let root = ObjectPath::from_str("/").unwrap();
+        assert_eq!("/_", root.child(b"").0);
+        assert_eq!(Some(Vec::new()), root.child(b"").child_id(&root));
+    }
+
+    #[test]
+    fn signature() {
+        assert_eq!(Ok(Signature("a{sv}(ii)as".into())), Signature::from_str("a{sv}(ii)as"));
+        assert_eq!(Ok(Signature("".into())), Signature::from_str(""));
+        assert_eq!(Err(SignatureError::InvalidCharacter('m')), Signature::from_str("ms"));
+        assert_eq!(Err(SignatureError::UnexpectedEnd), Signature::from_str("aa"));
+        assert_eq!(Err(SignatureError::UnbalancedParentheses), Signature::from_str("(ii"));
+        assert_eq!(Err(SignatureError::UnbalancedParentheses), Signature::from_str("ii)"));
+        assert_eq!(Err(SignatureError::EmptyStruct), Signature::from_str("()"));
+        assert_eq!(Err(SignatureError::DictEntryOutsideArray), Signature::from_str("{sv}"));
+        assert_eq!(Err(SignatureError::DictEntryKeyMustBeBasicType), Signature::from_str("a{vs}"));
+        assert_eq!(Err(SignatureError::DictEntryMustHaveTwoTypes), Signature::from_str("a{sss}"));
+        assert_eq!(Err(SignatureError::ExceedsMaxDepth), Signature::from_str(&format!("{}y", "a".repeat(33))));
+        assert_eq!(Err(SignatureError::ExceedsMaxSize), Signature::from_str(&"y".repeat(256)));
+
+        assert_eq!(Ok(vec![Type::Maybe(Box::new(Type::String)), Type::Struct(vec![])]), Type::parse_gvariant("ms()"));
+        assert_eq!("a{sv}", Type::parse_single("a{sv}").unwrap().to_string());
+    }
+
+    /// `validate_signature` is a constant re-implementation of `TypeParser` and must agree with it.
+    fn agrees_with_parser(signature: &str) -> bool {
+        validate_signature(signature) == Type::parse(signature).map(|_| ())
+    }
+
+    #[test]
+    fn validate_signature_agrees_with_parser() {
+        let signatures = [
+            "", "a{sv}(ii)as", "ms", "aa", "(ii", "ii)", "()", "{sv}", "a{vs}", "a{sss}", "a{s}", "a{(i)s}", "a{as}", "(a{sv}a(ii))",
+            "a{sv", "a(i}", "a{si)", "x", "*",
+        ];
+        for signature in &signatures {
+            assert!(agrees_with_parser(signature), "{}", signature);
+        }
+        for depth in 31..34 {
+            assert!(agrees_with_parser(&format!("{}y", "a".repeat(depth))));
+            assert!(agrees_with_parser(&format!("{}y{}", "(".repeat(depth), ")".repeat(depth))));
+            assert!(agrees_with_parser(&format!("{}a{{sy}}{}", "(".repeat(depth), ")".repeat(depth))));
+        }
+        assert!(agrees_with_parser(&"y".repeat(256)));
+    }
+
+    quickcheck! {
+        fn validate_signature_agrees_with_parser_on_random_codes(codes: Vec<u8>) -> bool {
+            const CODES: &[u8] = b"ybnqiuxtdsoghvam(){}";
+            let signature: String = codes.iter().map(|c| CODES[*c as usize % CODES.len()] as char).collect();
+            agrees_with_parser(&signature)
+        }
+    }
+
     #[test]
     fn type_code_hashmap() {
         let mut hmap = HashMap::new();
@@ -46,7 +126,7 @@ pub type TypeCode = String;
 
 /// Marker type for DictEntry enforcing that only basic types can act as key.
 /// >  The first single complete type (the "key") must be a basic type rather than a container type.
-///    Implementations must not accept [..] dict entries with non-basic-typed keys.
+/// >  Implementations must not accept [..] dict entries with non-basic-typed keys.
 pub trait BasicType {}
 
 impl BasicType for u8 {}
@@ -63,6 +143,181 @@ impl BasicType for String {}
 impl BasicType for ObjectPath {}
 impl BasicType for Signature {}
 
+/// Basic types whose marshaled size equals their alignment, such that arrays of them
+/// are a plain run of bytes which can be copied at once.
+pub trait FixedSizeType: Copy + Default {
+    /// Size and alignment of the marshaled type in bytes.
+    const SIZE: usize;
+
+    /// Encodes `src` into `dst` in the byte order `T`, `dst` must be `src.len() * SIZE` bytes long.
+    fn to_bytes<T: ByteOrder>(src: &[Self], dst: &mut [u8]);
+
+    /// Decodes `src` in the byte order `T` into `dst`, `src` must be `dst.len() * SIZE` bytes long.
+    fn from_bytes<T: ByteOrder>(src: &[u8], dst: &mut [Self]);
+
+    /// The bytes of `src` in the host byte order, without copying.
+    fn native_bytes(src: &[Self]) -> &[u8];
+}
+
+impl FixedSizeType for u8 {
+    const SIZE: usize = 1;
+
+    #[inline]
+    fn to_bytes<T: ByteOrder>(src: &[u8], dst: &mut [u8]) {
+        dst.copy_from_slice(src);
+    }
+
+    #[inline]
+    fn from_bytes<T: ByteOrder>(src: &[u8], dst: &mut [u8]) {
+        dst.copy_from_slice(src);
+    }
+
+    #[inline]
+    fn native_bytes(src: &[u8]) -> &[u8] {
+        src
+    }
+}
+
+macro_rules! impl_fixed_size_type {
+    ($t:ty, $size:expr, $write_into:ident, $read_into:ident) => {
+        impl FixedSizeType for $t {
+            const SIZE: usize = $size;
+
+            #[inline]
+            fn to_bytes<T: ByteOrder>(src: &[$t], dst: &mut [u8]) {
+                T::$write_into(src, dst);
+            }
+
+            #[inline]
+            fn from_bytes<T: ByteOrder>(src: &[u8], dst: &mut [$t]) {
+                T::$read_into(src, dst);
+            }
+
+            #[inline]
+            fn native_bytes(src: &[$t]) -> &[u8] {
+                // SAFETY: the primitive numeric types have no padding bytes, and the
+                // returned slice covers exactly the memory of `src` for its lifetime.
+                unsafe { std::slice::from_raw_parts(src.as_ptr() as *const u8, std::mem::size_of_val(src)) }
+            }
+        }
+    };
+}
+
+impl_fixed_size_type!(i16, 2, write_i16_into, read_i16_into);
+impl_fixed_size_type!(u16, 2, write_u16_into, read_u16_into);
+impl_fixed_size_type!(i32, 4, write_i32_into, read_i32_into);
+impl_fixed_size_type!(u32, 4, write_u32_into, read_u32_into);
+impl_fixed_size_type!(i64, 8, write_i64_into, read_i64_into);
+impl_fixed_size_type!(u64, 8, write_u64_into, read_u64_into);
+impl_fixed_size_type!(f64, 8, write_f64_into, read_f64_into);
+
+/// Basic types that are marshaled in their natural byte order and alignment.
+macro_rules! impl_dbus_write_fixed_size {
+    ($t:ty, $write:ident) => {
+        impl DbusWrite for $t {
+            const ALIGNMENT: usize = <$t as FixedSizeType>::SIZE;
+
+            #[inline]
+            fn write<T1, T2>(&self, writer: &mut DbusWriter<T1>) -> Result<(), io::Error>
+                where T1: io::Write,
+                      T2: ByteOrder
+            {
+                writer.$write::<T2>(*self)
+            }
+
+            #[inline]
+            fn write_elements<T1, T2>(elements: &[$t], writer: &mut DbusWriter<T1>) -> Result<(), io::Error>
+                where T1: io::Write,
+                      T2: ByteOrder
+            {
+                writer.write_fixed_elements::<T2, $t>(elements)
+            }
+
+            #[inline]
+            fn encoded_size(&self, offset: usize) -> usize {
+                padding(offset, Self::ALIGNMENT) + Self::ALIGNMENT
+            }
+
+            #[inline]
+            fn elements_size(elements: &[$t], _offset: usize) -> usize {
+                elements.len() * <$t as FixedSizeType>::SIZE
+            }
+        }
+    };
+}
+
+impl_dbus_write_fixed_size!(i16, write_i16);
+impl_dbus_write_fixed_size!(u16, write_u16);
+impl_dbus_write_fixed_size!(i32, write_i32);
+impl_dbus_write_fixed_size!(u32, write_u32);
+impl_dbus_write_fixed_size!(i64, write_i64);
+impl_dbus_write_fixed_size!(u64, write_u64);
+impl_dbus_write_fixed_size!(f64, write_f64);
+
+impl DbusWrite for u8 {
+    const ALIGNMENT: usize = 1;
+
+    #[inline]
+    fn write<T1, T2>(&self, writer: &mut DbusWriter<T1>) -> Result<(), io::Error>
+        where T1: io::Write,
+              T2: ByteOrder
+    {
+        writer.write_u8(*self)
+    }
+
+    #[inline]
+    fn write_elements<T1, T2>(elements: &[u8], writer: &mut DbusWriter<T1>) -> Result<(), io::Error>
+        where T1: io::Write,
+              T2: ByteOrder
+    {
+        writer.write_all(elements)
+    }
+
+    #[inline]
+    fn encoded_size(&self, _offset: usize) -> usize {
+        1
+    }
+
+    #[inline]
+    fn elements_size(elements: &[u8], _offset: usize) -> usize {
+        elements.len()
+    }
+}
+
+impl DbusWrite for bool {
+    const ALIGNMENT: usize = 4;
+
+    #[inline]
+    fn write<T1, T2>(&self, writer: &mut DbusWriter<T1>) -> Result<(), io::Error>
+        where T1: io::Write,
+              T2: ByteOrder
+    {
+        writer.write_boolean::<T2>(*self)
+    }
+
+    #[inline]
+    fn encoded_size(&self, offset: usize) -> usize {
+        padding(offset, 4) + 4
+    }
+}
+
+impl DbusWrite for String {
+    const ALIGNMENT: usize = 4;
+
+    #[inline]
+    fn write<T1, T2>(&self, writer: &mut DbusWriter<T1>) -> Result<(), io::Error>
+        where T1: io::Write,
+              T2: ByteOrder
+    {
+        writer.write_string::<T2>(self)
+    }
+
+    #[inline]
+    fn encoded_size(&self, offset: usize) -> usize {
+        string_size(offset, self.len())
+    }
+}
+
 pub trait ToTypeCode: Sized {
     fn to_type_code(&self) -> TypeCode;
 }
@@ -72,14 +327,13 @@ pub trait ToTypeCode: Sized {
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Serial(pub u32);
 
-struct Variant {
-    
-}
+pub struct Variant {}
 
 /// VARIANT has ASCII character 'v' as its type code.
 /// A marshaled value of type VARIANT will have the signature of a single complete type as part of the value.
 /// This signature will be followed by a marshaled value of that type.
 impl ToTypeCode for Variant {
+    #[inline]
     fn to_type_code(&self) -> TypeCode {
         "v".to_string()
         // TODO add remaining variants ?
@@ -91,21 +345,152 @@ impl ToTypeCode for Variant {
 /// object instances (think of C++ or Java objects) and each such instance will have a path.
 /// Like a filesystem, the object instances in an application form a hierarchical tree.
 #[derive(Clone, Debug, PartialEq, Eq)]
-pub struct ObjectPath(pub String);
+pub struct ObjectPath(pub Cow<'static, str>);
 
-// TODO impl from str for ObjectPath see "Valid Object Paths"
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ObjectPathError {
+    /// The path must begin with an ASCII '/' (integer 47) character.
+    MissingLeadingSlash,
+
+    /// Each element must only contain the ASCII characters "[A-Z][a-z][0-9]_".
+    InvalidCharacter(char),
+
+    /// No element may be the empty string.
+    EmptyElement,
+
+    /// A trailing '/' character is not allowed unless the path is the root path (a single '/' character).
+    TrailingSlash,
+}
+
+impl fmt::Display for ObjectPathError {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ObjectPathError::MissingLeadingSlash => write!(f, "object path must begin with `/`"),
+            ObjectPathError::InvalidCharacter(c) => write!(f, "invalid character `{}` in object path", c),
+            ObjectPathError::EmptyElement => write!(f, "object path elements must not be empty"),
+            ObjectPathError::TrailingSlash => write!(f, "trailing `/` in object path"),
+        }
+    }
+}
+
+impl std::error::Error for ObjectPathError {}
+
+/// Checks the rules of "Valid Object Paths", usable in constant expressions.
+const fn validate_object_path(s: &str) -> Result<(), ObjectPathError> {
+    let bytes = s.as_bytes();
+    if bytes.is_empty() || bytes[0] != b'/' {
+        return Err(ObjectPathError::MissingLeadingSlash);
+    }
+    if bytes.len() == 1 {
+        return Ok(());
+    }
+
+    let mut i = 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'/' if bytes[i - 1] == b'/' => return Err(ObjectPathError::EmptyElement),
+            b'/' | b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'_' => {}
+            _ => return Err(ObjectPathError::InvalidCharacter(char_at(bytes, i))),
+        }
+        i += 1;
+    }
+    if bytes[bytes.len() - 1] == b'/' {
+        return Err(ObjectPathError::TrailingSlash);
+    }
+    Ok(())
+}
+
+/// Decodes the character starting at index `i` of the valid UTF-8 `bytes`.
+pub(crate) const fn char_at(bytes: &[u8], i: usize) -> char {
+    let first = bytes[i] as u32;
+    let (len, mut c) = if first < 0x80 {
+        (1, first)
+    } else if first < 0xe0 {
+        (2, first & 0x1f)
+    } else if first < 0xf0 {
+        (3, first & 0x0f)
+    } else {
+        (4, first & 0x07)
+    };
+    let mut j = 1;
+    while j < len {
+        c = (c << 6) | (bytes[i + j] as u32 & 0x3f);
+        j += 1;
+    }
+    match char::from_u32(c) {
+        Some(c) => c,
+        None => char::REPLACEMENT_CHARACTER,
+    }
+}
+
+impl ObjectPath {
+    /// Borrows `s` without copying it, for use in constant expressions such as [`object_path!`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `s` is not a valid object path, which fails compilation in a constant.
+    #[inline]
+    pub const fn from_static(s: &'static str) -> ObjectPath {
+        match validate_object_path(s) {
+            Ok(()) => ObjectPath(Cow::Borrowed(s)),
+            Err(_) => panic!("invalid object path"),
+        }
+    }
+}
+
+impl FromStr for ObjectPath {
+    type Err = ObjectPathError;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<ObjectPath, ObjectPathError> {
+        validate_object_path(s)?;
+        Ok(ObjectPath(Cow::Owned(s.to_string())))
+    }
+}
+
+impl ObjectPath {
+    /// The child path whose last element is the escaped `id`, e.g. for objects keyed by
+    /// user supplied identifiers. Compatible with systemd's `sd_bus_path_encode`.
+    #[inline]
+    pub fn child(&self, id: &[u8]) -> ObjectPath {
+        let parent = if self.0 == "/" { "" } else { &self.0 };
+        ObjectPath(Cow::Owned(format!("{}/{}", parent, escape_element(id))))
+    }
+
+    /// The unescaped identifier of this path if it is a direct [`child`](ObjectPath::child)
+    /// of `parent`. Compatible with systemd's `sd_bus_path_decode`.
+    #[inline]
+    pub fn child_id(&self, parent: &ObjectPath) -> Option<Vec<u8>> {
+        let prefix = if parent.0 == "/" { "" } else { &parent.0 };
+        let element = self.0.strip_prefix(prefix)?.strip_prefix('/')?;
+        if element.is_empty() || element.contains('/') {
+            return None;
+        }
+        unescape_element(element).ok()
+    }
+}
 
 impl DbusWrite for ObjectPath {
+    const ALIGNMENT: usize = 4;
+
+    #[inline]
     fn write<T1, T2>(&self, writer: &mut DbusWriter<T1>) -> Result<(), io::Error>
         where T1: io::Write,
               T2: ByteOrder
     {
         writer.write_string::<T2>(&self.0)
     }
+
+    #[inline]
+    fn encoded_size(&self, offset: usize) -> usize {
+        string_size(offset, self.0.len())
+    }
 }
 
 /// based on "Basic type" - Table
 impl ToTypeCode for ObjectPath {
+    #[inline]
     fn to_type_code(&self) -> TypeCode {
         "o".to_string()
     }
@@ -115,21 +500,416 @@ impl ToTypeCode for ObjectPath {
 /// (thus signatures have a maximum length of 255) and the
 /// content must be a valid signature (see above).
 #[derive(Clone, Debug, PartialEq, Eq)]
-pub struct Signature(pub String);
+pub struct Signature(pub Cow<'static, str>);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SignatureError {
+    /// The maximum length of a signature is 255.
+    ExceedsMaxSize,
+
+    /// The signature may only contain the type codes of the type system.
+    InvalidCharacter(char),
+
+    /// The signature ends in the middle of a single complete type.
+    UnexpectedEnd,
+
+    /// STRUCT has a type code, ASCII character 'r', but this type code does not appear in signatures.
+    /// Instead, ASCII characters '(' and ')' are used to mark the beginning and end of the struct.
+    UnbalancedParentheses,
+
+    /// DICT_ENTRY types are written with ASCII characters '{' and '}'.
+    UnbalancedBraces,
+
+    /// Empty structures are not allowed; there must be at least one type code between the parentheses.
+    EmptyStruct,
+
+    /// A DICT_ENTRY must occur only as an array element type.
+    DictEntryOutsideArray,
+
+    /// The first single complete type (the "key") must be a basic type rather than a container type.
+    DictEntryKeyMustBeBasicType,
+
+    /// A DICT_ENTRY must contain exactly two single complete types.
+    DictEntryMustHaveTwoTypes,
+
+    /// The maximum depth of container type nesting is 32 array type codes and 32 open parentheses.
+    ExceedsMaxDepth,
+
+    /// Exactly one single complete type was expected.
+    NotSingleCompleteType,
+}
+
+impl fmt::Display for SignatureError {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SignatureError::ExceedsMaxSize => write!(f, "signature exceeds the maximum length of 255"),
+            SignatureError::InvalidCharacter(c) => write!(f, "invalid type code `{}`", c),
+            SignatureError::UnexpectedEnd => write!(f, "signature ends within a single complete type"),
+            SignatureError::UnbalancedParentheses => write!(f, "unbalanced parentheses"),
+            SignatureError::UnbalancedBraces => write!(f, "unbalanced braces"),
+            SignatureError::EmptyStruct => write!(f, "empty structures are not allowed"),
+            SignatureError::DictEntryOutsideArray => write!(f, "dict entries must be array elements"),
+            SignatureError::DictEntryKeyMustBeBasicType => write!(f, "dict entry keys must be basic types"),
+            SignatureError::DictEntryMustHaveTwoTypes => write!(f, "dict entries must contain exactly two types"),
+            SignatureError::ExceedsMaxDepth => write!(f, "container nesting exceeds the maximum depth"),
+            SignatureError::NotSingleCompleteType => write!(f, "expected a single complete type"),
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {}
+
+/// Checks that `signature` is a sequence of zero or more single complete types,
+/// usable in constant expressions. Agrees with `Type::parse`.
+const fn validate_signature(signature: &str) -> Result<(), SignatureError> {
+    let bytes = signature.as_bytes();
+    if bytes.len() > 255 {
+        return Err(SignatureError::ExceedsMaxSize);
+    }
+
+    let mut position = 0;
+    while position < bytes.len() {
+        position = match validate_type(bytes, position, 0, 0, false) {
+            Ok(end) => end,
+            Err(err) => return Err(err),
+        };
+    }
+    Ok(())
+}
+
+/// Checks the single complete type starting at `position`, returning the position after it.
+const fn validate_type(bytes: &[u8], position: usize, arrays: usize, structs: usize, in_array: bool) -> Result<usize, SignatureError> {
+    if position == bytes.len() {
+        return Err(SignatureError::UnexpectedEnd);
+    }
+    let start = position + 1;
+    let mut position = start;
+
+    match bytes[start - 1] {
+        b'y' | b'b' | b'n' | b'q' | b'i' | b'u' | b'x' | b't' | b'd' | b's' | b'o' | b'g' | b'h' | b'v' => Ok(position),
+        b'a' => {
+            if arrays == MAX_CONTAINER_DEPTH {
+                return Err(SignatureError::ExceedsMaxDepth);
+            }
+            validate_type(bytes, position, arrays + 1, structs, true)
+        }
+        b'(' => {
+            if structs == MAX_CONTAINER_DEPTH {
+                return Err(SignatureError::ExceedsMaxDepth);
+            }
+            loop {
+                if position == bytes.len() {
+                    return Err(SignatureError::UnbalancedParentheses);
+                }
+                if bytes[position] == b')' {
+                    break;
+                }
+                position = match validate_type(bytes, position, arrays, structs + 1, false) {
+                    Ok(end) => end,
+                    Err(err) => return Err(err),
+                };
+            }
+            if position == start {
+                return Err(SignatureError::EmptyStruct);
+            }
+            Ok(position + 1)
+        }
+        b'{' => {
+            if !in_array {
+                return Err(SignatureError::DictEntryOutsideArray);
+            }
+            if structs == MAX_CONTAINER_DEPTH {
+                return Err(SignatureError::ExceedsMaxDepth);
+            }
+            let mut fields = 0;
+            loop {
+                if position == bytes.len() {
+                    return Err(SignatureError::UnbalancedBraces);
+                }
+                if bytes[position] == b'}' {
+                    break;
+                }
+                position = match validate_type(bytes, position, arrays, structs + 1, false) {
+                    Ok(end) => end,
+                    Err(err) => return Err(err),
+                };
+                fields += 1;
+            }
+            if fields != 2 {
+                return Err(SignatureError::DictEntryMustHaveTwoTypes);
+            }
+            if matches!(bytes[start], b'a' | b'(' | b'{' | b'v') {
+                return Err(SignatureError::DictEntryKeyMustBeBasicType);
+            }
+            Ok(position + 1)
+        }
+        b')' => Err(SignatureError::UnbalancedParentheses),
+        b'}' => Err(SignatureError::UnbalancedBraces),
+        c => Err(SignatureError::InvalidCharacter(c as char)),
+    }
+}
+
+impl Signature {
+    /// Borrows `s` without copying it, for use in constant expressions such as [`signature!`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `s` is not a valid signature, which fails compilation in a constant.
+    #[inline]
+    pub const fn from_static(s: &'static str) -> Signature {
+        match validate_signature(s) {
+            Ok(()) => Signature(Cow::Borrowed(s)),
+            Err(_) => panic!("invalid signature"),
+        }
+    }
+}
+
+impl FromStr for Signature {
+    type Err = SignatureError;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Signature, SignatureError> {
+        validate_signature(s)?;
+        Ok(Signature(Cow::Owned(s.to_string())))
+    }
+}
+
+/// The maximum depth of container type nesting is 32 array type codes and 32 open parentheses.
+pub const MAX_CONTAINER_DEPTH: usize = 32;
+
+/// A single complete type, parsed from a signature.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Type {
+    Byte,
+    Boolean,
+    Int16,
+    Uint16,
+    Int32,
+    Uint32,
+    Int64,
+    Uint64,
+    Double,
+    String,
+    ObjectPath,
+    Signature,
+    UnixFd,
+    Array(Box<Type>),
+    Struct(Vec<Type>),
+    DictEntry(Box<Type>, Box<Type>),
+    Variant,
+    /// The GVariant maybe type, it has no D-Bus encoding.
+    Maybe(Box<Type>),
+}
+
+impl Type {
+    /// Parses the single complete types of a D-Bus signature.
+    #[inline]
+    pub fn parse(signature: &str) -> Result<Vec<Type>, SignatureError> {
+        TypeParser::new(signature, false).parse_all()
+    }
+
+    /// Parses a signature consisting of exactly one single complete type.
+    #[inline]
+    pub fn parse_single(signature: &str) -> Result<Type, SignatureError> {
+        let mut types = Type::parse(signature)?;
+        if types.len() != 1 {
+            return Err(SignatureError::NotSingleCompleteType);
+        }
+        Ok(types.remove(0))
+    }
+
+    /// Parses the single complete types of a GVariant type string, which in addition
+    /// allows the maybe type `m`, the unit type `()` and dict entries outside of arrays.
+    #[inline]
+    pub fn parse_gvariant(signature: &str) -> Result<Vec<Type>, SignatureError> {
+        TypeParser::new(signature, true).parse_all()
+    }
+
+    /// Basic types are the types that are not containers and may be used as dict entry keys.
+    #[inline]
+    pub fn is_basic(&self) -> bool {
+        !matches!(self, Type::Array(_) | Type::Struct(_) | Type::DictEntry(..) | Type::Variant | Type::Maybe(_))
+    }
 
-// TODO impl from str for Signature see "Valid Signatures"
+    /// Alignment boundary of the type in bytes in the D-Bus encoding.
+    #[inline]
+    pub fn alignment(&self) -> usize {
+        match self {
+            Type::Byte | Type::Signature | Type::Variant | Type::Maybe(_) => 1,
+            Type::Int16 | Type::Uint16 => 2,
+            Type::Boolean
+            | Type::Int32
+            | Type::Uint32
+            | Type::UnixFd
+            | Type::String
+            | Type::ObjectPath
+            | Type::Array(_) => 4,
+            Type::Int64 | Type::Uint64 | Type::Double | Type::Struct(_) | Type::DictEntry(..) => 8,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Byte => write!(f, "y"),
+            Type::Boolean => write!(f, "b"),
+            Type::Int16 => write!(f, "n"),
+            Type::Uint16 => write!(f, "q"),
+            Type::Int32 => write!(f, "i"),
+            Type::Uint32 => write!(f, "u"),
+            Type::Int64 => write!(f, "x"),
+            Type::Uint64 => write!(f, "t"),
+            Type::Double => write!(f, "d"),
+            Type::String => write!(f, "s"),
+            Type::ObjectPath => write!(f, "o"),
+            Type::Signature => write!(f, "g"),
+            Type::UnixFd => write!(f, "h"),
+            Type::Array(element) => write!(f, "a{}", element),
+            Type::Struct(fields) => {
+                write!(f, "(")?;
+                for field in fields {
+                    write!(f, "{}", field)?;
+                }
+                write!(f, ")")
+            }
+            Type::DictEntry(key, value) => write!(f, "{{{}{}}}", key, value),
+            Type::Variant => write!(f, "v"),
+            Type::Maybe(element) => write!(f, "m{}", element),
+        }
+    }
+}
+
+struct TypeParser<'a> {
+    signature: &'a [u8],
+    position: usize,
+    gvariant: bool,
+}
+
+impl<'a> TypeParser<'a> {
+    fn new(signature: &'a str, gvariant: bool) -> TypeParser<'a> {
+        TypeParser {
+            signature: signature.as_bytes(),
+            position: 0,
+            gvariant,
+        }
+    }
+
+    fn parse_all(mut self) -> Result<Vec<Type>, SignatureError> {
+        if self.signature.len() > 255 {
+            return Err(SignatureError::ExceedsMaxSize);
+        }
+
+        let mut types = Vec::new();
+        while self.position < self.signature.len() {
+            types.push(self.parse_type(0, 0, false)?);
+        }
+        Ok(types)
+    }
+
+    fn parse_type(&mut self, arrays: usize, structs: usize, in_array: bool) -> Result<Type, SignatureError> {
+        let c = match self.signature.get(self.position) {
+            Some(c) => *c,
+            None => return Err(SignatureError::UnexpectedEnd),
+        };
+        self.position += 1;
+
+        let t = match c {
+            b'y' => Type::Byte,
+            b'b' => Type::Boolean,
+            b'n' => Type::Int16,
+            b'q' => Type::Uint16,
+            b'i' => Type::Int32,
+            b'u' => Type::Uint32,
+            b'x' => Type::Int64,
+            b't' => Type::Uint64,
+            b'd' => Type::Double,
+            b's' => Type::String,
+            b'o' => Type::ObjectPath,
+            b'g' => Type::Signature,
+            b'h' => Type::UnixFd,
+            b'v' => Type::Variant,
+            b'a' => {
+                if arrays == MAX_CONTAINER_DEPTH {
+                    return Err(SignatureError::ExceedsMaxDepth);
+                }
+                Type::Array(Box::new(self.parse_type(arrays + 1, structs, true)?))
+            }
+            b'm' if self.gvariant => Type::Maybe(Box::new(self.parse_type(arrays, structs, false)?)),
+            b'(' => {
+                if structs == MAX_CONTAINER_DEPTH {
+                    return Err(SignatureError::ExceedsMaxDepth);
+                }
+                let mut fields = Vec::new();
+                loop {
+                    match self.signature.get(self.position) {
+                        Some(b')') => break,
+                        Some(_) => fields.push(self.parse_type(arrays, structs + 1, false)?),
+                        None => return Err(SignatureError::UnbalancedParentheses),
+                    }
+                }
+                self.position += 1;
+                if fields.is_empty() && !self.gvariant {
+                    return Err(SignatureError::EmptyStruct);
+                }
+                Type::Struct(fields)
+            }
+            b'{' => {
+                if !in_array && !self.gvariant {
+                    return Err(SignatureError::DictEntryOutsideArray);
+                }
+                if structs == MAX_CONTAINER_DEPTH {
+                    return Err(SignatureError::ExceedsMaxDepth);
+                }
+                let mut fields = Vec::new();
+                loop {
+                    match self.signature.get(self.position) {
+                        Some(b'}') => break,
+                        Some(_) => fields.push(self.parse_type(arrays, structs + 1, false)?),
+                        None => return Err(SignatureError::UnbalancedBraces),
+                    }
+                }
+                self.position += 1;
+                if fields.len() != 2 {
+                    return Err(SignatureError::DictEntryMustHaveTwoTypes);
+                }
+                let value = fields.pop().unwrap();
+                let key = fields.pop().unwrap();
+                if !key.is_basic() {
+                    return Err(SignatureError::DictEntryKeyMustBeBasicType);
+                }
+                Type::DictEntry(Box::new(key), Box::new(value))
+            }
+            b')' => return Err(SignatureError::UnbalancedParentheses),
+            b'}' => return Err(SignatureError::UnbalancedBraces),
+            c => return Err(SignatureError::InvalidCharacter(c as char)),
+        };
+        Ok(t)
+    }
+}
 
 impl DbusWrite for Signature {
+    const ALIGNMENT: usize = 1;
+
+    #[inline]
     fn write<T1, T2>(&self, writer: &mut DbusWriter<T1>) -> Result<(), io::Error>
         where T1: io::Write,
               T2: ByteOrder
     {
-        writer.write_string::<T2>(&self.0)
+        writer.write_signature_str(&self.0)
+    }
+
+    #[inline]
+    fn encoded_size(&self, _offset: usize) -> usize {
+        signature_size(self.0.len())
     }
 }
 
 /// based on "Basic type" - Table
 impl ToTypeCode for Signature {
+    #[inline]
     fn to_type_code(&self) -> TypeCode {
         "g".to_string()
     }
@@ -138,8 +918,176 @@ impl ToTypeCode for Signature {
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct UnixFd(pub u32);
 
+/// Alignment boundary in bytes of the single complete type starting with `type_code`.
+#[inline]
+pub fn alignment_of(type_code: u8) -> usize {
+    match type_code {
+        b'n' | b'q' => 2,
+        b'b' | b'i' | b'u' | b'h' | b's' | b'o' | b'a' => 4,
+        b'x' | b't' | b'd' | b'(' | b'{' => 8,
+        _ => 1,
+    }
+}
+
+impl Signature {
+    /// Alignment boundary in bytes of the first single complete type of the signature.
+    #[inline]
+    pub fn alignment(&self) -> usize {
+        self.0.bytes().next().map_or(1, alignment_of)
+    }
+}
+
+/// A value of the D-Bus type system whose type is only known at runtime,
+/// such as message arguments or the content of a VARIANT.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Byte(u8),
+    Boolean(bool),
+    Int16(i16),
+    Uint16(u16),
+    Int32(i32),
+    Uint32(u32),
+    Int64(i64),
+    Uint64(u64),
+    Double(f64),
+    String(String),
+    ObjectPath(ObjectPath),
+    Signature(Signature),
+    UnixFd(UnixFd),
+    /// The signature is the single complete type of the elements,
+    /// it is kept separately such that empty arrays can be marshaled.
+    Array(Signature, Vec<Value>),
+    Struct(Vec<Value>),
+    DictEntry(Box<Value>, Box<Value>),
+    Variant(Box<Value>),
+    /// The GVariant maybe type, the signature is the single complete type of the content.
+    /// It has no D-Bus encoding.
+    Maybe(Signature, Option<Box<Value>>),
+}
+
+impl Value {
+    /// The signature of the single complete type of this value.
+    #[inline]
+    pub fn signature(&self) -> Signature {
+        let mut signature = String::new();
+        self.push_signature(&mut signature);
+        Signature(signature.into())
+    }
+
+    fn push_signature(&self, signature: &mut String) {
+        match self {
+            Value::Byte(_) => signature.push('y'),
+            Value::Boolean(_) => signature.push('b'),
+            Value::Int16(_) => signature.push('n'),
+            Value::Uint16(_) => signature.push('q'),
+            Value::Int32(_) => signature.push('i'),
+            Value::Uint32(_) => signature.push('u'),
+            Value::Int64(_) => signature.push('x'),
+            Value::Uint64(_) => signature.push('t'),
+            Value::Double(_) => signature.push('d'),
+            Value::String(_) => signature.push('s'),
+            Value::ObjectPath(_) => signature.push('o'),
+            Value::Signature(_) => signature.push('g'),
+            Value::UnixFd(_) => signature.push('h'),
+            Value::Array(element, _) => {
+                signature.push('a');
+                signature.push_str(&element.0);
+            }
+            Value::Struct(fields) => {
+                signature.push('(');
+                for field in fields {
+                    field.push_signature(signature);
+                }
+                signature.push(')');
+            }
+            Value::DictEntry(key, value) => {
+                signature.push('{');
+                key.push_signature(signature);
+                value.push_signature(signature);
+                signature.push('}');
+            }
+            Value::Variant(_) => signature.push('v'),
+            Value::Maybe(element, _) => {
+                signature.push('m');
+                signature.push_str(&element.0);
+            }
+        }
+    }
+
+    /// Alignment boundary of the marshaled value in bytes.
+    #[inline]
+    pub fn alignment(&self) -> usize {
+        match self {
+            Value::Byte(_) | Value::Signature(_) | Value::Variant(_) | Value::Maybe(..) => 1,
+            Value::Int16(_) | Value::Uint16(_) => 2,
+            Value::Boolean(_)
+            | Value::Int32(_)
+            | Value::Uint32(_)
+            | Value::UnixFd(_)
+            | Value::String(_)
+            | Value::ObjectPath(_)
+            | Value::Array(..) => 4,
+            Value::Int64(_)
+            | Value::Uint64(_)
+            | Value::Double(_)
+            | Value::Struct(_)
+            | Value::DictEntry(..) => 8,
+        }
+    }
+
+    /// Exact number of bytes the marshaled value occupies when starting at `offset`,
+    /// including the leading alignment padding.
+    #[inline]
+    pub fn encoded_size(&self, offset: usize) -> usize {
+        let start = offset + padding(offset, self.alignment());
+        let end = match self {
+            Value::Byte(_) => start + 1,
+            Value::Int16(_) | Value::Uint16(_) => start + 2,
+            Value::Boolean(_) | Value::Int32(_) | Value::Uint32(_) | Value::UnixFd(_) => start + 4,
+            Value::Int64(_) | Value::Uint64(_) | Value::Double(_) => start + 8,
+            Value::String(s) => start + string_size(start, s.len()),
+            Value::ObjectPath(path) => start + string_size(start, path.0.len()),
+            Value::Signature(signature) => start + signature_size(signature.0.len()),
+            Value::Array(element, elements) => {
+                let elements_start = start + 4;
+                let elements_start = elements_start + padding(elements_start, element.alignment());
+                Value::sequence_end(elements, elements_start)
+            }
+            Value::Struct(fields) => Value::sequence_end(fields, start),
+            Value::DictEntry(key, value) => {
+                let value_start = start + key.encoded_size(start);
+                value_start + value.encoded_size(value_start)
+            }
+            Value::Variant(value) => {
+                let value_start = start + signature_size(value.signature().0.len());
+                value_start + value.encoded_size(value_start)
+            }
+            Value::Maybe(..) => start,
+        };
+        end - offset
+    }
+
+    /// Position after marshaling `values` back to back starting at `start`.
+    #[inline]
+    pub fn sequence_end(values: &[Value], start: usize) -> usize {
+        values.iter().fold(start, |end, x| end + x.encoded_size(end))
+    }
+
+    /// Fails with `InvalidInput` unless all `elements` of an array have the signature `element`.
+    pub(crate) fn check_elements(element: &Signature, elements: &[Value]) -> Result<(), io::Error> {
+        match elements.iter().map(Value::signature).find(|signature| signature != element) {
+            Some(signature) => {
+                let str_err = format!("Array element of signature `{}` does not match the element signature `{}`", signature.0, element.0);
+                Err(io::Error::new(io::ErrorKind::InvalidInput, str_err))
+            }
+            None => Ok(()),
+        }
+    }
+}
+
 /// based on "Basic type" - Table
 impl ToTypeCode for UnixFd {
+    #[inline]
     fn to_type_code(&self) -> TypeCode {
         "h".to_string()
     }
@@ -148,6 +1096,7 @@ impl ToTypeCode for UnixFd {
 
 /// based on "Basic type" - Table
 impl ToTypeCode for u8 {
+    #[inline]
     fn to_type_code(&self) -> TypeCode {
         "y".to_string()
     }
@@ -155,6 +1104,7 @@ impl ToTypeCode for u8 {
 
 /// based on "Basic type" - Table
 impl ToTypeCode for bool {
+    #[inline]
     fn to_type_code(&self) -> TypeCode {
         "b".to_string()
     }
@@ -162,6 +1112,7 @@ impl ToTypeCode for bool {
 
 /// based on "Basic type" - Table
 impl ToTypeCode for i16 {
+    #[inline]
     fn to_type_code(&self) -> TypeCode {
         "n".to_string()
     }
@@ -169,6 +1120,7 @@ impl ToTypeCode for i16 {
 
 /// based on "Basic type" - Table
 impl ToTypeCode for u16 {
+    #[inline]
     fn to_type_code(&self) -> TypeCode {
         "q".to_string()
     }
@@ -176,6 +1128,7 @@ impl ToTypeCode for u16 {
 
 /// based on "Basic type" - Table
 impl ToTypeCode for i32 {
+    #[inline]
     fn to_type_code(&self) -> TypeCode {
         "i".to_string()
     }
@@ -183,6 +1136,7 @@ impl ToTypeCode for i32 {
 
 /// based on "Basic type" - Table
 impl ToTypeCode for u32 {
+    #[inline]
     fn to_type_code(&self) -> TypeCode {
         "u".to_string()
     }
@@ -190,6 +1144,7 @@ impl ToTypeCode for u32 {
 
 /// based on "Basic type" - Table
 impl ToTypeCode for i64 {
+    #[inline]
     fn to_type_code(&self) -> TypeCode {
         "x".to_string()
     }
@@ -197,6 +1152,7 @@ impl ToTypeCode for i64 {
 
 /// based on "Basic type" - Table
 impl ToTypeCode for u64 {
+    #[inline]
     fn to_type_code(&self) -> TypeCode {
         "t".to_string()
     }
@@ -204,6 +1160,7 @@ impl ToTypeCode for u64 {
 
 /// based on "Basic type" - Table
 impl ToTypeCode for f64 {
+    #[inline]
     fn to_type_code(&self) -> TypeCode {
         "d".to_string()
     }
@@ -211,6 +1168,7 @@ impl ToTypeCode for f64 {
 
 /// based on "Basic type" - Table
 impl ToTypeCode for String {
+    #[inline]
     fn to_type_code(&self) -> TypeCode {
         "s".to_string()
     }
@@ -218,6 +1176,7 @@ impl ToTypeCode for String {
 
 /// based on "Basic type" - Table
 impl ToTypeCode for &str {
+    #[inline]
     fn to_type_code(&self) -> TypeCode {
         "s".to_string()
     }
@@ -228,9 +1187,10 @@ impl ToTypeCode for &str {
 /// The array type code must be followed by a single complete type.
 /// The single complete type following the array is the type of each array element.
 impl<T: ToTypeCode> ToTypeCode for Vec<T> {
+    #[inline]
     fn to_type_code(&self) -> TypeCode {
         let mut type_code = String::new();
-        type_code.push_str("a");
+        type_code.push('a');
         for x in self.iter() {
             type_code.push_str(&x.to_type_code());
         }
@@ -239,12 +1199,20 @@ impl<T: ToTypeCode> ToTypeCode for Vec<T> {
 }
 
 impl DbusWrite for Serial {
+    const ALIGNMENT: usize = 4;
+
+    #[inline]
     fn write<T1, T2>(&self, writer: &mut DbusWriter<T1>) -> Result<(), io::Error>
         where T1: io::Write,
               T2: ByteOrder
     {
         writer.write_u32::<T2>(self.0)
     }
+
+    #[inline]
+    fn encoded_size(&self, offset: usize) -> usize {
+        padding(offset, 4) + 4
+    }
 }
 
 /// A DICT_ENTRY works exactly like a struct, but rather than parentheses
@@ -254,16 +1222,17 @@ where K: BasicType + ToTypeCode + Eq + Hash,
       V: ToTypeCode,
       S: BuildHasher
 {
+    #[inline]
     fn to_type_code(&self) -> TypeCode {
         let mut type_code = String::new();
-        type_code.push_str("{");
+        type_code.push('{');
 
         if let Some((key, value)) = self.iter().next() {
             type_code.push_str(&key.to_type_code());
             type_code.push_str(&value.to_type_code());
         }
 
-        type_code.push_str("}");
+        type_code.push('}');
         type_code
     }
 }
\ No newline at end of file
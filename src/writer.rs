@@ -1,98 +1,414 @@
 use std::io;
 use byteorder::{WriteBytesExt, ByteOrder};
-use crate::type_system::{ObjectPath, Signature};
+use crate::type_system::{FixedSizeType, ObjectPath, Signature, Value};
 
 type Result<T> = std::result::Result<T, std::io::Error>;
 
 pub trait DbusWrite {
+    /// Alignment boundary of the marshaled type in bytes.
+    const ALIGNMENT: usize;
+
     fn write<T1, T2>(&self, writer: &mut DbusWriter<T1>) -> Result<()>
         where T1: io::Write,
               T2: ByteOrder;
+
+    /// Writes the content of an ARRAY with elements of this type, i.e. everything
+    /// following the alignment padding after the array length.
+    /// Fixed-size basic types override this to copy all elements at once.
+    #[inline]
+    fn write_elements<T1, T2>(elements: &[Self], writer: &mut DbusWriter<T1>) -> Result<()>
+        where Self: Sized,
+              T1: io::Write,
+              T2: ByteOrder
+    {
+        for x in elements {
+            x.write::<T1, T2>(writer)?;
+        }
+        Ok(())
+    }
+
+    /// Exact number of bytes `write` produces when starting at `offset`,
+    /// including the leading alignment padding.
+    fn encoded_size(&self, offset: usize) -> usize;
+
+    /// Exact number of bytes `write_elements` produces when starting at `offset`,
+    /// which must already be aligned to `ALIGNMENT`.
+    #[inline]
+    fn elements_size(elements: &[Self], offset: usize) -> usize
+        where Self: Sized
+    {
+        elements.iter().fold(offset, |end, x| end + x.encoded_size(end)) - offset
+    }
 }
 
 pub struct DbusWriter<T: io::Write> {
     writer: T,
+    /// Number of bytes written so far, used to compute alignment padding.
+    position: usize,
 }
 
 impl<T: io::Write> DbusWriter<T> {
+    #[inline]
     pub fn new(writer: T) -> DbusWriter<T> {
+        DbusWriter::with_position(writer, 0)
+    }
+
+    /// Creates a writer that continues marshaling at `position`, e.g. for writing
+    /// a message body after a header that has been written separately.
+    #[inline]
+    pub fn with_position(writer: T, position: usize) -> DbusWriter<T> {
         DbusWriter {
-            writer
+            writer,
+            position,
         }
     }
 
+    /// Number of bytes written so far.
+    #[inline]
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.writer
+    }
+
+    #[inline]
     pub fn write_invalid(&self) -> Result<()> {
         Err(io::Error::new(io::ErrorKind::InvalidInput, "HeaderField::Invalid can not be marshaled!"))
     }
 
+    /// Writes nul bytes until the position is a multiple of `alignment`.
+    #[inline]
+    pub fn write_padding(&mut self, alignment: usize) -> Result<()> {
+        const ZEROS: [u8; 8] = [0; 8];
+        let padding = padding(self.position, alignment);
+        self.write_all(&ZEROS[..padding])
+    }
+
+    #[inline]
+    pub fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+        self.writer.write_all(buf)?;
+        self.position += buf.len();
+        Ok(())
+    }
+
     /// A single 8-bit byte.
+    #[inline]
     pub fn write_u8(&mut self, n: u8) -> Result<()> {
-        self.writer.write_u8(n)
+        self.writer.write_u8(n)?;
+        self.position += 1;
+        Ok(())
     }
 
     /// As for UINT32, but only 0 and 1 are valid values.
+    #[inline]
     pub fn write_boolean<T1: ByteOrder>(&mut self, b: bool) -> Result<()> {
-        self.writer.write_u32::<T1>(b as u32)
+        self.write_u32::<T1>(b as u32)
     }
 
     /// 16-bit signed integer in the message's byte order.
+    #[inline]
     pub fn write_i16<T1: ByteOrder>(&mut self, i: i16) -> Result<()> {
-        self.writer.write_i16::<T1>(i)
+        self.write_padding(2)?;
+        self.writer.write_i16::<T1>(i)?;
+        self.position += 2;
+        Ok(())
     }
 
     /// 16-bit unsigned integer in the message's byte order.
+    #[inline]
     pub fn write_u16<T1: ByteOrder>(&mut self, u: u16) -> Result<()> {
-        self.writer.write_u16::<T1>(u)
+        self.write_padding(2)?;
+        self.writer.write_u16::<T1>(u)?;
+        self.position += 2;
+        Ok(())
     }
 
     /// 32-bit signed integer in the message's byte order.
+    #[inline]
     pub fn write_i32<T1: ByteOrder>(&mut self, i: i32) -> Result<()> {
-        self.writer.write_i32::<T1>(i)
+        self.write_padding(4)?;
+        self.writer.write_i32::<T1>(i)?;
+        self.position += 4;
+        Ok(())
     }
 
     /// 32-bit unsigned integer in the message's byte order.
+    #[inline]
     pub fn write_u32<T1: ByteOrder>(&mut self, u: u32) -> Result<()> {
-        self.writer.write_u32::<T1>(u)
+        self.write_padding(4)?;
+        self.writer.write_u32::<T1>(u)?;
+        self.position += 4;
+        Ok(())
     }
 
     /// 64-bit signed integer in the message's byte order.
+    #[inline]
     pub fn write_i64<T1: ByteOrder>(&mut self, i: i64) -> Result<()> {
-        self.writer.write_i64::<T1>(i)
+        self.write_padding(8)?;
+        self.writer.write_i64::<T1>(i)?;
+        self.position += 8;
+        Ok(())
     }
 
     /// 64-bit unsigned integer in the message's byte order.
+    #[inline]
     pub fn write_u64<T1: ByteOrder>(&mut self, u: u64) -> Result<()> {
-        self.writer.write_u64::<T1>(u)
+        self.write_padding(8)?;
+        self.writer.write_u64::<T1>(u)?;
+        self.position += 8;
+        Ok(())
+    }
+
+    /// 64-bit IEEE 754 double in the message's byte order.
+    #[inline]
+    pub fn write_f64<T1: ByteOrder>(&mut self, f: f64) -> Result<()> {
+        self.write_padding(8)?;
+        self.writer.write_f64::<T1>(f)?;
+        self.position += 8;
+        Ok(())
     }
 
     /// A UINT32 indicating the string's length in bytes excluding its terminating nul,
     /// followed by non-nul string data of the given length, followed by a terminating nul byte.
+    #[inline]
     pub fn write_string<T1: ByteOrder>(&mut self, s: &str) -> Result<()> {
-        self.writer.write_u32::<T1>(s.len() as u32)?;
-        self.writer.write_all(s.as_bytes())?;
-        self.writer.write_u8(b'\n')?;
+        self.write_u32::<T1>(s.len() as u32)?;
+        self.write_all(s.as_bytes())?;
+        self.write_u8(0)?;
         Ok(())
     }
 
     /// Exactly the same as STRING except the content must be a valid object path (see above).
+    #[inline]
     pub fn write_object_path<T1: ByteOrder>(&mut self, object_path: ObjectPath) -> Result<()> {
         self.write_string::<T1>(&object_path.0)
     }
 
     /// The same as STRING except the length is a single byte (thus signatures
     /// have a maximum length of 255) and the content must be a valid signature (see above).
+    #[inline]
     pub fn write_signature<T1: ByteOrder>(&mut self, signature: Signature) -> Result<()> {
-        self.write_string::<T1>(&signature.0)
+        self.write_signature_str(&signature.0)
+    }
+
+    #[inline]
+    pub fn write_signature_str(&mut self, s: &str) -> Result<()> {
+        if s.len() > MAX_SIGNATURE_LENGTH {
+            let str_err = format!("Signature length `{}` exceeds maximum of {} bytes", s.len(), MAX_SIGNATURE_LENGTH);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, str_err));
+        }
+        self.write_u8(s.len() as u8)?;
+        self.write_all(s.as_bytes())?;
+        self.write_u8(0)?;
+        Ok(())
     }
 
     /// A UINT32 giving the length of the array data in bytes, followed by alignment
     /// padding to the alignment boundary of the array element type, followed by each array element.
+    #[inline]
     pub fn write_array<T1: ByteOrder, T2: DbusWrite>(&mut self, a: &[T2]) -> Result<()> {
-        self.writer.write_u32::<T1>(a.len() as u32)?;
-        for x in a {
-            x.write::<_, T1>(self)?;
+        self.write_padding(4)?;
+        let elements_position = self.position + 4 + padding(self.position + 4, T2::ALIGNMENT);
+        let len = T2::elements_size(a, elements_position);
+
+        self.write_u32::<T1>(array_length(len)?)?;
+        self.write_padding(T2::ALIGNMENT)?;
+        T2::write_elements::<T, T1>(a, self)
+    }
+
+    /// Marshals a value of any type, its signature is not written except
+    /// for the content of variants.
+    #[inline]
+    pub fn write_value<T1: ByteOrder>(&mut self, value: &Value) -> Result<()> {
+        match value {
+            Value::Byte(n) => self.write_u8(*n),
+            Value::Boolean(b) => self.write_boolean::<T1>(*b),
+            Value::Int16(i) => self.write_i16::<T1>(*i),
+            Value::Uint16(u) => self.write_u16::<T1>(*u),
+            Value::Int32(i) => self.write_i32::<T1>(*i),
+            Value::Uint32(u) => self.write_u32::<T1>(*u),
+            Value::Int64(i) => self.write_i64::<T1>(*i),
+            Value::Uint64(u) => self.write_u64::<T1>(*u),
+            Value::Double(f) => self.write_f64::<T1>(*f),
+            Value::String(s) => self.write_string::<T1>(s),
+            Value::ObjectPath(object_path) => self.write_string::<T1>(&object_path.0),
+            Value::Signature(signature) => self.write_signature_str(&signature.0),
+            Value::UnixFd(fd) => self.write_u32::<T1>(fd.0),
+            Value::Array(element, elements) => {
+                Value::check_elements(element, elements)?;
+                self.write_padding(4)?;
+                let elements_position = self.position + 4 + padding(self.position + 4, element.alignment());
+                let len = Value::sequence_end(elements, elements_position) - elements_position;
+
+                self.write_u32::<T1>(array_length(len)?)?;
+                self.write_padding(element.alignment())?;
+                for x in elements {
+                    self.write_value::<T1>(x)?;
+                }
+                Ok(())
+            }
+            Value::Struct(fields) => {
+                if fields.is_empty() {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "Empty structures are not allowed"));
+                }
+                self.write_padding(8)?;
+                for field in fields {
+                    self.write_value::<T1>(field)?;
+                }
+                Ok(())
+            }
+            Value::DictEntry(key, value) => {
+                self.write_padding(8)?;
+                self.write_value::<T1>(key)?;
+                self.write_value::<T1>(value)
+            }
+            Value::Variant(value) => {
+                self.write_signature_str(&value.signature().0)?;
+                self.write_value::<T1>(value)
+            }
+            Value::Maybe(..) => {
+                Err(io::Error::new(io::ErrorKind::InvalidInput, "The maybe type has no D-Bus encoding"))
+            }
         }
-        Ok(())
+    }
+
+    /// Writes the content of an array of fixed-size basic types at once, copying to swap
+    /// bytes only if `T1` is not the host byte order.
+    #[inline]
+    pub fn write_fixed_elements<T1: ByteOrder, T2: FixedSizeType>(&mut self, a: &[T2]) -> Result<()> {
+        if is_native::<T1>() {
+            return self.write_all(T2::native_bytes(a));
+        }
+        let mut buffer = vec![0; a.len() * T2::SIZE];
+        T2::to_bytes::<T1>(a, &mut buffer);
+        self.write_all(&buffer)
     }
 }
 
+/// Whether `T` is the byte order of the host.
+fn is_native<T: ByteOrder>() -> bool {
+    T::read_u16(&[0, 1]) == u16::from_ne_bytes([0, 1])
+}
+
+/// Number of padding bytes needed at `position` to reach the next multiple of `alignment`.
+#[inline]
+pub fn padding(position: usize, alignment: usize) -> usize {
+    (alignment - position % alignment) % alignment
+}
+
+/// Encoded size of a STRING or OBJECT_PATH with `len` bytes of content starting at `offset`.
+#[inline]
+pub fn string_size(offset: usize, len: usize) -> usize {
+    padding(offset, 4) + 4 + len + 1
+}
+
+/// Encoded size of a SIGNATURE with `len` bytes of content.
+#[inline]
+pub fn signature_size(len: usize) -> usize {
+    1 + len + 1
+}
+
+/// Array data may not exceed 2 to the 26th power or 67108864 (64 MiB).
+fn array_length(len: usize) -> Result<u32> {
+    if len > MAX_ARRAY_LENGTH {
+        let str_err = format!("Array length `{}` exceeds maximum of {} bytes", len, MAX_ARRAY_LENGTH);
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, str_err));
+    }
+    Ok(len as u32)
+}
+
+/// Arrays have a maximum length defined to be 2 to the 26th power or 67108864 (64 MiB).
+pub const MAX_ARRAY_LENGTH: usize = 1 << 26;
+
+/// The maximum length of a signature is 255.
+pub const MAX_SIGNATURE_LENGTH: usize = 255;
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::reader::DbusReader;
+    use byteorder::{BigEndian, LittleEndian};
+
+    fn write_array<T1: ByteOrder, T2: DbusWrite>(position: usize, a: &[T2]) -> Vec<u8> {
+        let mut writer = DbusWriter::with_position(Vec::new(), position);
+        writer.write_array::<T1, T2>(a).unwrap();
+        writer.into_inner()
+    }
+
+    fn read_fixed_array<T1: ByteOrder, T2: FixedSizeType>(position: usize, buf: &[u8]) -> Vec<T2> {
+        let mut reader = DbusReader::with_position(buf, position);
+        reader.read_fixed_array::<T1, T2>().unwrap()
+    }
+
+    #[test]
+    fn byte_array() {
+        assert_eq!(vec![3, 0, 0, 0, 1, 2, 3], write_array::<LittleEndian, u8>(0, &[1, 2, 3]));
+        assert_eq!(vec![0, 0, 0, 3, 1, 2, 3], write_array::<BigEndian, u8>(0, &[1, 2, 3]));
+    }
+
+    #[test]
+    fn fixed_array_padding() {
+        // length at offset 4, first element at offset 8
+        let expected = vec![0, 0, 0, 16, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(expected, write_array::<LittleEndian, u64>(1, &[1, 2]));
+
+        // the element padding is written even for empty arrays
+        assert_eq!(vec![0, 0, 0, 0, 0, 0, 0, 0], write_array::<BigEndian, u64>(0, &[]));
+    }
+
+    #[test]
+    fn fixed_array_byte_swapping() {
+        assert_eq!(vec![4, 0, 0, 0, 0x34, 0x12, 0x78, 0x56], write_array::<LittleEndian, u16>(0, &[0x1234, 0x5678]));
+        assert_eq!(vec![0, 0, 0, 4, 0x12, 0x34, 0x56, 0x78], write_array::<BigEndian, u16>(0, &[0x1234, 0x5678]));
+    }
+
+    #[test]
+    fn fixed_array_matches_element_wise_marshaling() {
+        let a = [1i32, -2, 3];
+        let mut writer = DbusWriter::new(Vec::new());
+        for x in &a {
+            x.write::<_, BigEndian>(&mut writer).unwrap();
+        }
+        let expected = writer.into_inner();
+        assert_eq!(expected[..], write_array::<BigEndian, i32>(0, &a)[4..]);
+    }
+
+    #[test]
+    fn fixed_array_invalid_length() {
+        let buf = [3, 0, 0, 0, 1, 2, 3, 4];
+        let mut reader = DbusReader::new(&buf[..]);
+        assert!(reader.read_fixed_array::<LittleEndian, u32>().is_err());
+    }
+
+    quickcheck! {
+        fn fixed_array_round_trip_u8(xs: Vec<u8>, position: u8) -> bool {
+            let position = position as usize;
+            xs == read_fixed_array::<LittleEndian, u8>(position, &write_array::<LittleEndian, u8>(position, &xs))
+        }
+
+        fn fixed_array_round_trip_i16(xs: Vec<i16>, position: u8) -> bool {
+            let position = position as usize;
+            xs == read_fixed_array::<BigEndian, i16>(position, &write_array::<BigEndian, i16>(position, &xs))
+        }
+
+        fn fixed_array_round_trip_u32(xs: Vec<u32>, position: u8) -> bool {
+            let position = position as usize;
+            xs == read_fixed_array::<LittleEndian, u32>(position, &write_array::<LittleEndian, u32>(position, &xs))
+        }
+
+        fn fixed_array_round_trip_i64(xs: Vec<i64>, position: u8) -> bool {
+            let position = position as usize;
+            xs == read_fixed_array::<BigEndian, i64>(position, &write_array::<BigEndian, i64>(position, &xs))
+        }
+
+        fn fixed_array_round_trip_f64(xs: Vec<f64>, position: u8) -> bool {
+            let position = position as usize;
+            let ys = read_fixed_array::<LittleEndian, f64>(position, &write_array::<LittleEndian, f64>(position, &xs));
+            xs.iter().map(|x| x.to_bits()).eq(ys.iter().map(|y| y.to_bits()))
+        }
+    }
+}